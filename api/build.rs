@@ -0,0 +1,48 @@
+//! Generates what the client needs from syntect without linking it: the CSS of the
+//! light and dark code themes, and the names and file extensions of the languages
+//! the server highlights.
+use std::fmt::Write;
+use std::path::Path;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle};
+use syntect::parsing::SyntaxSet;
+
+/// Kept in step with `CLASS_STYLE` in `src/highlight.rs`.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set for build scripts");
+    let out_dir = Path::new(&out_dir);
+
+    let themes = ThemeSet::load_defaults();
+    let css = |name: &str| {
+        css_for_theme_with_class_style(&themes.themes[name], CLASS_STYLE)
+            .expect("theme CSS is generated")
+    };
+    std::fs::write(out_dir.join("light.css"), css("InspiredGitHub")).expect("CSS is written");
+    // Nested in `.dark`, the class the app's dark mode puts on the page.
+    let dark = format!(".dark {{\n{}}}\n", css("base16-ocean.dark"));
+    std::fs::write(out_dir.join("dark.css"), dark).expect("CSS is written");
+
+    // Lowercase fence tokens in the order `SyntaxSet::find_syntax_by_token` tries
+    // them: every extension and then every name, the last syntax first.
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let mut languages = String::from("&[\n");
+    let by_extension = syntaxes.syntaxes().iter().rev().flat_map(|syntax| {
+        syntax.file_extensions.iter().map(move |ext| (ext, syntax))
+    });
+    let by_name = syntaxes.syntaxes().iter().rev().map(|syntax| (&syntax.name, syntax));
+    for (token, syntax) in by_extension.chain(by_name) {
+        let extension = syntax.file_extensions.first().map_or("txt", String::as_str);
+        writeln!(
+            languages,
+            "    ({:?}, {:?}, {extension:?}),",
+            token.to_ascii_lowercase(),
+            syntax.name
+        )
+        .expect("writing to a String succeeds");
+    }
+    languages.push_str("]\n");
+    std::fs::write(out_dir.join("languages.rs"), languages).expect("languages are written");
+}
@@ -0,0 +1,69 @@
+//! A feed of changes to each user's conversation list, so every tab and device
+//! can keep its sidebar current without reloading.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::{StreamingText, TextStream};
+
+use crate::{ConversationId, ConversationSummary};
+
+#[cfg(feature = "server")]
+use crate::session;
+#[cfg(feature = "server")]
+use futures::StreamExt;
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::{broadcast, RwLock};
+#[cfg(feature = "server")]
+use tokio_stream::wrappers::BroadcastStream;
+
+/// Something that happened to one of the user's conversations.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum UserEvent {
+    ConversationCreated(ConversationSummary),
+    /// Renamed, pinned, archived, moved to a folder or restored.
+    ConversationUpdated(ConversationSummary),
+    ConversationDeleted(ConversationId),
+    /// A message was added, moving the conversation to the top of its group.
+    MessageAdded(ConversationId),
+    /// A message was edited or deleted, which also moves the conversation up.
+    MessagesChanged(ConversationId),
+}
+
+#[cfg(feature = "server")]
+type Channels = HashMap<String, broadcast::Sender<UserEvent>>;
+
+/// Event channels keyed by [`session::Caller::key`], created on first subscription.
+#[cfg(feature = "server")]
+static USER_EVENTS: Lazy<Arc<RwLock<Channels>>> =
+    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+/// Tell every subscribed tab and device of `owner` about `event`.
+#[cfg(feature = "server")]
+pub(crate) async fn publish(owner: &str, event: UserEvent) {
+    let mut channels = USER_EVENTS.write().await;
+    if let Some(tx) = channels.get(owner) {
+        if tx.send(event).is_err() {
+            // Nobody is listening any more.
+            channels.remove(owner);
+        }
+    }
+}
+
+/// Stream the caller's [`UserEvent`]s as they happen, one JSON object per line.
+#[server(StreamEvents, output = StreamingText)]
+pub async fn stream_events(session: String) -> Result<TextStream, ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let rx = USER_EVENTS
+        .write()
+        .await
+        .entry(owner)
+        .or_insert_with(|| broadcast::channel(32).0)
+        .subscribe();
+    let events = BroadcastStream::new(rx).filter_map(|event| async move { event.ok() });
+    Ok(crate::json_lines(events))
+}
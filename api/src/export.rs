@@ -0,0 +1,217 @@
+//! Conversation exports for archiving and pasting into documents and tickets.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use crate::{ChatMessage, ConversationId, UsageTotals};
+
+#[cfg(feature = "server")]
+use crate::highlight;
+#[cfg(feature = "server")]
+use crate::render::{escape_html, message_to_html};
+#[cfg(feature = "server")]
+use crate::{session, MessageSender, Role};
+
+/// Version written to [`ConversationExport::format_version`].
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// File formats a conversation can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [
+        ExportFormat::Markdown,
+        ExportFormat::Json,
+        ExportFormat::Html,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Html => "text/html",
+        }
+    }
+}
+
+/// An exported conversation ready to be saved as a file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExportedFile {
+    pub filename: String,
+    pub content_type: String,
+    pub content: String,
+}
+
+/// Lossless JSON form of a conversation, including its metadata and attachments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationExport {
+    pub format_version: u32,
+    pub id: ConversationId,
+    pub title: Option<String>,
+    pub folder: Option<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    /// RFC 3339 time of the last message.
+    pub updated_at: String,
+    /// RFC 3339 time the export was made.
+    pub exported_at: String,
+    pub usage: UsageTotals,
+    pub messages: Vec<ChatMessage>,
+}
+
+#[cfg(feature = "server")]
+impl ConversationExport {
+    fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or("Conversation")
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n\n", self.title());
+        for msg in &self.messages {
+            out.push_str(&format!("## {}\n\n", sender_label(&msg.sender)));
+            if let Some(reasoning) = &msg.reasoning {
+                for line in reasoning.lines() {
+                    out.push_str(&format!("> {line}\n"));
+                }
+                out.push('\n');
+            }
+            if let Some(text) = &msg.text {
+                out.push_str(text.trim_end());
+                out.push_str("\n\n");
+            }
+            if let Some(att) = &msg.attachment {
+                out.push_str(&format!(
+                    "Attachment: `{}` ({})\n\n",
+                    att.filename, att.content_type
+                ));
+            }
+        }
+        out
+    }
+
+    fn to_html(&self) -> String {
+        let title = escape_html(self.title());
+        let mut body = String::new();
+        for msg in &self.messages {
+            body.push_str(&format!(
+                "<section class=\"message\"><h2>{}</h2>",
+                sender_label(&msg.sender)
+            ));
+            if let Some(reasoning) = &msg.reasoning {
+                body.push_str(&format!(
+                    "<details><summary>Thinking</summary><pre class=\"reasoning\">{}</pre></details>",
+                    escape_html(reasoning)
+                ));
+            }
+            if let Some(text) = &msg.text {
+                body.push_str(&message_to_html(text));
+            }
+            if let Some(att) = &msg.attachment {
+                let name = escape_html(&att.filename);
+                if att.content_type.starts_with("image/") && att.data.starts_with("data:image/") {
+                    body.push_str(&format!(
+                        "<img src=\"{}\" alt=\"{name}\">",
+                        escape_html(&att.data)
+                    ));
+                } else {
+                    body.push_str(&format!("<p class=\"attachment\">Attachment: {name}</p>"));
+                }
+            }
+            body.push_str("</section>\n");
+        }
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+<style>{HTML_STYLE}{}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p class=\"meta\">Exported {}</p>\n{body}</body>\n</html>\n",
+            highlight::light_css(),
+            escape_html(&self.exported_at)
+        )
+    }
+}
+
+#[cfg(feature = "server")]
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:48rem;margin:2rem auto;padding:0 1rem;line-height:1.5}\
+.message{border-top:1px solid #ddd;padding:.5rem 0}.message h2{font-size:.9rem;color:#666;margin:.5rem 0}\
+pre{padding:.75rem;overflow-x:auto;border-radius:4px}.reasoning{white-space:pre-wrap;color:#666;background:#f6f6f6}\
+.meta,.attachment{color:#666;font-size:.9rem}img,figure.diagram svg{max-width:100%;height:auto}table{border-collapse:collapse}td,th{border:1px solid #ddd;padding:.25rem .5rem}";
+
+#[cfg(feature = "server")]
+fn sender_label(sender: &MessageSender) -> &'static str {
+    match sender {
+        MessageSender::User => "You",
+        MessageSender::AI => "Assistant",
+    }
+}
+
+/// Lowercase ASCII words of `title` joined by dashes, for use in a filename.
+#[cfg(feature = "server")]
+fn slug(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "conversation".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Export one of the caller's conversations.
+#[server(ExportConversation)]
+pub async fn export_conversation(
+    session: String,
+    conv_id: ConversationId,
+    format: ExportFormat,
+) -> Result<ExportedFile, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let export = {
+        let history = crate::CHAT_HISTORY.read().await;
+        let conv = crate::accessible_conversation(&history, &key, conv_id, Role::Viewer)
+            .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+        ConversationExport {
+            format_version: EXPORT_FORMAT_VERSION,
+            id: conv_id,
+            title: conv.title.clone(),
+            folder: conv.folder.clone(),
+            pinned: conv.pinned,
+            archived: conv.archived,
+            updated_at: conv.updated_at.to_rfc3339(),
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            usage: conv.usage,
+            messages: conv.messages.clone(),
+        }
+    };
+    let filename = format!("{}.{}", slug(export.title()), format.extension());
+    let content = match format {
+        ExportFormat::Markdown => export.to_markdown(),
+        ExportFormat::Json => serde_json::to_string_pretty(&export)?,
+        ExportFormat::Html => {
+            // KaTeX runs in an embedded JS engine, so keep it off the async workers.
+            tokio::task::spawn_blocking(move || export.to_html())
+                .await
+                .map_err(|e| ServerFnError::new(e.to_string()))?
+        }
+    };
+    Ok(ExportedFile {
+        filename,
+        content_type: format.content_type().to_string(),
+        content,
+    })
+}
@@ -0,0 +1,450 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use crate::model_config::{ModelConfig, Provider};
+use crate::usage::Usage;
+
+/// Max tokens sent to Anthropic when neither the request nor the model sets one.
+/// The Messages API rejects requests without it.
+pub const ANTHROPIC_FALLBACK_MAX_TOKENS: u32 = 1024;
+
+/// Smallest extended-thinking budget Anthropic accepts.
+pub const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// How much effort an OpenAI-style reasoning model spends before answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+/// Sampling parameters for a single completion request.
+///
+/// Unset fields fall back to the model's registry defaults and then to the
+/// provider's own defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f32>,
+    pub stop: Vec<String>,
+    /// Reasoning effort for OpenAI-style reasoning models.
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Extended-thinking token budget for Anthropic models.
+    pub thinking_budget: Option<u32>,
+    /// Instructions sent ahead of the prompt as the system message.
+    pub system: Option<String>,
+}
+
+impl GenerationParams {
+    /// Fill every unset field from `defaults`.
+    pub fn or(&self, defaults: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature.or(defaults.temperature),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            top_p: self.top_p.or(defaults.top_p),
+            stop: if self.stop.is_empty() {
+                defaults.stop.clone()
+            } else {
+                self.stop.clone()
+            },
+            reasoning_effort: self.reasoning_effort.or(defaults.reasoning_effort),
+            thinking_budget: self.thinking_budget.or(defaults.thinking_budget),
+            system: self.system.clone().or_else(|| defaults.system.clone()),
+        }
+    }
+
+    /// Check the parameters against the provider's accepted ranges and the model's token limit.
+    pub fn validate(&self, model: &ModelConfig) -> Result<(), ParamError> {
+        let limits = ParamLimits::for_provider(model.provider);
+        if let Some(t) = self.temperature {
+            if !(0.0..=limits.max_temperature).contains(&t) {
+                return Err(ParamError::Temperature {
+                    value: t,
+                    max: limits.max_temperature,
+                });
+            }
+        }
+        if let Some(p) = self.top_p {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(ParamError::TopP(p));
+            }
+        }
+        if let Some(n) = self.max_tokens {
+            if n == 0 || n as usize > model.max_tokens {
+                return Err(ParamError::MaxTokens {
+                    value: n,
+                    max: model.max_tokens,
+                });
+            }
+        }
+        if let Some(max) = limits.max_stop_sequences {
+            if self.stop.len() > max {
+                return Err(ParamError::TooManyStops {
+                    count: self.stop.len(),
+                    max,
+                });
+            }
+        }
+        if self.stop.iter().any(|s| s.is_empty()) {
+            return Err(ParamError::EmptyStop);
+        }
+        if self.reasoning_effort.is_some()
+            && (!model.capabilities.reasoning || model.provider == Provider::Anthropic)
+        {
+            return Err(ParamError::ReasoningUnsupported("reasoning effort"));
+        }
+        if let Some(budget) = self.thinking_budget {
+            if !model.capabilities.reasoning || model.provider != Provider::Anthropic {
+                return Err(ParamError::ReasoningUnsupported("thinking budget"));
+            }
+            let max_tokens = self.max_tokens.unwrap_or(ANTHROPIC_FALLBACK_MAX_TOKENS);
+            if budget < MIN_THINKING_BUDGET || budget >= max_tokens {
+                return Err(ParamError::ThinkingBudget {
+                    value: budget,
+                    max_tokens,
+                });
+            }
+            if self.temperature.is_some_and(|t| t != 1.0) || self.top_p.is_some() {
+                return Err(ParamError::SamplingWithThinking);
+            }
+        }
+        // OpenAI reasoning models only accept the default sampling settings.
+        if model.capabilities.reasoning
+            && model.provider == Provider::OpenAI
+            && (self.temperature.is_some_and(|t| t != 1.0) || self.top_p.is_some_and(|p| p != 1.0))
+        {
+            return Err(ParamError::SamplingWithThinking);
+        }
+        Ok(())
+    }
+}
+
+/// Ranges a provider accepts for sampling parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamLimits {
+    pub max_temperature: f32,
+    /// `None` when the provider does not document a limit.
+    pub max_stop_sequences: Option<usize>,
+}
+
+impl ParamLimits {
+    pub fn for_provider(provider: Provider) -> Self {
+        match provider {
+            Provider::Anthropic => Self {
+                max_temperature: 1.0,
+                max_stop_sequences: None,
+            },
+            Provider::Google => Self {
+                max_temperature: 2.0,
+                max_stop_sequences: Some(5),
+            },
+            Provider::DeepSeek => Self {
+                max_temperature: 2.0,
+                max_stop_sequences: Some(16),
+            },
+            Provider::OpenRouter => Self {
+                max_temperature: 2.0,
+                max_stop_sequences: None,
+            },
+            Provider::OpenAI | Provider::XAI | Provider::Groq => Self {
+                max_temperature: 2.0,
+                max_stop_sequences: Some(4),
+            },
+        }
+    }
+}
+
+/// Why a set of generation parameters was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamError {
+    Temperature { value: f32, max: f32 },
+    TopP(f32),
+    MaxTokens { value: u32, max: usize },
+    TooManyStops { count: usize, max: usize },
+    EmptyStop,
+    ReasoningUnsupported(&'static str),
+    ThinkingBudget { value: u32, max_tokens: u32 },
+    SamplingWithThinking,
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Temperature { value, max } => {
+                write!(f, "temperature {value} must be between 0 and {max}")
+            }
+            ParamError::TopP(value) => write!(f, "top_p {value} must be between 0 and 1"),
+            ParamError::MaxTokens { value, max } => {
+                write!(f, "max_tokens {value} must be between 1 and {max}")
+            }
+            ParamError::TooManyStops { count, max } => {
+                write!(f, "{count} stop sequences given, at most {max} allowed")
+            }
+            ParamError::EmptyStop => write!(f, "stop sequences cannot be empty"),
+            ParamError::ReasoningUnsupported(param) => {
+                write!(f, "{param} is not supported by this model")
+            }
+            ParamError::ThinkingBudget { value, max_tokens } => write!(
+                f,
+                "thinking budget {value} must be at least {MIN_THINKING_BUDGET} and below max_tokens ({max_tokens})"
+            ),
+            ParamError::SamplingWithThinking => {
+                write!(f, "temperature and top_p cannot be changed while the model is reasoning")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// The result of a completion request.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Completion {
+    /// The final answer shown to the user.
+    pub text: String,
+    /// The model's thinking trace, when the provider exposes one.
+    pub reasoning: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+/// Errors from the generation path that the UI explains to the user.
+///
+/// `Display` and `FromStr` use JSON so the error survives the server function boundary intact;
+/// use [`ChatError::message`] for text meant for people.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ChatError {
+    InvalidParams(String),
+    /// The registry has no model by this name.
+    UnknownModel(String),
+    /// Neither the user nor the operator has a key for this provider.
+    NoApiKey(Provider),
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    /// The daily allowance on the operator's keys is used up.
+    QuotaExceeded {
+        used: u64,
+        limit: u64,
+    },
+    /// The provider rejected the request or returned something unreadable.
+    Provider(String),
+}
+
+impl ChatError {
+    pub fn message(&self) -> String {
+        match self {
+            ChatError::InvalidParams(e) => format!("Invalid generation parameters: {e}"),
+            ChatError::UnknownModel(name) => format!("The model {name} is not available."),
+            ChatError::NoApiKey(provider) => {
+                format!("No API key is available for {provider}. Add your own under Settings.")
+            }
+            ChatError::RateLimited { retry_after_secs } => {
+                format!("Too many requests. Try again in {retry_after_secs}s.")
+            }
+            ChatError::QuotaExceeded { used, limit } => format!(
+                "You've used {used} of today's {limit} free tokens. Add your own API key under Settings to keep chatting."
+            ),
+            ChatError::Provider(e) => format!("The model provider returned an error: {e}"),
+        }
+    }
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+impl FromStr for ChatError {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl From<ParamError> for ChatError {
+    fn from(e: ParamError) -> Self {
+        ChatError::InvalidParams(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_config::Capabilities;
+
+    fn model(provider: Provider, reasoning: bool) -> ModelConfig {
+        ModelConfig {
+            provider,
+            max_tokens: 8192,
+            capabilities: Capabilities {
+                reasoning,
+                ..Capabilities::default()
+            },
+            ..ModelConfig::default()
+        }
+    }
+
+    #[test]
+    fn accepts_unset_and_in_range_params() {
+        let gpt = model(Provider::OpenAI, false);
+        assert_eq!(GenerationParams::default().validate(&gpt), Ok(()));
+        let params = GenerationParams {
+            temperature: Some(2.0),
+            top_p: Some(0.0),
+            max_tokens: Some(8192),
+            stop: vec!["END".into()],
+            ..Default::default()
+        };
+        assert_eq!(params.validate(&gpt), Ok(()));
+    }
+
+    #[test]
+    fn rejects_temperature_above_the_provider_limit() {
+        let params = GenerationParams {
+            temperature: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(params.validate(&model(Provider::OpenAI, false)), Ok(()));
+        assert_eq!(
+            params.validate(&model(Provider::Anthropic, false)),
+            Err(ParamError::Temperature { value: 1.5, max: 1.0 })
+        );
+        let negative = GenerationParams {
+            temperature: Some(-0.1),
+            ..Default::default()
+        };
+        assert!(negative.validate(&model(Provider::OpenAI, false)).is_err());
+    }
+
+    #[test]
+    fn rejects_top_p_outside_zero_to_one() {
+        for p in [-0.5, 1.01] {
+            let params = GenerationParams {
+                top_p: Some(p),
+                ..Default::default()
+            };
+            assert_eq!(
+                params.validate(&model(Provider::OpenAI, false)),
+                Err(ParamError::TopP(p))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_max_tokens_of_zero_or_above_the_model_limit() {
+        for n in [0, 8193] {
+            let params = GenerationParams {
+                max_tokens: Some(n),
+                ..Default::default()
+            };
+            assert_eq!(
+                params.validate(&model(Provider::OpenAI, false)),
+                Err(ParamError::MaxTokens { value: n, max: 8192 })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_too_many_or_empty_stop_sequences() {
+        let params = GenerationParams {
+            stop: vec!["a".into(); 5],
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(&model(Provider::OpenAI, false)),
+            Err(ParamError::TooManyStops { count: 5, max: 4 })
+        );
+        let params = GenerationParams {
+            stop: vec![String::new()],
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(&model(Provider::OpenAI, false)),
+            Err(ParamError::EmptyStop)
+        );
+    }
+
+    #[test]
+    fn rejects_reasoning_params_on_non_reasoning_models() {
+        let effort = GenerationParams {
+            reasoning_effort: Some(ReasoningEffort::High),
+            ..Default::default()
+        };
+        assert_eq!(
+            effort.validate(&model(Provider::OpenAI, false)),
+            Err(ParamError::ReasoningUnsupported("reasoning effort"))
+        );
+        assert_eq!(effort.validate(&model(Provider::OpenAI, true)), Ok(()));
+        assert_eq!(
+            effort.validate(&model(Provider::Anthropic, true)),
+            Err(ParamError::ReasoningUnsupported("reasoning effort"))
+        );
+
+        let budget = GenerationParams {
+            thinking_budget: Some(2048),
+            max_tokens: Some(4096),
+            ..Default::default()
+        };
+        assert_eq!(
+            budget.validate(&model(Provider::Anthropic, false)),
+            Err(ParamError::ReasoningUnsupported("thinking budget"))
+        );
+        assert_eq!(budget.validate(&model(Provider::Anthropic, true)), Ok(()));
+        assert_eq!(
+            budget.validate(&model(Provider::OpenAI, true)),
+            Err(ParamError::ReasoningUnsupported("thinking budget"))
+        );
+    }
+
+    #[test]
+    fn rejects_thinking_budget_outside_its_range() {
+        let claude = model(Provider::Anthropic, true);
+        for (budget, max_tokens) in [(512, 4096), (4096, 4096)] {
+            let params = GenerationParams {
+                thinking_budget: Some(budget),
+                max_tokens: Some(max_tokens),
+                ..Default::default()
+            };
+            assert_eq!(
+                params.validate(&claude),
+                Err(ParamError::ThinkingBudget {
+                    value: budget,
+                    max_tokens
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_sampling_changes_while_reasoning() {
+        let params = GenerationParams {
+            temperature: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(&model(Provider::OpenAI, true)),
+            Err(ParamError::SamplingWithThinking)
+        );
+        let params = GenerationParams {
+            thinking_budget: Some(2048),
+            max_tokens: Some(4096),
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(&model(Provider::Anthropic, true)),
+            Err(ParamError::SamplingWithThinking)
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Graphviz diagrams, drawn on the server so that neither the chat nor exported
+//! pages load a third-party script to show them.
+//!
+//! Labels are escaped by the renderer and colours parsed, so the SVG carries no
+//! text from the source other than as escaped character data.
+use dioxus::prelude::*;
+
+#[cfg(feature = "server")]
+use crate::render::escape_html;
+#[cfg(feature = "server")]
+use crate::{quota, session};
+
+/// Longest Graphviz source, in bytes, that is drawn.
+pub const MAX_GRAPHVIZ_LEN: usize = 20_000;
+
+/// Draw Graphviz `source` as inline SVG, or `None` if it does not parse, uses
+/// something the renderer does not support, or is too long.
+#[cfg(feature = "server")]
+pub(crate) fn graphviz_svg(source: &str) -> Option<String> {
+    use layout::backends::svg::SVGWriter;
+    use layout::gv::{DotParser, GraphBuilder};
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    if source.len() > MAX_GRAPHVIZ_LEN {
+        return None;
+    }
+    // The parser and layout panic on some graphs rather than returning an error.
+    let svg = catch_unwind(AssertUnwindSafe(|| {
+        let graph = DotParser::new(source).process().ok()?;
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&graph);
+        let mut writer = SVGWriter::new();
+        builder.get().do_it(false, false, false, &mut writer);
+        Some(writer.finalize())
+    }))
+    .ok()??;
+    let svg = &svg[svg.find("<svg")?..];
+
+    // Every drawing names its markers and paths the same, so give each source its
+    // own ids for several diagrams to share a page.
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let prefix = format!("gv{:x}-", hasher.finish());
+    Some(
+        svg.replace("id=\"", &format!("id=\"{prefix}"))
+            .replace("href=\"#", &format!("href=\"#{prefix}"))
+            .replace("url(#", &format!("url(#{prefix}")),
+    )
+}
+
+/// A drawn diagram with its source under a disclosure, for exported pages.
+#[cfg(feature = "server")]
+pub(crate) fn graphviz_figure(source: &str) -> Option<String> {
+    Some(format!(
+        "<figure class=\"diagram\">{}<details><summary>Source</summary><pre><code>{}</code></pre></details></figure>",
+        graphviz_svg(source)?,
+        escape_html(source)
+    ))
+}
+
+/// Draw Graphviz `source` for the web client, as SVG markup. Counts against the
+/// caller's rendering rate limit.
+#[server(RenderGraphviz)]
+pub async fn render_graphviz(session: String, source: String) -> Result<String, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    if source.len() > MAX_GRAPHVIZ_LEN {
+        return Err(ServerFnError::new("The diagram is too long to draw"));
+    }
+    quota::check_rendering(&caller)
+        .await
+        .map_err(|e| ServerFnError::new(e.message()))?;
+    // Layout is CPU-bound, so keep it off the async workers.
+    tokio::task::spawn_blocking(move || graphviz_svg(&source))
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?
+        .ok_or_else(|| ServerFnError::new("The diagram could not be drawn"))
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_graphs_with_escaped_labels() {
+        let svg = graphviz_svg(r#"digraph { a -> b [label="<script>x</script>"]; }"#).unwrap();
+        assert!(svg.starts_with("<svg"), "{svg}");
+        assert!(!svg.contains("<script"), "{svg}");
+        assert!(svg.contains("&lt;script&gt;"), "{svg}");
+    }
+
+    #[test]
+    fn gives_each_source_its_own_ids() {
+        let first = graphviz_svg("digraph { a -> b; }").unwrap();
+        let second = graphviz_svg("digraph { a -> c; }").unwrap();
+        let ids = |svg: &str| {
+            svg.split("id=\"")
+                .skip(1)
+                .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+                .collect::<Vec<_>>()
+        };
+        let first_ids = ids(&first);
+        assert!(!first_ids.is_empty());
+        assert!(ids(&second).iter().all(|id| !first_ids.contains(id)));
+        assert!(!first.contains("url(#endarrow)"), "{first}");
+    }
+
+    #[test]
+    fn rejects_invalid_and_long_sources() {
+        assert_eq!(graphviz_svg("digraph { a -> "), None);
+        let long = format!("digraph {{ {} }}", "a -> b; ".repeat(MAX_GRAPHVIZ_LEN));
+        assert_eq!(graphviz_svg(&long), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Syntax highlighting for code blocks, shared by the chat and exports.
+//!
+//! Code is split into spans with `hl-` classes rather than inline colours, so the
+//! same HTML follows the app's light and dark themes through [`light_css`] and
+//! [`dark_css`]. Only the server links syntect: the web client gets its spans from
+//! [`highlight_code_blocks`], and the stylesheets and language names are generated
+//! by `build.rs`.
+use dioxus::prelude::*;
+
+#[cfg(feature = "server")]
+use crate::{quota, session};
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+#[cfg(feature = "server")]
+use syntect::parsing::SyntaxSet;
+#[cfg(feature = "server")]
+use syntect::util::LinesWithEndings;
+
+#[cfg(feature = "server")]
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+#[cfg(feature = "server")]
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+/// Fence tokens with the name and usual extension of the language they stand for.
+static LANGUAGES: &[(&str, &str, &str)] = include!(concat!(env!("OUT_DIR"), "/languages.rs"));
+
+/// Most code, in bytes, [`highlight_code_blocks`] highlights in one call.
+pub const MAX_HIGHLIGHT_LEN: usize = 200_000;
+
+/// Styles for highlighted code in the light theme.
+pub fn light_css() -> &'static str {
+    include_str!(concat!(env!("OUT_DIR"), "/light.css"))
+}
+
+/// Styles for highlighted code in the dark theme, nested in `.dark`.
+pub fn dark_css() -> &'static str {
+    include_str!(concat!(env!("OUT_DIR"), "/dark.css"))
+}
+
+#[cfg(feature = "server")]
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A language code blocks can be highlighted as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Language {
+    pub name: &'static str,
+    /// Usual file extension, without the dot.
+    pub extension: &'static str,
+}
+
+/// The language a fence info string token such as `rust` or `py` stands for.
+pub fn language(lang: &str) -> Option<Language> {
+    if lang.is_empty() {
+        return None;
+    }
+    let token = lang.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(t, _, _)| *t == token)
+        .map(|&(_, name, extension)| Language { name, extension })
+}
+
+/// Diagram languages, labelled as such on their code blocks. Graphviz is drawn by
+/// [`crate::graphviz`]. Mermaid is only shown as its source, since drawing it needs
+/// the Mermaid JavaScript library, which the app does not bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagram {
+    Mermaid,
+    Graphviz,
+}
+
+impl Diagram {
+    /// The diagram language a fence info string token names, if any.
+    pub fn from_fence(lang: &str) -> Option<Diagram> {
+        match lang {
+            "mermaid" => Some(Diagram::Mermaid),
+            "dot" | "graphviz" | "gv" => Some(Diagram::Graphviz),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Diagram::Mermaid => "Mermaid",
+            Diagram::Graphviz => "Graphviz",
+        }
+    }
+
+    /// Usual file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Diagram::Mermaid => "mmd",
+            Diagram::Graphviz => "dot",
+        }
+    }
+}
+
+/// Highlight `code` as `lang` into spans, without the surrounding `<pre>`.
+/// Unknown languages are shown as plain text.
+#[cfg(feature = "server")]
+pub fn highlight_spans(code: &str, lang: &str) -> String {
+    SYNTAXES
+        .find_syntax_by_token(lang)
+        .filter(|_| !lang.is_empty())
+        .and_then(|syntax| {
+            let mut html =
+                ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+            for line in LinesWithEndings::from(code) {
+                html.parse_html_for_line_which_includes_newline(line).ok()?;
+            }
+            Some(html.finalize())
+        })
+        .unwrap_or_else(|| escape(code))
+}
+
+/// Highlight `code` as `lang`, a fence info string token such as `rust` or `py`.
+#[cfg(feature = "server")]
+pub fn highlight_code(code: &str, lang: &str) -> String {
+    let lang_class = match lang {
+        "" => String::new(),
+        lang => format!(" class=\"language-{}\"", escape(lang)),
+    };
+    format!(
+        "<pre class=\"hl-code\"><code{lang_class}>{}</code></pre>",
+        highlight_spans(code, lang)
+    )
+}
+
+/// Highlight code blocks, each given as its code and fence token, into spans as
+/// [`highlight_spans`] does, in the same order. Counts against the caller's
+/// rendering rate limit.
+#[server(HighlightCodeBlocks)]
+pub async fn highlight_code_blocks(
+    session: String,
+    blocks: Vec<(String, String)>,
+) -> Result<Vec<String>, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    if blocks.iter().map(|(code, _)| code.len()).sum::<usize>() > MAX_HIGHLIGHT_LEN {
+        return Err(ServerFnError::new("Too much code to highlight at once"));
+    }
+    quota::check_rendering(&caller)
+        .await
+        .map_err(|e| ServerFnError::new(e.message()))?;
+    // Highlighting is CPU-bound, so keep it off the async workers.
+    tokio::task::spawn_blocking(move || {
+        blocks
+            .iter()
+            .map(|(code, lang)| highlight_spans(code, lang))
+            .collect()
+    })
+    .await
+    .map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_languages_by_extension_and_name() {
+        let rust = language("rs").unwrap();
+        assert_eq!((rust.name, rust.extension), ("Rust", "rs"));
+        assert_eq!(language("Python").map(|l| l.extension), Some("py"));
+        assert_eq!(language("brainfuck"), None);
+        assert_eq!(language(""), None);
+    }
+
+    #[test]
+    fn nests_dark_theme_in_dark_class() {
+        assert!(light_css().contains(".hl-code"));
+        assert!(dark_css().starts_with(".dark {\n"));
+        assert!(dark_css().trim_end().ends_with('}'));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn languages_match_syntect() {
+        for syntax in SYNTAXES.syntaxes() {
+            for token in syntax.file_extensions.iter().chain([&syntax.name]) {
+                let found = SYNTAXES.find_syntax_by_token(token).unwrap();
+                assert_eq!(language(token).map(|l| l.name), Some(found.name.as_str()));
+            }
+        }
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn highlights_known_languages_only() {
+        assert!(highlight_spans("let x = 1;\n", "rust").contains("hl-"));
+        assert_eq!(highlight_spans("<a>", "brainfuck"), "&lt;a&gt;");
+    }
+}
@@ -0,0 +1,574 @@
+//! Importers for chat history exported from ChatGPT and Claude.
+//!
+//! Both exports are a `conversations.json` array. ChatGPT stores each
+//! conversation as a tree of nodes and Claude may link messages to a parent when
+//! a reply was edited or regenerated; in both cases the branch the user last
+//! saw is imported and the other branches are counted in the summary.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+#[cfg(feature = "server")]
+use crate::{
+    events, session, Attachment, ChatMessage, Conversation, ConversationId, MessageSender,
+    UserEvent,
+};
+#[cfg(feature = "server")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "server")]
+use serde_json::Value;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+
+/// Most conversations one import may hold.
+pub const MAX_IMPORTED_CONVERSATIONS: usize = 1_000;
+/// Most messages one import may hold, across all of its conversations.
+pub const MAX_IMPORTED_MESSAGES: usize = 50_000;
+
+/// Where an export file came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum ImportSource {
+    #[strum(serialize = "ChatGPT")]
+    ChatGpt,
+    Claude,
+}
+
+/// What importing one conversation brings in.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportedConversationSummary {
+    pub title: String,
+    pub messages: usize,
+    pub attachments: usize,
+    /// Messages on edited or regenerated branches that are left out.
+    pub other_branch_messages: usize,
+}
+
+/// Result of an import or of a dry run.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportSummary {
+    pub source: ImportSource,
+    pub conversations: Vec<ImportedConversationSummary>,
+    /// System, tool and empty messages that have no equivalent here.
+    pub skipped_messages: usize,
+    /// `false` for a dry run.
+    pub imported: bool,
+}
+
+/// A conversation parsed from an export, before it is stored.
+#[cfg(feature = "server")]
+struct ParsedConversation {
+    title: String,
+    updated_at: Option<DateTime<Utc>>,
+    messages: Vec<ChatMessage>,
+    other_branch_messages: usize,
+    skipped_messages: usize,
+}
+
+#[cfg(feature = "server")]
+impl ParsedConversation {
+    fn summary(&self) -> ImportedConversationSummary {
+        ImportedConversationSummary {
+            title: self.title.clone(),
+            messages: self.messages.len(),
+            attachments: self
+                .messages
+                .iter()
+                .filter(|m| m.attachment.is_some())
+                .count(),
+            other_branch_messages: self.other_branch_messages,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+fn message(sender: MessageSender, text: String, reasoning: Option<String>) -> ChatMessage {
+    ChatMessage {
+        id: Default::default(),
+        text: Some(text),
+        attachment: None,
+        sender,
+        author: None,
+        reasoning,
+        usage: None,
+    }
+}
+
+/// Tell the two export formats apart by the fields their conversations carry.
+#[cfg(feature = "server")]
+fn detect(conversations: &[Value]) -> Option<ImportSource> {
+    let first = conversations.first()?;
+    if first.get("mapping").is_some() {
+        Some(ImportSource::ChatGpt)
+    } else if first.get("chat_messages").is_some() {
+        Some(ImportSource::Claude)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "server")]
+fn unix_time(value: &Value) -> Option<DateTime<Utc>> {
+    let secs = value.as_f64()?;
+    DateTime::from_timestamp(secs as i64, 0)
+}
+
+#[cfg(feature = "server")]
+fn rfc3339_time(value: &Value) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.as_str()?)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Parse one conversation from a ChatGPT export by walking up from `current_node`.
+#[cfg(feature = "server")]
+fn parse_chatgpt(conv: &Value) -> ParsedConversation {
+    let mapping = conv["mapping"].as_object();
+    let mut branch = Vec::new();
+    let mut node_id = conv["current_node"].as_str();
+    while let Some(id) = node_id {
+        let Some(node) = mapping.and_then(|m| m.get(id)) else {
+            break;
+        };
+        branch.push(node);
+        node_id = node["parent"].as_str();
+    }
+    branch.reverse();
+    let total_nodes = mapping.map_or(0, |m| {
+        m.values().filter(|n| n["message"].is_object()).count()
+    });
+    let branch_nodes = branch.iter().filter(|n| n["message"].is_object()).count();
+
+    let mut messages = Vec::new();
+    let mut skipped = 0;
+    // Thoughts arrive as their own node just before the reply they belong to.
+    let mut pending_reasoning: Option<String> = None;
+    for node in branch {
+        let msg = &node["message"];
+        if !msg.is_object() {
+            continue;
+        }
+        let role = msg["author"]["role"].as_str().unwrap_or_default();
+        let content = &msg["content"];
+        let hidden = msg["metadata"]["is_visually_hidden_from_conversation"]
+            .as_bool()
+            .unwrap_or(false);
+        let text = match content["content_type"].as_str() {
+            Some("text") | Some("multimodal_text") => content["parts"]
+                .as_array()
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join("\n\n")
+                })
+                .unwrap_or_default(),
+            Some("code") => format!("```\n{}\n```", content["text"].as_str().unwrap_or_default()),
+            Some("thoughts") => {
+                let thoughts: Vec<&str> = content["thoughts"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|t| t["content"].as_str())
+                    .collect();
+                if !thoughts.is_empty() {
+                    pending_reasoning = Some(thoughts.join("\n\n"));
+                }
+                continue;
+            }
+            _ => String::new(),
+        };
+        let sender = match role {
+            "user" => MessageSender::User,
+            "assistant" => MessageSender::AI,
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        };
+        if hidden || text.trim().is_empty() {
+            skipped += 1;
+            continue;
+        }
+        let reasoning = match sender {
+            MessageSender::AI => pending_reasoning.take(),
+            MessageSender::User => None,
+        };
+        messages.push(message(sender, text, reasoning));
+    }
+
+    ParsedConversation {
+        title: conv["title"].as_str().unwrap_or("Untitled").to_string(),
+        updated_at: unix_time(&conv["update_time"]),
+        messages,
+        other_branch_messages: total_nodes.saturating_sub(branch_nodes),
+        skipped_messages: skipped,
+    }
+}
+
+/// Parent id Claude uses for the first message of a conversation.
+#[cfg(feature = "server")]
+const CLAUDE_ROOT_PARENT: &str = "00000000-0000-4000-8000-000000000000";
+
+/// Parse one conversation from a Claude export. When messages carry parent ids the branch
+/// ending at the last message is kept, otherwise messages are taken in order.
+#[cfg(feature = "server")]
+fn parse_claude(conv: &Value) -> ParsedConversation {
+    let all: Vec<&Value> = conv["chat_messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .collect();
+    let by_id: HashMap<&str, &Value> = all
+        .iter()
+        .filter_map(|m| Some((m["uuid"].as_str()?, *m)))
+        .collect();
+    let branch: Vec<&Value> = if all.iter().any(|m| m["parent_message_uuid"].is_string()) {
+        let mut branch = Vec::new();
+        let mut next = all.last().copied();
+        while let Some(msg) = next {
+            branch.push(msg);
+            next = msg["parent_message_uuid"]
+                .as_str()
+                .filter(|p| *p != CLAUDE_ROOT_PARENT)
+                .and_then(|p| by_id.get(p).copied());
+        }
+        branch.reverse();
+        branch
+    } else {
+        all.clone()
+    };
+
+    let mut messages = Vec::new();
+    let mut skipped = 0;
+    for msg in &branch {
+        let sender = match msg["sender"].as_str() {
+            Some("human") => MessageSender::User,
+            Some("assistant") => MessageSender::AI,
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        };
+        let blocks = msg["content"].as_array();
+        let block_text = |kind: &str, field: &str| -> Option<String> {
+            let parts: Vec<&str> = blocks?
+                .iter()
+                .filter(|b| b["type"].as_str() == Some(kind))
+                .filter_map(|b| b[field].as_str())
+                .collect();
+            (!parts.is_empty()).then(|| parts.join("\n\n"))
+        };
+        let text = block_text("text", "text")
+            .or_else(|| msg["text"].as_str().map(str::to_string))
+            .unwrap_or_default();
+        let attachment = msg["attachments"]
+            .as_array()
+            .and_then(|a| a.first())
+            .map(|att| Attachment {
+                filename: att["file_name"]
+                    .as_str()
+                    .unwrap_or("attachment")
+                    .to_string(),
+                content_type: att["file_type"]
+                    .as_str()
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or("text/plain")
+                    .to_string(),
+                data: att["extracted_content"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+        if text.trim().is_empty() && attachment.is_none() {
+            skipped += 1;
+            continue;
+        }
+        let mut chat_message = message(sender, text, block_text("thinking", "thinking"));
+        chat_message.attachment = attachment;
+        messages.push(chat_message);
+    }
+
+    ParsedConversation {
+        title: conv["name"]
+            .as_str()
+            .filter(|n| !n.is_empty())
+            .unwrap_or("Untitled")
+            .to_string(),
+        updated_at: rfc3339_time(&conv["updated_at"]),
+        messages,
+        other_branch_messages: all.len() - branch.len(),
+        skipped_messages: skipped,
+    }
+}
+
+/// Import a ChatGPT or Claude `conversations.json` export into the caller's conversations.
+/// With `dry_run` nothing is stored and the summary shows what would be imported. Imported
+/// conversations are filed in a folder named after the source. Exports larger than
+/// [`MAX_IMPORTED_CONVERSATIONS`] or [`MAX_IMPORTED_MESSAGES`] are refused.
+#[server(ImportHistory)]
+pub async fn import_history(
+    session: String,
+    export: String,
+    dry_run: bool,
+) -> Result<ImportSummary, ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let json: Value = serde_json::from_str(&export)
+        .map_err(|e| ServerFnError::new(format!("Not a JSON export: {e}")))?;
+    let conversations = json
+        .as_array()
+        .ok_or_else(|| ServerFnError::new("Expected a list of conversations"))?;
+    if conversations.len() > MAX_IMPORTED_CONVERSATIONS {
+        return Err(ServerFnError::new(format!(
+            "The export has {} conversations; at most {MAX_IMPORTED_CONVERSATIONS} can be imported at once",
+            conversations.len()
+        )));
+    }
+    let source = detect(conversations).ok_or_else(|| {
+        ServerFnError::new("Unrecognized export; expected ChatGPT or Claude conversations.json")
+    })?;
+    let parsed: Vec<ParsedConversation> = conversations
+        .iter()
+        .map(|conv| match source {
+            ImportSource::ChatGpt => parse_chatgpt(conv),
+            ImportSource::Claude => parse_claude(conv),
+        })
+        .filter(|conv| !conv.messages.is_empty())
+        .collect();
+    let message_count: usize = parsed.iter().map(|c| c.messages.len()).sum();
+    if message_count > MAX_IMPORTED_MESSAGES {
+        return Err(ServerFnError::new(format!(
+            "The export has {message_count} messages; at most {MAX_IMPORTED_MESSAGES} can be imported at once"
+        )));
+    }
+
+    let summary = ImportSummary {
+        source,
+        conversations: parsed.iter().map(ParsedConversation::summary).collect(),
+        skipped_messages: parsed.iter().map(|c| c.skipped_messages).sum(),
+        imported: !dry_run,
+    };
+    if dry_run {
+        return Ok(summary);
+    }
+
+    // Build the conversations before taking the lock so a large import does not hold it.
+    let mut imported = Vec::with_capacity(parsed.len());
+    for parsed in parsed {
+        let id = ConversationId::new_v4();
+        let mut conv = Conversation::new(owner.clone());
+        conv.title = Some(crate::clip(&parsed.title, crate::MAX_TITLE_LEN));
+        conv.folder = Some(source.to_string());
+        if let Some(updated_at) = parsed.updated_at {
+            conv.updated_at = updated_at;
+        }
+        for msg in parsed.messages {
+            conv.add_message(id, msg).await;
+        }
+        imported.push((id, conv));
+    }
+    for (id, conv) in &imported {
+        events::publish(&owner, UserEvent::ConversationCreated(conv.summary(*id, &owner))).await;
+    }
+    crate::CHAT_HISTORY.write().await.extend(imported);
+    Ok(summary)
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn texts(conv: &ParsedConversation) -> Vec<(MessageSender, &str)> {
+        conv.messages
+            .iter()
+            .map(|m| (m.sender.clone(), m.text.as_deref().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn detects_the_export_format() {
+        assert_eq!(detect(&[json!({ "mapping": {} })]), Some(ImportSource::ChatGpt));
+        assert_eq!(detect(&[json!({ "chat_messages": [] })]), Some(ImportSource::Claude));
+        assert_eq!(detect(&[json!({ "messages": [] })]), None);
+        assert_eq!(detect(&[]), None);
+    }
+
+    #[test]
+    fn chatgpt_follows_the_current_branch() {
+        let conv = json!({
+            "title": "Trip",
+            "update_time": 1700000000.5,
+            "current_node": "a2",
+            "mapping": {
+                "root": { "message": null, "parent": null },
+                "sys": {
+                    "parent": "root",
+                    "message": {
+                        "author": { "role": "system" },
+                        "content": { "content_type": "text", "parts": [""] },
+                        "metadata": { "is_visually_hidden_from_conversation": true },
+                    },
+                },
+                "u1": {
+                    "parent": "sys",
+                    "message": {
+                        "author": { "role": "user" },
+                        "content": { "content_type": "text", "parts": ["Plan a trip"] },
+                    },
+                },
+                "a1": {
+                    "parent": "u1",
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": { "content_type": "text", "parts": ["Old answer"] },
+                    },
+                },
+                "t2": {
+                    "parent": "u1",
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": {
+                            "content_type": "thoughts",
+                            "thoughts": [{ "content": "Mind the budget" }, { "content": "Trains" }],
+                        },
+                    },
+                },
+                "a2": {
+                    "parent": "t2",
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": { "content_type": "text", "parts": ["New answer"] },
+                    },
+                },
+            },
+        });
+        let parsed = parse_chatgpt(&conv);
+        assert_eq!(parsed.title, "Trip");
+        assert_eq!(
+            texts(&parsed),
+            [(MessageSender::User, "Plan a trip"), (MessageSender::AI, "New answer")]
+        );
+        assert_eq!(parsed.messages[0].reasoning, None);
+        assert_eq!(
+            parsed.messages[1].reasoning.as_deref(),
+            Some("Mind the budget\n\nTrains")
+        );
+        assert_eq!(parsed.skipped_messages, 1);
+        assert_eq!(parsed.other_branch_messages, 1);
+        assert_eq!(parsed.updated_at, DateTime::from_timestamp(1700000000, 0));
+    }
+
+    #[test]
+    fn chatgpt_skips_tool_messages_and_wraps_code() {
+        let conv = json!({
+            "current_node": "c",
+            "mapping": {
+                "a": {
+                    "parent": null,
+                    "message": {
+                        "author": { "role": "user" },
+                        "content": { "content_type": "multimodal_text", "parts": ["One", { "asset": 1 }, "Two"] },
+                    },
+                },
+                "b": {
+                    "parent": "a",
+                    "message": {
+                        "author": { "role": "tool" },
+                        "content": { "content_type": "text", "parts": ["search results"] },
+                    },
+                },
+                "c": {
+                    "parent": "b",
+                    "message": {
+                        "author": { "role": "assistant" },
+                        "content": { "content_type": "code", "text": "print(1)" },
+                    },
+                },
+            },
+        });
+        let parsed = parse_chatgpt(&conv);
+        assert_eq!(parsed.title, "Untitled");
+        assert_eq!(
+            texts(&parsed),
+            [
+                (MessageSender::User, "One\n\nTwo"),
+                (MessageSender::AI, "```\nprint(1)\n```")
+            ]
+        );
+        assert_eq!(parsed.skipped_messages, 1);
+        assert_eq!(parsed.other_branch_messages, 0);
+    }
+
+    #[test]
+    fn claude_follows_the_branch_ending_at_the_last_message() {
+        let conv = json!({
+            "name": "Poem",
+            "updated_at": "2024-05-01T10:00:00Z",
+            "chat_messages": [
+                {
+                    "uuid": "m1",
+                    "parent_message_uuid": CLAUDE_ROOT_PARENT,
+                    "sender": "human",
+                    "text": "Read this",
+                    "content": [{ "type": "text", "text": "Read this" }],
+                    "attachments": [{ "file_name": "notes.txt", "file_type": "", "extracted_content": "hello" }],
+                },
+                {
+                    "uuid": "m2",
+                    "parent_message_uuid": "m1",
+                    "sender": "assistant",
+                    "content": [{ "type": "text", "text": "First try" }],
+                },
+                {
+                    "uuid": "m3",
+                    "parent_message_uuid": "m1",
+                    "sender": "assistant",
+                    "content": [
+                        { "type": "thinking", "thinking": "Short lines" },
+                        { "type": "text", "text": "Second try" },
+                    ],
+                },
+            ],
+        });
+        let parsed = parse_claude(&conv);
+        assert_eq!(parsed.title, "Poem");
+        assert_eq!(
+            texts(&parsed),
+            [(MessageSender::User, "Read this"), (MessageSender::AI, "Second try")]
+        );
+        assert_eq!(parsed.messages[1].reasoning.as_deref(), Some("Short lines"));
+        assert_eq!(
+            parsed.messages[0].attachment,
+            Some(Attachment {
+                filename: "notes.txt".into(),
+                content_type: "text/plain".into(),
+                data: "hello".into(),
+            })
+        );
+        assert_eq!(parsed.other_branch_messages, 1);
+        assert_eq!(parsed.skipped_messages, 0);
+        assert!(parsed.updated_at.is_some());
+        assert_eq!(parsed.summary().attachments, 1);
+    }
+
+    #[test]
+    fn claude_without_parents_keeps_every_message_in_order() {
+        let conv = json!({
+            "name": "",
+            "chat_messages": [
+                { "uuid": "m1", "sender": "human", "text": "Hi", "content": [] },
+                { "uuid": "m2", "sender": "assistant", "text": "", "content": [] },
+                { "uuid": "m3", "sender": "assistant", "text": "Hello" },
+            ],
+        });
+        let parsed = parse_claude(&conv);
+        assert_eq!(parsed.title, "Untitled");
+        assert_eq!(
+            texts(&parsed),
+            [(MessageSender::User, "Hi"), (MessageSender::AI, "Hello")]
+        );
+        assert_eq!(parsed.skipped_messages, 1);
+        assert_eq!(parsed.other_branch_messages, 0);
+    }
+}
@@ -0,0 +1,235 @@
+//! Per-user provider API keys, encrypted at rest on the server.
+//!
+//! Keys are encrypted with AES-256-GCM under a server secret read from
+//! `API_KEY_ENCRYPTION_SECRET` (32 bytes, base64). When the variable is unset a
+//! random secret is generated, so stored keys do not survive a restart. When it is set
+//! but invalid the server refuses to start rather than quietly doing the same.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::model_config::Provider;
+
+#[cfg(feature = "server")]
+use crate::session::{self, Caller};
+#[cfg(feature = "server")]
+use aes_gcm::aead::{Aead, KeyInit};
+#[cfg(feature = "server")]
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+#[cfg(feature = "server")]
+use base64::Engine;
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use rand::RngCore;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+/// A stored key as shown to its owner.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MaskedKey {
+    pub provider: Provider,
+    /// Only the last four characters of a key longer than [`MIN_REVEALED_LEN`] are revealed.
+    pub masked: String,
+}
+
+/// Shortest key, in characters, whose last four characters are shown when it is masked.
+pub const MIN_REVEALED_LEN: usize = 12;
+
+#[cfg(feature = "server")]
+struct EncryptedKey {
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+    masked: String,
+}
+
+#[cfg(feature = "server")]
+type KeyStore = HashMap<String, HashMap<Provider, EncryptedKey>>;
+
+/// In-memory encrypted keys by username and provider.
+#[cfg(feature = "server")]
+static KEY_STORE: Lazy<Arc<RwLock<KeyStore>>> = Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+#[cfg(feature = "server")]
+static CIPHER: Lazy<Aes256Gcm> = Lazy::new(|| {
+    let secret = encryption_secret(std::env::var("API_KEY_ENCRYPTION_SECRET").ok())
+        .unwrap_or_else(|e| panic!("{e}"));
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&secret))
+});
+
+/// The secret in `API_KEY_ENCRYPTION_SECRET`, or a random one if it is unset.
+#[cfg(feature = "server")]
+fn encryption_secret(value: Option<String>) -> Result<Vec<u8>, String> {
+    let Some(value) = value else {
+        let mut bytes = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        return Ok(bytes);
+    };
+    base64::engine::general_purpose::STANDARD
+        .decode(value.trim())
+        .ok()
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| "API_KEY_ENCRYPTION_SECRET must be 32 bytes encoded as base64".to_string())
+}
+
+/// Check `API_KEY_ENCRYPTION_SECRET` when the server starts. Panics if it is set but
+/// invalid, since every key stored under a random secret is lost on restart.
+#[cfg(feature = "server")]
+pub fn check_encryption_secret() {
+    Lazy::force(&CIPHER);
+}
+
+#[cfg(feature = "server")]
+fn mask(key: &str) -> String {
+    let count = key.chars().count();
+    if count < MIN_REVEALED_LEN {
+        return "••••".to_string();
+    }
+    let tail: String = key.chars().skip(count - 4).collect();
+    format!("••••{tail}")
+}
+
+/// Only logged-in users can store keys.
+#[cfg(feature = "server")]
+async fn require_user(session: &str) -> Result<String, ServerFnError> {
+    match session::caller(session).await? {
+        Caller::User(name) => Ok(name),
+        Caller::Anonymous(_) => Err(ServerFnError::new("Log in to store API keys")),
+    }
+}
+
+/// Decrypt the caller's key for `provider`, if they have stored one.
+#[cfg(feature = "server")]
+pub(crate) async fn provider_key(caller: &Caller, provider: Provider) -> Option<String> {
+    let Caller::User(name) = caller else {
+        return None;
+    };
+    let store = KEY_STORE.read().await;
+    let stored = store.get(name)?.get(&provider)?;
+    let plaintext = CIPHER
+        .decrypt(Nonce::from_slice(&stored.nonce), stored.ciphertext.as_ref())
+        .ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+/// Encrypt and store the caller's key for a provider, replacing any previous one.
+#[server(SetProviderKey)]
+pub async fn set_provider_key(
+    session: String,
+    provider: Provider,
+    key: String,
+) -> Result<MaskedKey, ServerFnError> {
+    let user = require_user(&session).await?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(ServerFnError::new("API key cannot be empty"));
+    }
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = CIPHER
+        .encrypt(Nonce::from_slice(&nonce), key.as_bytes())
+        .map_err(|_| ServerFnError::new("Failed to encrypt API key"))?;
+    let masked = mask(key);
+    KEY_STORE.write().await.entry(user).or_default().insert(
+        provider,
+        EncryptedKey {
+            nonce,
+            ciphertext,
+            masked: masked.clone(),
+        },
+    );
+    Ok(MaskedKey { provider, masked })
+}
+
+/// Remove the caller's key for a provider.
+#[server(DeleteProviderKey)]
+pub async fn delete_provider_key(session: String, provider: Provider) -> Result<(), ServerFnError> {
+    let user = require_user(&session).await?;
+    if let Some(keys) = KEY_STORE.write().await.get_mut(&user) {
+        keys.remove(&provider);
+    }
+    Ok(())
+}
+
+/// List the caller's stored keys in masked form.
+#[server(ListProviderKeys)]
+pub async fn list_provider_keys(session: String) -> Result<Vec<MaskedKey>, ServerFnError> {
+    let user = require_user(&session).await?;
+    let store = KEY_STORE.read().await;
+    let mut keys: Vec<MaskedKey> = store
+        .get(&user)
+        .into_iter()
+        .flatten()
+        .map(|(provider, stored)| MaskedKey {
+            provider: *provider,
+            masked: stored.masked.clone(),
+        })
+        .collect();
+    keys.sort_by_key(|k| k.provider.to_string());
+    Ok(keys)
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_all_but_the_end_of_long_keys() {
+        assert_eq!(mask("sk-abcdefgh1234"), "••••1234");
+        assert_eq!(mask("abcd"), "••••");
+        assert_eq!(mask("sk-abcdefg"), "••••");
+        assert_eq!(mask(""), "••••");
+    }
+
+    #[test]
+    fn rejects_invalid_secrets() {
+        let valid = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        assert_eq!(encryption_secret(Some(valid)).unwrap(), vec![7u8; 32]);
+        assert_eq!(encryption_secret(None).unwrap().len(), 32);
+        assert!(encryption_secret(Some("not base64!".to_string())).is_err());
+        let short = base64::engine::general_purpose::STANDARD.encode([7u8; 16]);
+        assert!(encryption_secret(Some(short)).is_err());
+    }
+
+    #[tokio::test]
+    async fn stores_keys_encrypted_and_reads_them_back() {
+        let session = session::create_user_session("key-owner".to_string()).await;
+        let caller = session::caller(&session).await.unwrap();
+        let key = "sk-test-0123456789";
+        let masked = set_provider_key(session.clone(), Provider::OpenAI, format!(" {key} "))
+            .await
+            .unwrap();
+        assert_eq!(masked.masked, "••••6789");
+
+        let store = KEY_STORE.read().await;
+        let stored = &store["key-owner"][&Provider::OpenAI];
+        assert!(!stored
+            .ciphertext
+            .windows(key.len())
+            .any(|w| w == key.as_bytes()));
+        drop(store);
+
+        assert_eq!(
+            provider_key(&caller, Provider::OpenAI).await.as_deref(),
+            Some(key)
+        );
+        delete_provider_key(session, Provider::OpenAI)
+            .await
+            .unwrap();
+        assert_eq!(provider_key(&caller, Provider::OpenAI).await, None);
+    }
+
+    #[tokio::test]
+    async fn refuses_anonymous_callers() {
+        let session = session::start_session(None).await.unwrap();
+        assert!(
+            set_provider_key(session.clone(), Provider::OpenAI, "sk-x".to_string())
+                .await
+                .is_err()
+        );
+        assert!(list_provider_keys(session).await.is_err());
+    }
+}
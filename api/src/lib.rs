@@ -1,21 +1,61 @@
 //! This crate contains all shared fullstack server functions.
+pub mod events;
+pub mod export;
+pub mod generation;
+pub mod graphviz;
+pub mod highlight;
+pub mod import;
+pub mod keys;
+#[cfg(feature = "markup")]
+pub mod markdown;
+pub mod math;
+pub mod members;
 pub mod model_config;
+#[cfg(feature = "server")]
+mod quota;
+#[cfg(feature = "server")]
+mod render;
+#[cfg(feature = "markup")]
+pub mod sanitize;
+pub mod search;
+pub mod session;
+pub mod share;
+pub mod streaming;
+pub mod sync;
+pub mod templates;
+pub mod usage;
 
-use base64::Engine;
 use dioxus::prelude::*;
-use futures::{stream, Stream, StreamExt};
-use std::pin::Pin;
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use server_fn::codec::{StreamingText, TextStream};
-use server_fn::error::NoCustomError;
-use std::collections::HashMap;
+
+#[cfg(feature = "server")]
+use base64::Engine;
+#[cfg(feature = "server")]
+use futures::{stream, Stream, StreamExt};
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "server")]
+use std::pin::Pin;
+#[cfg(feature = "server")]
 use std::sync::Arc;
+#[cfg(feature = "server")]
 use tokio::sync::{broadcast, RwLock};
+#[cfg(feature = "server")]
 use tokio_stream::wrappers::BroadcastStream;
 
+pub use events::UserEvent;
+pub use generation::{ChatError, Completion, GenerationParams};
+pub use members::{Member, Participant, Role};
 pub use model_config::ModelConfig;
+pub use session::{current_user, start_session};
+pub use share::{ShareLink, SharedConversation};
+pub use sync::{Change, ChangeKind, ConversationEvent, SyncResponse};
+pub use usage::{get_usage, Usage, UsageTotals};
 
+#[cfg(feature = "server")]
 use crate::model_config::Provider;
 
 /// Represents a message in a conversation.
@@ -25,45 +65,366 @@ pub enum MessageSender {
     AI,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ChatMessage {
+    /// Chosen by the client that wrote the message, so a message queued offline is
+    /// stored once however often it is resent. The server fills in nil ids.
+    #[serde(default)]
+    pub id: MessageId,
     pub text: Option<String>,
     pub attachment: Option<Attachment>,
     pub sender: MessageSender,
+    /// Username of the member who wrote a user message; `None` for anonymous
+    /// users and AI replies.
+    #[serde(default)]
+    pub author: Option<String>,
+    /// The model's thinking trace, kept apart from the final answer in `text`.
+    #[serde(default)]
+    pub reasoning: Option<String>,
+    /// Token counts and cost for AI replies. Set by the server from the completion
+    /// the reply came from; whatever a client sends here is ignored.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Longest title a conversation can have, in characters.
+pub const MAX_TITLE_LEN: usize = 80;
+
+/// Stable identifier of a conversation.
+pub type ConversationId = uuid::Uuid;
+
+/// Stable identifier of a message within a conversation.
+pub type MessageId = uuid::Uuid;
+
+/// A conversation as listed in the sidebar.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConversationSummary {
+    pub id: ConversationId,
+    /// `None` until the conversation has been named.
+    pub title: Option<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub folder: Option<String>,
+    /// The caller's role in the conversation. Pins, archiving and folders are the
+    /// owner's and are left unset for other members.
+    #[serde(default)]
+    pub role: Role,
 }
 
 /// Attachment data sent with a chat message.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Attachment {
     pub filename: String,
     pub content_type: String,
     pub data: String,
 }
 
+/// An entry in a conversation's change log. Messages are named by id and read from the
+/// conversation when changes are sent, so the log holds no copy of them: a deleted
+/// message or replaced text is gone from what clients receive.
+#[cfg(feature = "server")]
+enum Logged {
+    Added(MessageId),
+    Edited(MessageId),
+    Deleted(Vec<MessageId>),
+}
+
+/// Most deletions a change log keeps before it drops them all. Clients from before
+/// that are sent the whole conversation again.
+#[cfg(feature = "server")]
+const MAX_LOGGED_DELETIONS: usize = 1_000;
+
 /// Represents a conversation with its messages and broadcast channel.
+#[cfg(feature = "server")]
 struct Conversation {
+    /// [`session::Caller::key`] of the caller the conversation belongs to.
+    owner: String,
+    /// Other users with access, by [`session::Caller::key`].
+    members: HashMap<String, Role>,
+    /// Who has the conversation open.
+    present: members::PresenceMap,
+    title: Option<String>,
+    pinned: bool,
+    archived: bool,
+    folder: Option<String>,
+    /// Time of the last message, used to order the sidebar.
+    updated_at: chrono::DateTime<chrono::Utc>,
+    /// Set when the conversation is deleted; it can be restored until [`DELETED_RETENTION`] passes.
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
     messages: Vec<ChatMessage>,
-    tx: broadcast::Sender<ChatMessage>,
+    /// Version of the last change in `log`.
+    version: u64,
+    /// The changes made to the conversation by version, replayed to clients that are
+    /// behind. Entries for deleted messages and earlier edits are dropped.
+    log: Vec<(u64, Logged)>,
+    /// Deletions up to this version have been dropped from `log`.
+    compacted: u64,
+    tx: broadcast::Sender<ConversationEvent>,
+    /// Running totals of the usage attached to this conversation's messages.
+    usage: UsageTotals,
 }
 
+#[cfg(feature = "server")]
 impl Conversation {
-    fn new() -> Self {
+    fn new(owner: String) -> Self {
         let (tx, _rx) = broadcast::channel(32);
         Self {
+            owner,
+            members: HashMap::new(),
+            present: HashMap::new(),
+            title: None,
+            pinned: false,
+            archived: false,
+            folder: None,
+            updated_at: chrono::Utc::now(),
+            deleted_at: None,
             messages: Vec::new(),
+            version: 0,
+            log: Vec::new(),
+            compacted: 0,
             tx,
+            usage: UsageTotals::default(),
+        }
+    }
+
+    /// Append a change to the log under the next version and broadcast it. Entries the
+    /// change makes obsolete are dropped from the log.
+    fn record(&mut self, kind: ChangeKind) {
+        self.version += 1;
+        let logged = match &kind {
+            ChangeKind::MessageAdded(msg) => Logged::Added(msg.id),
+            ChangeKind::MessageEdited(msg) => {
+                self.log
+                    .retain(|(_, l)| !matches!(l, Logged::Edited(id) if *id == msg.id));
+                Logged::Edited(msg.id)
+            }
+            ChangeKind::MessagesDeleted(ids) => {
+                self.log.retain(|(_, l)| match l {
+                    Logged::Added(id) | Logged::Edited(id) => !ids.contains(id),
+                    Logged::Deleted(_) => true,
+                });
+                Logged::Deleted(ids.clone())
+            }
+        };
+        self.log.push((self.version, logged));
+        let deletions: usize = self
+            .log
+            .iter()
+            .map(|(_, l)| match l {
+                Logged::Deleted(ids) => ids.len(),
+                _ => 0,
+            })
+            .sum();
+        if deletions > MAX_LOGGED_DELETIONS {
+            self.log.retain(|(_, l)| !matches!(l, Logged::Deleted(_)));
+            self.compacted = self.version;
+        }
+        let change = Change {
+            version: self.version,
+            kind,
+        };
+        let _ = self.tx.send(ConversationEvent::Changed(change));
+    }
+
+    /// The role of the caller with `key`, if they have access.
+    fn role_of(&self, key: &str) -> Option<Role> {
+        if self.owner == key {
+            Some(Role::Owner)
+        } else {
+            self.members.get(key).copied()
         }
     }
+
+    /// The conversation as the caller with `key` sees it in the sidebar.
+    fn summary(&self, id: ConversationId, key: &str) -> ConversationSummary {
+        let role = self.role_of(key).unwrap_or(Role::Viewer);
+        let own = role == Role::Owner;
+        ConversationSummary {
+            id,
+            title: self.title.clone(),
+            pinned: own && self.pinned,
+            archived: own && self.archived,
+            folder: self.folder.clone().filter(|_| own),
+            role,
+        }
+    }
+
+    /// Publish an event to the owner and every member, built from the summary each sees.
+    async fn publish(&self, id: ConversationId, event: impl Fn(ConversationSummary) -> UserEvent) {
+        for key in std::iter::once(&self.owner).chain(self.members.keys()) {
+            events::publish(key, event(self.summary(id, key))).await;
+        }
+    }
+
+    /// Whether a client at version `since` has missed deletions that are no longer in
+    /// the log, and needs the whole conversation again.
+    fn needs_reset(&self, since: u64) -> bool {
+        since > self.version || (since > 0 && since < self.compacted)
+    }
+
+    /// Changes after version `since`, oldest first, with the messages as they are now.
+    /// Versions can skip the changes that were dropped from the log.
+    fn changes_since(&self, since: u64) -> Vec<Change> {
+        let messages: HashMap<MessageId, &ChatMessage> =
+            self.messages.iter().map(|m| (m.id, m)).collect();
+        let start = self.log.partition_point(|(version, _)| *version <= since);
+        self.log[start..]
+            .iter()
+            .filter_map(|(version, logged)| {
+                let kind = match logged {
+                    Logged::Added(id) => ChangeKind::MessageAdded((*messages.get(id)?).clone()),
+                    Logged::Edited(id) => ChangeKind::MessageEdited((*messages.get(id)?).clone()),
+                    Logged::Deleted(ids) => ChangeKind::MessagesDeleted(ids.clone()),
+                };
+                Some(Change {
+                    version: *version,
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Store a message unless one with the same id is already here.
+    /// Returns `false` for such duplicates.
+    async fn add_message(&mut self, conv_id: ConversationId, mut msg: ChatMessage) -> bool {
+        if msg.id.is_nil() {
+            msg.id = MessageId::new_v4();
+        } else if self.messages.iter().any(|m| m.id == msg.id) {
+            return false;
+        }
+        if let Some(usage) = &msg.usage {
+            self.usage.add(usage);
+        }
+        if let Some(text) = &msg.text {
+            search::index_message(conv_id, msg.id, text).await;
+        }
+        self.messages.push(msg.clone());
+        self.record(ChangeKind::MessageAdded(msg));
+        true
+    }
+
+    /// Whether the caller may edit or delete `msg`: the owner may change any message,
+    /// editors only the ones they wrote.
+    fn can_change(&self, caller: &session::Caller, msg: &ChatMessage) -> bool {
+        match self.role_of(&caller.key()) {
+            Some(Role::Owner) => true,
+            Some(Role::Editor) => msg.author.is_some() && msg.author == caller.username(),
+            _ => false,
+        }
+    }
+
+    /// Remove the messages at `range` and take them out of the search index.
+    async fn remove_messages(&mut self, conv_id: ConversationId, range: std::ops::Range<usize>) {
+        let removed: Vec<ChatMessage> = self.messages.drain(range).collect();
+        for msg in &removed {
+            if let Some(text) = &msg.text {
+                search::unindex_message(conv_id, msg.id, text).await;
+            }
+        }
+        self.record(ChangeKind::MessagesDeleted(
+            removed.into_iter().map(|m| m.id).collect(),
+        ));
+    }
 }
 
-type Conversations = Vec<Conversation>;
+#[cfg(feature = "server")]
+type Conversations = HashMap<ConversationId, Conversation>;
+
+/// How long deleted conversations are kept so they can be restored.
+#[cfg(feature = "server")]
+const DELETED_RETENTION: chrono::Duration = chrono::Duration::days(30);
+
+/// The conversation with `conv_id` if it belongs to `owner` and is not deleted.
+#[cfg(feature = "server")]
+fn owned_conversation<'a>(
+    history: &'a Conversations,
+    owner: &str,
+    conv_id: ConversationId,
+) -> Option<&'a Conversation> {
+    history
+        .get(&conv_id)
+        .filter(|c| c.owner == owner && c.deleted_at.is_none())
+}
 
-/// In-memory list of conversations used for demo purposes.
+/// Mutable access to the caller's conversation `conv_id`, if it is not deleted.
+#[cfg(feature = "server")]
+fn owned_conversation_mut<'a>(
+    history: &'a mut Conversations,
+    owner: &str,
+    conv_id: ConversationId,
+) -> Option<&'a mut Conversation> {
+    history
+        .get_mut(&conv_id)
+        .filter(|c| c.owner == owner && c.deleted_at.is_none())
+}
+
+/// The conversation with `conv_id` if the caller with `key` has at least `role` in it
+/// and it is not deleted.
+#[cfg(feature = "server")]
+fn accessible_conversation<'a>(
+    history: &'a Conversations,
+    key: &str,
+    conv_id: ConversationId,
+    role: Role,
+) -> Option<&'a Conversation> {
+    history
+        .get(&conv_id)
+        .filter(|c| c.deleted_at.is_none() && c.role_of(key) >= Some(role))
+}
+
+/// Mutable access to a conversation the caller with `key` has at least `role` in.
+#[cfg(feature = "server")]
+fn accessible_conversation_mut<'a>(
+    history: &'a mut Conversations,
+    key: &str,
+    conv_id: ConversationId,
+    role: Role,
+) -> Option<&'a mut Conversation> {
+    history
+        .get_mut(&conv_id)
+        .filter(|c| c.deleted_at.is_none() && c.role_of(key) >= Some(role))
+}
+
+/// Apply `update` to one of the caller's conversations and tell everyone with access.
+#[cfg(feature = "server")]
+async fn update_conversation(
+    session: &str,
+    conv_id: ConversationId,
+    update: impl FnOnce(&mut Conversation),
+) -> Result<(), ServerFnError> {
+    let owner = session::caller(session).await?.key();
+    let mut history = CHAT_HISTORY.write().await;
+    let conv = owned_conversation_mut(&mut history, &owner, conv_id)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    update(conv);
+    match conv.deleted_at {
+        Some(_) => conv.publish(conv_id, |s| UserEvent::ConversationDeleted(s.id)).await,
+        None => conv.publish(conv_id, UserEvent::ConversationUpdated).await,
+    }
+    Ok(())
+}
+
+/// Serialize each item as one line of JSON for a streaming server function.
+#[cfg(feature = "server")]
+fn json_lines<T: Serialize>(items: impl Stream<Item = T> + Send + 'static) -> TextStream {
+    let lines = items.map(|item| {
+        serde_json::to_string(&item)
+            .map(|json| json + "\n")
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))
+    });
+    let boxed: Pin<Box<dyn Stream<Item = Result<String, ServerFnError>> + Send>> =
+        Box::pin(lines);
+    TextStream::new(boxed)
+}
+
+/// In-memory conversations used for demo purposes.
 /// Each conversation stores messages and broadcasts new ones for streaming.
+#[cfg(feature = "server")]
 static CHAT_HISTORY: Lazy<Arc<RwLock<Conversations>>> =
-    Lazy::new(|| Arc::new(RwLock::new(vec![Conversation::new()])));
+    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
 
 /// In-memory store of users where the key is the username and the value is the password.
+#[cfg(feature = "server")]
 static USERS: Lazy<Arc<RwLock<HashMap<String, String>>>> =
     Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
 
@@ -73,51 +434,420 @@ pub async fn echo(input: String) -> Result<String, ServerFnError> {
     Ok(input)
 }
 
-/// Create a new conversation and return its id.
+/// Create a new conversation owned by the caller and return its id.
 #[server(CreateConversation)]
-pub async fn create_conversation() -> Result<usize, ServerFnError> {
-    let mut history = CHAT_HISTORY.write().await;
-    let id = history.len();
-    history.push(Conversation::new());
+pub async fn create_conversation(session: String) -> Result<ConversationId, ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let id = ConversationId::new_v4();
+    let conv = Conversation::new(owner.clone());
+    events::publish(&owner, UserEvent::ConversationCreated(conv.summary(id, &owner))).await;
+    CHAT_HISTORY.write().await.insert(id, conv);
     Ok(id)
 }
 
-/// List all conversation ids.
+/// List the caller's conversations and the ones shared with them, archived ones included,
+/// pinned first and then by last activity. Conversations deleted longer ago than the
+/// retention period are purged.
 #[server(ListConversations)]
-pub async fn list_conversations() -> Result<Vec<usize>, ServerFnError> {
-    let history = CHAT_HISTORY.read().await;
-    Ok((0..history.len()).collect())
+pub async fn list_conversations(
+    session: String,
+) -> Result<Vec<ConversationSummary>, ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let now = chrono::Utc::now();
+    let mut history = CHAT_HISTORY.write().await;
+    let expired: HashSet<ConversationId> = history
+        .iter()
+        .filter(|(_, c)| c.deleted_at.is_some_and(|at| now - at >= DELETED_RETENTION))
+        .map(|(id, _)| *id)
+        .collect();
+    history.retain(|id, _| !expired.contains(id));
+    search::unindex_conversations(&expired).await;
+    let mut convs: Vec<(&ConversationId, &Conversation)> = history
+        .iter()
+        .filter(|(_, c)| c.role_of(&owner).is_some() && c.deleted_at.is_none())
+        .collect();
+    convs.sort_by(|(_, a), (_, b)| {
+        b.pinned
+            .cmp(&a.pinned)
+            .then(b.updated_at.cmp(&a.updated_at))
+    });
+    Ok(convs
+        .into_iter()
+        .map(|(id, c)| c.summary(*id, &owner))
+        .collect())
+}
+
+/// Rename one of the caller's conversations.
+#[server(RenameConversation)]
+pub async fn rename_conversation(
+    session: String,
+    conv_id: ConversationId,
+    title: String,
+) -> Result<String, ServerFnError> {
+    let title = clip(&title, MAX_TITLE_LEN);
+    if title.is_empty() {
+        return Err(ServerFnError::new("Title cannot be empty"));
+    }
+    update_conversation(&session, conv_id, |c| c.title = Some(title.clone())).await?;
+    Ok(title)
+}
+
+/// Pin or unpin one of the caller's conversations.
+#[server(SetConversationPinned)]
+pub async fn set_conversation_pinned(
+    session: String,
+    conv_id: ConversationId,
+    pinned: bool,
+) -> Result<(), ServerFnError> {
+    update_conversation(&session, conv_id, |c| c.pinned = pinned).await
+}
+
+/// Archive or unarchive one of the caller's conversations.
+#[server(SetConversationArchived)]
+pub async fn set_conversation_archived(
+    session: String,
+    conv_id: ConversationId,
+    archived: bool,
+) -> Result<(), ServerFnError> {
+    update_conversation(&session, conv_id, |c| c.archived = archived).await
+}
+
+/// Move one of the caller's conversations into a folder, or out of any folder with `None`
+/// or a blank name.
+#[server(SetConversationFolder)]
+pub async fn set_conversation_folder(
+    session: String,
+    conv_id: ConversationId,
+    folder: Option<String>,
+) -> Result<(), ServerFnError> {
+    let folder = folder
+        .map(|f| clip(&f, MAX_TITLE_LEN))
+        .filter(|f| !f.is_empty());
+    update_conversation(&session, conv_id, |c| c.folder = folder).await
+}
+
+/// Delete one of the caller's conversations. It can be brought back with
+/// [`restore_conversation`] until the retention period passes.
+#[server(DeleteConversation)]
+pub async fn delete_conversation(
+    session: String,
+    conv_id: ConversationId,
+) -> Result<(), ServerFnError> {
+    update_conversation(&session, conv_id, |c| {
+        c.deleted_at = Some(chrono::Utc::now())
+    })
+    .await
+}
+
+/// Undo [`delete_conversation`].
+#[server(RestoreConversation)]
+pub async fn restore_conversation(
+    session: String,
+    conv_id: ConversationId,
+) -> Result<(), ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let mut history = CHAT_HISTORY.write().await;
+    let conv = history
+        .get_mut(&conv_id)
+        .filter(|c| c.owner == owner)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    conv.deleted_at = None;
+    conv.publish(conv_id, UserEvent::ConversationUpdated).await;
+    Ok(())
+}
+
+/// Collapse whitespace and cut `text` to at most `max` characters, ending in an ellipsis when cut.
+#[cfg(feature = "server")]
+fn clip(text: &str, max: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= max {
+        return text;
+    }
+    let mut clipped: String = text.chars().take(max.saturating_sub(1)).collect();
+    clipped.truncate(clipped.trim_end().len());
+    clipped.push('…');
+    clipped
+}
+
+/// Model used to name conversations, overridable with `TITLE_MODEL`.
+#[cfg(feature = "server")]
+static TITLE_MODEL: Lazy<String> =
+    Lazy::new(|| std::env::var("TITLE_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string()));
+
+/// Ask the title model to name an exchange, charging the caller for it as for chat. `None`
+/// if no key is available, the caller is over their limits or the request fails.
+#[cfg(feature = "server")]
+async fn llm_title(caller: &session::Caller, question: &str, answer: &str) -> Option<String> {
+    let model = ModelConfig::find(&TITLE_MODEL)?;
+    let (api_key, operator_key) = completion_key(caller, &model).await.ok()?;
+    let params = GenerationParams {
+        max_tokens: Some(24),
+        ..Default::default()
+    }
+    .or(&model.defaults);
+    let prompt = format!(
+        "Write a title of at most six words for a chat that starts with the exchange below. \
+Reply with the title only.\n\nUser: {}\n\nAssistant: {}",
+        clip(question, 1000),
+        clip(answer, 1000),
+    );
+    let tokens = max_request_tokens(&model, &params, &prompt);
+    let reservation = quota::check(caller, operator_key, tokens).await.ok()?;
+    // As for chat, the request is charged in its own task, even if the client goes away.
+    let caller = caller.clone();
+    let text = tokio::spawn(async move {
+        let mut completion = match request_completion(&model, &params, &api_key, &prompt).await {
+            Ok(completion) => completion,
+            Err(_) => {
+                if let Some(reservation) = reservation {
+                    quota::settle(reservation, 0).await;
+                }
+                return None;
+            }
+        };
+        let usage = completion
+            .usage
+            .get_or_insert_with(|| estimate_usage(&prompt, &completion.text));
+        charge_usage(&caller, &model, usage, reservation).await;
+        Some(completion.text)
+    })
+    .await
+    .ok()??;
+    let title = text.lines().next()?.trim();
+    let title = title.trim_matches(|c: char| c == '"' || c == '\'' || c == '*');
+    let title = clip(title.trim_end_matches('.'), MAX_TITLE_LEN);
+    (!title.is_empty()).then_some(title)
+}
+
+/// Name a conversation after its first exchange, falling back to the start of the first
+/// message when the title model is unavailable. Returns the existing title if it has one.
+#[server(GenerateTitle)]
+pub async fn generate_title(
+    session: String,
+    conv_id: ConversationId,
+) -> Result<Option<String>, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let key = caller.key();
+    let (question, answer) = {
+        let history = CHAT_HISTORY.read().await;
+        let Some(conv) = accessible_conversation(&history, &key, conv_id, Role::Editor) else {
+            return Ok(None);
+        };
+        if conv.title.is_some() {
+            return Ok(conv.title.clone());
+        }
+        let first_text = |sender: MessageSender| {
+            conv.messages
+                .iter()
+                .find(|m| m.sender == sender)
+                .and_then(|m| m.text.clone())
+        };
+        (first_text(MessageSender::User), first_text(MessageSender::AI))
+    };
+    let Some(question) = question else {
+        return Ok(None);
+    };
+    let generated = match &answer {
+        Some(answer) => llm_title(&caller, &question, answer).await,
+        None => None,
+    };
+    let title = generated.unwrap_or_else(|| clip(&question, 40));
+    let mut history = CHAT_HISTORY.write().await;
+    let Some(conv) = accessible_conversation_mut(&mut history, &key, conv_id, Role::Editor) else {
+        return Ok(None);
+    };
+    if conv.title.is_none() {
+        conv.title = Some(title);
+        conv.publish(conv_id, UserEvent::ConversationUpdated).await;
+    }
+    Ok(conv.title.clone())
+}
+
+/// Copy the messages behind a share link, attachments included, into a new
+/// conversation owned by the caller and return its id. Only logged-in users can fork.
+#[server(ForkSharedConversation)]
+pub async fn fork_shared_conversation(
+    session: String,
+    token: String,
+) -> Result<ConversationId, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    if !matches!(caller, session::Caller::User(_)) {
+        return Err(ServerFnError::new("Log in to continue this chat"));
+    }
+    let owner = caller.key();
+    let shared = share::get_shared_conversation(token).await?;
+    let mut conv = Conversation::new(owner.clone());
+    conv.title = shared.title;
+    let id = ConversationId::new_v4();
+    for msg in shared.messages {
+        conv.add_message(id, msg).await;
+    }
+    events::publish(&owner, UserEvent::ConversationCreated(conv.summary(id, &owner))).await;
+    CHAT_HISTORY.write().await.insert(id, conv);
+    Ok(id)
 }
 
-/// Store a chat message in memory for a specific conversation.
+/// `msg` as the server stores it when the caller sends it: user messages are attributed
+/// to the caller, and AI replies carry the usage held for their id by
+/// [`chat_completion`] or [`stream_completion`](streaming::stream_completion).
+#[cfg(feature = "server")]
+async fn received_message(caller: &session::Caller, mut msg: ChatMessage) -> ChatMessage {
+    msg.usage = match msg.sender {
+        MessageSender::User => {
+            msg.author = caller.username();
+            None
+        }
+        MessageSender::AI => usage::take_for_reply(caller, msg.id).await,
+    };
+    msg
+}
+
+/// Store a chat message in memory for a conversation the caller can write in. User
+/// messages are attributed to the caller.
 #[server(SendMessage)]
-pub async fn send_message(conv_id: usize, msg: ChatMessage) -> Result<(), ServerFnError> {
+pub async fn send_message(
+    session: String,
+    conv_id: ConversationId,
+    msg: ChatMessage,
+) -> Result<(), ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let msg = received_message(&caller, msg).await;
+    let mut history = CHAT_HISTORY.write().await;
+    if let Some(conv) = accessible_conversation_mut(&mut history, &caller.key(), conv_id, Role::Editor) {
+        if conv.add_message(conv_id, msg).await {
+            conv.updated_at = chrono::Utc::now();
+            conv.publish(conv_id, |_| UserEvent::MessageAdded(conv_id)).await;
+        }
+    }
+    Ok(())
+}
+
+/// Replace the text of a user message and drop every message after it, as they
+/// answered the old text. The client asks for a new reply.
+#[server(EditMessage)]
+pub async fn edit_message(
+    session: String,
+    conv_id: ConversationId,
+    msg_id: MessageId,
+    text: String,
+) -> Result<(), ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let mut history = CHAT_HISTORY.write().await;
+    let conv = accessible_conversation_mut(&mut history, &caller.key(), conv_id, Role::Editor)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    let index = conv
+        .messages
+        .iter()
+        .position(|m| m.id == msg_id && m.sender == MessageSender::User)
+        .ok_or_else(|| ServerFnError::new("Message not found"))?;
+    if !conv.can_change(&caller, &conv.messages[index]) {
+        return Err(ServerFnError::new("Only the author can edit this message"));
+    }
+    if index + 1 < conv.messages.len() {
+        let end = conv.messages.len();
+        conv.remove_messages(conv_id, index + 1..end).await;
+    }
+    let msg = &mut conv.messages[index];
+    if let Some(old) = &msg.text {
+        search::unindex_message(conv_id, msg.id, old).await;
+    }
+    search::index_message(conv_id, msg.id, &text).await;
+    msg.text = Some(text);
+    let msg = msg.clone();
+    conv.record(ChangeKind::MessageEdited(msg));
+    conv.updated_at = chrono::Utc::now();
+    conv.publish(conv_id, |_| UserEvent::MessagesChanged(conv_id)).await;
+    Ok(())
+}
+
+/// The messages that go together with the one at `index`: a prompt and the reply
+/// right after it. A prompt left unanswered, or a reply with no prompt before it,
+/// stands alone.
+#[cfg(feature = "server")]
+fn pair_range(messages: &[ChatMessage], index: usize) -> std::ops::Range<usize> {
+    let is_user = |i: usize| messages.get(i).map(|m| m.sender == MessageSender::User);
+    let start = match is_user(index) {
+        Some(false) if index > 0 && is_user(index - 1) == Some(true) => index - 1,
+        _ => index,
+    };
+    let end = match (is_user(start), is_user(start + 1)) {
+        (Some(true), Some(false)) => start + 2,
+        _ => start + 1,
+    };
+    start..end
+}
+
+/// Delete a prompt together with its reply. `msg_id` may name either of them.
+#[server(DeleteMessage)]
+pub async fn delete_message(
+    session: String,
+    conv_id: ConversationId,
+    msg_id: MessageId,
+) -> Result<(), ServerFnError> {
+    let caller = session::caller(&session).await?;
     let mut history = CHAT_HISTORY.write().await;
-    if let Some(conv) = history.get_mut(conv_id) {
-        conv.messages.push(msg.clone());
-        let _ = conv.tx.send(msg);
+    let conv = accessible_conversation_mut(&mut history, &caller.key(), conv_id, Role::Editor)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    let index = conv
+        .messages
+        .iter()
+        .position(|m| m.id == msg_id)
+        .ok_or_else(|| ServerFnError::new("Message not found"))?;
+    let pair = pair_range(&conv.messages, index);
+    let allowed = match conv.messages[pair.clone()].iter().find(|m| m.sender == MessageSender::User) {
+        Some(prompt) => conv.can_change(&caller, prompt),
+        None => conv.role_of(&caller.key()) == Some(Role::Owner),
+    };
+    if !allowed {
+        return Err(ServerFnError::new("Only the author can delete this message"));
     }
+    conv.remove_messages(conv_id, pair).await;
+    conv.updated_at = chrono::Utc::now();
+    conv.publish(conv_id, |_| UserEvent::MessagesChanged(conv_id)).await;
     Ok(())
 }
 
-/// Retrieve all chat messages for a specific conversation.
+/// Retrieve all chat messages for a conversation the caller has access to.
 #[server(GetMessages)]
-pub async fn get_messages(conv_id: usize) -> Result<Vec<ChatMessage>, ServerFnError> {
+pub async fn get_messages(session: String, conv_id: ConversationId) -> Result<Vec<ChatMessage>, ServerFnError> {
+    let key = session::caller(&session).await?.key();
     let history = CHAT_HISTORY.read().await;
-    Ok(history
-        .get(conv_id)
+    Ok(accessible_conversation(&history, &key, conv_id, Role::Viewer)
         .map(|c| c.messages.clone())
         .unwrap_or_default())
 }
 
-/// Stream messages for a conversation starting at the given index.
+/// Total token usage and cost of a conversation.
+#[server(GetConversationUsage)]
+pub async fn get_conversation_usage(
+    session: String,
+    conv_id: ConversationId,
+) -> Result<UsageTotals, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let history = CHAT_HISTORY.read().await;
+    Ok(accessible_conversation(&history, &key, conv_id, Role::Viewer)
+        .map(|c| c.usage)
+        .unwrap_or_default())
+}
+
+/// Stream a conversation's events, one JSON [`ConversationEvent`] per line: first the
+/// changes after version `since` already in the log, then changes and presence updates
+/// as they happen. The caller counts as present until the stream is dropped.
 #[server(StreamMessages, output = StreamingText)]
-pub async fn stream_messages(conv_id: usize, from: usize) -> Result<TextStream, ServerFnError> {
-    // Get the conversation and its message sender
-    let (messages, sender) = {
-        let history = CHAT_HISTORY.read().await;
-        if let Some(conv) = history.get(conv_id) {
-            (conv.messages.clone(), conv.tx.clone())
+pub async fn stream_messages(
+    session: String,
+    conv_id: ConversationId,
+    since: u64,
+) -> Result<TextStream, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let key = caller.key();
+    // Subscribe while holding the lock so no change falls between the two streams.
+    let (past, rx, mut removed) = {
+        let mut history = CHAT_HISTORY.write().await;
+        if let Some(conv) = accessible_conversation_mut(&mut history, &key, conv_id, Role::Viewer) {
+            let rx = conv.tx.subscribe();
+            let removed = conv.join(&caller);
+            (conv.changes_since(since), rx, removed)
         } else {
             // Return an empty stream if conversation not found
             let empty: Pin<Box<dyn Stream<Item = Result<String, ServerFnError>> + Send>> =
@@ -126,25 +856,22 @@ pub async fn stream_messages(conv_id: usize, from: usize) -> Result<TextStream,
         }
     };
 
-    // Create a stream of past messages serialized to JSON
-    let past = stream::iter(
-        messages
-            .into_iter()
-            .skip(from)
-            .map(|m| serde_json::to_string(&m).map_err(|e| ServerFnError::ServerError(e.to_string()))),
-    );
-
-    // Create a stream of incoming messages
-    let rx = sender.subscribe();
+    let guard = members::PresenceGuard {
+        conv_id,
+        key,
+        joined: removed.clone(),
+    };
     let incoming = BroadcastStream::new(rx)
-        .filter_map(|msg| async move { msg.ok() })
-        .map(|m| serde_json::to_string(&m).map_err(|e| ServerFnError::ServerError(e.to_string())));
-
-    // Combine the streams and convert to TextStream
-    let stream = past.chain(incoming);
-    let boxed: Pin<Box<dyn Stream<Item = Result<String, ServerFnError>> + Send>> =
-        Box::pin(stream);
-    Ok(TextStream::new(boxed))
+        .filter_map(move |event| {
+            let _present = &guard;
+            futures::future::ready(event.ok())
+        })
+        // Nothing more once the caller is removed from the conversation.
+        .take_until(async move {
+            let _ = removed.changed().await;
+        });
+    let past = past.into_iter().map(ConversationEvent::Changed);
+    Ok(json_lines(stream::iter(past).chain(incoming)))
 }
 
 /// Generate an image from a prompt and return it as a data URI.
@@ -171,101 +898,317 @@ pub async fn register(username: String, password: String) -> Result<(), ServerFn
     Ok(())
 }
 
-/// Verify user credentials and return true if they are valid.
+/// Verify user credentials and return a session token for the user if they are valid.
 #[server(Login)]
-pub async fn login(username: String, password: String) -> Result<bool, ServerFnError> {
-    let users = USERS.read().await;
-    Ok(users
-        .get(&username)
-        .map(|p| p == &password)
-        .unwrap_or(false))
+pub async fn login(username: String, password: String) -> Result<Option<String>, ServerFnError> {
+    let valid = {
+        let users = USERS.read().await;
+        users
+            .get(&username)
+            .map(|p| p == &password)
+            .unwrap_or(false)
+    };
+    if !valid {
+        return Ok(None);
+    }
+    Ok(Some(session::create_user_session(username).await))
 }
 
 //TODO: Why is this "OpenAiMessage"? we have multiple ai providers
+#[cfg(feature = "server")]
 #[derive(Serialize, Deserialize)]
 struct OpenAiMessage<'a> {
     role: &'a str,
     content: &'a str,
 }
 
-/// Query an AI model using the provided API key.
-#[server(ChatCompletion)]
-pub async fn chat_completion(
-    api_key: String,
-    prompt: String,
-    model: ModelConfig,
-) -> Result<String, ServerFnError> {
-    let client = reqwest::Client::new();
-    match model.provider {
-        Provider::OpenAI => {
-            let body = serde_json::json!({
-                "model": model,
-                "messages": [OpenAiMessage { role: "user", content: &prompt }],
-            });
-            let res = client
-                .post("https://api.openai.com/v1/chat/completions")
-                .bearer_auth(api_key)
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            let json: serde_json::Value = res
-                .json()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            if let Some(reply) = json["choices"][0]["message"]["content"].as_str() {
-                Ok(reply.to_string())
-            } else {
-                Err(ServerFnError::<NoCustomError>::ServerError("invalid response".into()))
-            }
+/// Copy the sampling parameters into a provider request body using that provider's field names.
+#[cfg(feature = "server")]
+fn apply_generation_params(
+    body: &mut serde_json::Value,
+    model: &ModelConfig,
+    params: &GenerationParams,
+) {
+    let openai_reasoning = model.capabilities.reasoning && model.provider == Provider::OpenAI;
+    if !openai_reasoning {
+        if let Some(t) = params.temperature {
+            body["temperature"] = t.into();
+        }
+        if let Some(p) = params.top_p {
+            body["top_p"] = p.into();
         }
+    }
+    match model.provider {
         Provider::Anthropic => {
-            let body = serde_json::json!({
-                "model": model,
-                "max_tokens": 1024,
-                "messages": [{"role": "user", "content": prompt}],
-            });
-            let res = client
-                .post("https://api.anthropic.com/v1/messages")
-                .header("x-api-key", api_key)
-                .header("anthropic-version", "2023-06-01")
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            let json: serde_json::Value = res
-                .json()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            if let Some(reply) = json["content"][0]["text"].as_str() {
-                Ok(reply.to_string())
-            } else {
-                Err(ServerFnError::<NoCustomError>::ServerError("invalid response".into()))
+            body["max_tokens"] = params
+                .max_tokens
+                .unwrap_or(generation::ANTHROPIC_FALLBACK_MAX_TOKENS)
+                .into();
+            if !params.stop.is_empty() {
+                body["stop_sequences"] = params.stop.clone().into();
+            }
+            if let Some(budget) = params.thinking_budget {
+                body["thinking"] = serde_json::json!({
+                    "type": "enabled",
+                    "budget_tokens": budget,
+                });
+            }
+            if let Some(system) = &params.system {
+                body["system"] = system.as_str().into();
             }
         }
-        Provider::OpenRouter => {
-            let body = serde_json::json!({
-                "model": model,
-                "messages": [{"role": "user", "content": prompt}],
-            });
-            let res = client
-                .post("https://api.openrouter.ai/v1/chat/completions")
-                .bearer_auth(api_key)
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            let json: serde_json::Value = res
-                .json()
-                .await
-                .map_err(|e| ServerFnError::<NoCustomError>::ServerError(e.to_string()))?;
-            if let Some(reply) = json["choices"][0]["message"]["content"].as_str() {
-                Ok(reply.to_string())
-            } else {
-                Err(ServerFnError::<NoCustomError>::ServerError("invalid response".into()))
+        _ => {
+            if let Some(n) = params.max_tokens {
+                // Reasoning models count hidden reasoning tokens against this limit.
+                let field = if openai_reasoning {
+                    "max_completion_tokens"
+                } else {
+                    "max_tokens"
+                };
+                body[field] = n.into();
+            }
+            if !params.stop.is_empty() {
+                body["stop"] = params.stop.clone().into();
+            }
+            if let Some(effort) = params.reasoning_effort {
+                if model.provider == Provider::OpenRouter {
+                    body["reasoning"] = serde_json::json!({ "effort": effort });
+                } else {
+                    body["reasoning_effort"] = effort.to_string().into();
+                }
+            }
+            if let (Some(system), Some(messages)) = (&params.system, body["messages"].as_array_mut()) {
+                messages.insert(0, serde_json::json!(OpenAiMessage { role: "system", content: system }));
             }
         }
-        _ => Err(ServerFnError::<NoCustomError>::ServerError("unknown provider".into())),
+    }
+}
+
+/// The provider's error message, or a generic one when the body has none.
+#[cfg(feature = "server")]
+fn provider_error(json: &serde_json::Value) -> ChatError {
+    ChatError::Provider(
+        json["error"]["message"]
+            .as_str()
+            .unwrap_or("invalid response")
+            .to_string(),
+    )
+}
+
+/// Read the reply from an OpenAI-compatible response, along with any reasoning the provider exposes.
+#[cfg(feature = "server")]
+fn parse_openai_completion(json: &serde_json::Value) -> Option<Completion> {
+    let message = &json["choices"][0]["message"];
+    let text = message["content"].as_str()?.to_string();
+    let reasoning = message["reasoning"]
+        .as_str()
+        .or_else(|| message["reasoning_content"].as_str())
+        .map(str::to_string);
+    let usage = json["usage"].is_object().then(|| Usage {
+        prompt_tokens: json["usage"]["prompt_tokens"].as_u64().unwrap_or_default() as u32,
+        completion_tokens: json["usage"]["completion_tokens"].as_u64().unwrap_or_default() as u32,
+        cost: None,
+    });
+    Some(Completion {
+        text,
+        reasoning,
+        usage,
+    })
+}
+
+/// Read the reply from an Anthropic response, collecting thinking blocks separately from text.
+#[cfg(feature = "server")]
+fn parse_anthropic_completion(json: &serde_json::Value) -> Option<Completion> {
+    let mut completion = Completion::default();
+    for block in json["content"].as_array()? {
+        match block["type"].as_str() {
+            Some("text") => completion
+                .text
+                .push_str(block["text"].as_str().unwrap_or_default()),
+            Some("thinking") => completion
+                .reasoning
+                .get_or_insert_with(String::new)
+                .push_str(block["thinking"].as_str().unwrap_or_default()),
+            _ => {}
+        }
+    }
+    completion.usage = json["usage"].is_object().then(|| Usage {
+        prompt_tokens: json["usage"]["input_tokens"].as_u64().unwrap_or_default() as u32,
+        completion_tokens: json["usage"]["output_tokens"].as_u64().unwrap_or_default() as u32,
+        cost: None,
+    });
+    Some(completion)
+}
+
+/// Build the request for a single-turn prompt to the model's provider, asking for
+/// server-sent events when `stream` is set.
+#[cfg(feature = "server")]
+fn completion_request(
+    model: &ModelConfig,
+    params: &GenerationParams,
+    api_key: &str,
+    prompt: &str,
+    stream: bool,
+) -> Result<reqwest::RequestBuilder, ChatError> {
+    let mut body = serde_json::json!({
+        "model": model.name,
+        "messages": [OpenAiMessage { role: "user", content: prompt }],
+    });
+    apply_generation_params(&mut body, model, params);
+    if stream {
+        body["stream"] = true.into();
+        if model.provider != Provider::Anthropic {
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+    }
+    let client = reqwest::Client::new();
+    let request = match model.provider {
+        Provider::OpenAI => client
+            .post("https://api.openai.com/v1/chat/completions")
+            .bearer_auth(api_key),
+        Provider::Anthropic => client
+            .post("https://api.anthropic.com/v1/messages")
+            .header("x-api-key", api_key)
+            .header("anthropic-version", "2023-06-01"),
+        Provider::OpenRouter => client
+            .post("https://api.openrouter.ai/v1/chat/completions")
+            .bearer_auth(api_key),
+        _ => return Err(ChatError::Provider("unknown provider".into())),
+    };
+    Ok(request.json(&body))
+}
+
+/// Send a single-turn prompt to the model's provider.
+#[cfg(feature = "server")]
+async fn request_completion(
+    model: &ModelConfig,
+    params: &GenerationParams,
+    api_key: &str,
+    prompt: &str,
+) -> Result<Completion, ChatError> {
+    let res = completion_request(model, params, api_key, prompt, false)?
+        .send()
+        .await
+        .map_err(|e| ChatError::Provider(e.to_string()))?;
+    let json: serde_json::Value = res
+        .json()
+        .await
+        .map_err(|e| ChatError::Provider(e.to_string()))?;
+    let completion = match model.provider {
+        Provider::Anthropic => parse_anthropic_completion(&json),
+        _ => parse_openai_completion(&json),
+    };
+    completion.ok_or_else(|| provider_error(&json))
+}
+
+/// The registry's model called `name`. Requests name their model rather than describe it,
+/// so its limits and price are the operator's.
+#[cfg(feature = "server")]
+fn registry_model(name: &str) -> Result<ModelConfig, ChatError> {
+    ModelConfig::find(name).ok_or_else(|| ChatError::UnknownModel(name.to_string()))
+}
+
+/// The key to call the model's provider with: the caller's own, or else the operator's,
+/// in which case the second value is `true`.
+#[cfg(feature = "server")]
+async fn completion_key(
+    caller: &session::Caller,
+    model: &ModelConfig,
+) -> Result<(String, bool), ChatError> {
+    match keys::provider_key(caller, model.provider).await {
+        Some(key) => Ok((key, false)),
+        None => quota::operator_key(model.provider)
+            .map(|key| (key, true))
+            .ok_or(ChatError::NoApiKey(model.provider)),
+    }
+}
+
+/// Query an AI model using the caller's stored key for the model's provider, falling back to
+/// the operator's key within the caller's quota.
+///
+/// `model` names a model in the registry. `params` overrides its defaults and is validated
+/// against the provider's limits. Reasoning output is returned separately from the answer.
+/// Token usage is priced, added to the caller's totals and attached to the reply when the
+/// caller stores it under `reply_id`.
+#[server(ChatCompletion)]
+pub async fn chat_completion(
+    session: String,
+    prompt: String,
+    model: String,
+    params: GenerationParams,
+    reply_id: MessageId,
+) -> Result<Completion, ServerFnError<ChatError>> {
+    let model = registry_model(&model)?;
+    let params = params.or(&model.defaults);
+    params.validate(&model).map_err(ChatError::from)?;
+    let caller = session::caller(&session)
+        .await
+        .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+    let (api_key, operator_key) = completion_key(&caller, &model).await?;
+    let reservation =
+        quota::check(&caller, operator_key, max_request_tokens(&model, &params, &prompt)).await?;
+
+    // The request runs in its own task so that it is charged and its reservation settled
+    // even if the client goes away and this future is dropped.
+    let completion = tokio::spawn(async move {
+        let mut completion = match request_completion(&model, &params, &api_key, &prompt).await {
+            Ok(completion) => completion,
+            Err(e) => {
+                if let Some(reservation) = reservation {
+                    quota::settle(reservation, 0).await;
+                }
+                return Err(e);
+            }
+        };
+        let usage = completion
+            .usage
+            .get_or_insert_with(|| estimate_usage(&prompt, &completion.text));
+        charge_usage(&caller, &model, usage, reservation).await;
+        usage::hold_for_reply(&caller, reply_id, *usage).await;
+        Ok(completion)
+    })
+    .await
+    .map_err(|e| ChatError::Provider(e.to_string()))??;
+    Ok(completion)
+}
+
+/// The most tokens a request can use: its prompt and system message plus the longest
+/// answer `params` allows.
+#[cfg(feature = "server")]
+fn max_request_tokens(model: &ModelConfig, params: &GenerationParams, prompt: &str) -> u64 {
+    let system = params.system.as_deref().unwrap_or_default();
+    let answer = params
+        .max_tokens
+        .map_or(model.max_tokens as u64, u64::from);
+    quota::estimate_tokens(prompt) + quota::estimate_tokens(system) + answer
+}
+
+/// Usage estimated from the text of an exchange, for providers that do not report it.
+#[cfg(feature = "server")]
+fn estimate_usage(prompt: &str, answer: &str) -> Usage {
+    Usage {
+        prompt_tokens: quota::estimate_tokens(prompt) as u32,
+        completion_tokens: quota::estimate_tokens(answer) as u32,
+        cost: None,
+    }
+}
+
+/// Price a completion's usage and add it to the caller's totals, and settle the quota
+/// reservation when it was paid for with an operator key.
+#[cfg(feature = "server")]
+async fn charge_usage(
+    caller: &session::Caller,
+    model: &ModelConfig,
+    usage: &mut Usage,
+    reservation: Option<quota::Reservation>,
+) {
+    usage.cost = model
+        .pricing
+        .map(|p| p.cost(usage.prompt_tokens, usage.completion_tokens));
+    usage::record(caller, &model.name, *usage).await;
+    if let Some(reservation) = reservation {
+        let tokens = u64::from(usage.prompt_tokens) + u64::from(usage.completion_tokens);
+        quota::settle(reservation, tokens).await;
     }
 }
 
@@ -299,3 +1242,116 @@ pub async fn web_search(query: String) -> Result<String, ServerFnError> {
 
     Ok(results.join("\n"))
 }
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    fn message(sender: MessageSender) -> ChatMessage {
+        ChatMessage {
+            id: MessageId::new_v4(),
+            text: Some("text".to_string()),
+            attachment: None,
+            sender,
+            author: None,
+            reasoning: None,
+            usage: None,
+        }
+    }
+
+    fn messages(senders: &str) -> Vec<ChatMessage> {
+        senders
+            .chars()
+            .map(|c| match c {
+                'U' => message(MessageSender::User),
+                _ => message(MessageSender::AI),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pairs_the_reply_of_the_last_prompt_with_it() {
+        assert_eq!(pair_range(&messages("UAUA"), 3), 2..4);
+        assert_eq!(pair_range(&messages("UAUA"), 2), 2..4);
+    }
+
+    #[test]
+    fn pairs_the_prompt_of_a_middle_pair_with_its_reply() {
+        assert_eq!(pair_range(&messages("UAUAUA"), 2), 2..4);
+        assert_eq!(pair_range(&messages("UAUAUA"), 3), 2..4);
+    }
+
+    #[test]
+    fn leaves_an_unanswered_prompt_out_of_the_next_pair() {
+        // The first prompt got no reply; the second one did.
+        let conv = messages("UUA");
+        assert_eq!(pair_range(&conv, 0), 0..1);
+        assert_eq!(pair_range(&conv, 1), 1..3);
+        assert_eq!(pair_range(&conv, 2), 1..3);
+        // The last prompt is still waiting for its reply.
+        assert_eq!(pair_range(&messages("UAU"), 2), 2..3);
+    }
+
+    #[test]
+    fn leaves_a_reply_without_a_prompt_alone() {
+        assert_eq!(pair_range(&messages("AUA"), 0), 0..1);
+        assert_eq!(pair_range(&messages("UAA"), 2), 2..3);
+    }
+
+    fn texts(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .filter_map(|c| match &c.kind {
+                ChangeKind::MessageAdded(m) | ChangeKind::MessageEdited(m) => m.text.clone(),
+                ChangeKind::MessagesDeleted(_) => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replays_no_deleted_messages_or_replaced_text() {
+        let conv_id = ConversationId::new_v4();
+        let mut conv = Conversation::new("user:owner".to_string());
+        for (i, msg) in messages("UAUA").into_iter().enumerate() {
+            let msg = ChatMessage {
+                text: Some(format!("message {i}")),
+                ..msg
+            };
+            conv.add_message(conv_id, msg).await;
+        }
+        conv.remove_messages(conv_id, 2..4).await;
+        conv.messages[0].text = Some("edited".to_string());
+        conv.record(ChangeKind::MessageEdited(conv.messages[0].clone()));
+        conv.messages[0].text = Some("edited again".to_string());
+        conv.record(ChangeKind::MessageEdited(conv.messages[0].clone()));
+
+        let changes = conv.changes_since(0);
+        assert_eq!(
+            texts(&changes),
+            ["edited again", "message 1", "edited again"]
+        );
+        assert_eq!(conv.log.len(), 4);
+        // A client that had the deleted messages still learns they are gone.
+        let after_adding = conv.changes_since(4);
+        assert!(matches!(
+            after_adding[0].kind,
+            ChangeKind::MessagesDeleted(ref ids) if ids.len() == 2
+        ));
+        assert_eq!(after_adding.last().unwrap().version, conv.version);
+    }
+
+    #[tokio::test]
+    async fn drops_old_deletions_and_resets_clients_from_before() {
+        let conv_id = ConversationId::new_v4();
+        let mut conv = Conversation::new("user:owner".to_string());
+        for _ in 0..=MAX_LOGGED_DELETIONS {
+            conv.add_message(conv_id, message(MessageSender::User))
+                .await;
+            conv.remove_messages(conv_id, 0..1).await;
+        }
+        assert!(conv.log.is_empty());
+        assert!(conv.needs_reset(1));
+        assert!(!conv.needs_reset(0));
+        assert!(!conv.needs_reset(conv.version));
+    }
+}
@@ -0,0 +1,124 @@
+//! Message Markdown with TeX math, rendered to sanitized HTML.
+//!
+//! The chat and exported pages render messages the same way and differ only in how
+//! they draw formulas and code blocks, which callers pass in.
+use crate::math::{
+    extract_math, has_placeholders, restore_placeholders, split_placeholders, Math, Piece,
+};
+use crate::sanitize::{sanitize_math_html, TrustedHtml};
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
+
+/// The Markdown extensions messages are parsed with.
+pub fn markdown_options() -> Options {
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_TABLES);
+    opts.insert(Options::ENABLE_FOOTNOTES);
+    opts.insert(Options::ENABLE_STRIKETHROUGH);
+    opts.insert(Options::ENABLE_TASKLISTS);
+    opts
+}
+
+/// The language token of a code block's fence info string.
+pub fn fence_lang(kind: CodeBlockKind) -> String {
+    match kind {
+        CodeBlockKind::Fenced(info) => info
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        CodeBlockKind::Indented => String::new(),
+    }
+}
+
+/// Render `text` as Markdown with TeX math in `$`, `$$`, `\(` and `\[` delimiters.
+///
+/// Formulas are drawn by `render_math`, whose output goes through
+/// [`sanitize_math_html`], and code blocks by `render_code` from their code and
+/// fence token. Both are put into the Markdown after it is sanitized. Raw HTML in
+/// the source is shown as text, and so is math in an image description, which
+/// becomes an attribute.
+pub fn render_markdown(
+    text: &str,
+    mut render_math: impl FnMut(&Math) -> String,
+    mut render_code: impl FnMut(&str, &str) -> String,
+) -> String {
+    let (source, math) = extract_math(&TrustedHtml::strip_markers(text));
+    let mut trusted = TrustedHtml::default();
+    let mut code_block: Option<(String, String)> = None;
+    let mut images = 0;
+    let events = Parser::new_ext(&source, markdown_options()).filter_map(|event| match event {
+        Event::Start(Tag::CodeBlock(kind)) => {
+            code_block = Some((fence_lang(kind), String::new()));
+            None
+        }
+        Event::End(Tag::CodeBlock(_)) => code_block
+            .take()
+            .map(|(lang, code)| Event::Html(trusted.insert(render_code(&code, &lang)).into())),
+        Event::Text(t) if code_block.is_some() => {
+            if let Some((_, code)) = &mut code_block {
+                code.push_str(&restore_placeholders(&t, &math));
+            }
+            None
+        }
+        Event::Start(Tag::Image(..)) => {
+            images += 1;
+            Some(event)
+        }
+        Event::End(Tag::Image(..)) => {
+            images -= 1;
+            Some(event)
+        }
+        Event::Html(raw) => Some(Event::Text(restore_placeholders(&raw, &math).into())),
+        Event::Text(t) if images > 0 => Some(Event::Text(restore_placeholders(&t, &math).into())),
+        Event::Text(t) if has_placeholders(&t) => {
+            let mut html = String::new();
+            for piece in split_placeholders(&t, &math) {
+                match piece {
+                    Piece::Text(text) => {
+                        let _ = escape_html(&mut html, text);
+                    }
+                    Piece::Math(m) => {
+                        html.push_str(&trusted.insert(sanitize_math_html(&render_math(m))));
+                    }
+                }
+            }
+            Some(Event::Html(html.into()))
+        }
+        other => Some(other),
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    trusted.sanitize(&output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str) -> String {
+        render_markdown(
+            text,
+            |m| format!("<span class=\"katex\">{}</span>", m.tex),
+            |code, lang| format!("<pre class=\"code\" title=\"{lang}\">{code}</pre>"),
+        )
+    }
+
+    #[test]
+    fn renders_math_and_code_with_the_given_renderers() {
+        let html = render("$x^2$\n\n```rust\nfn main() {}\n```");
+        assert!(html.contains(r#"<span class="katex">x^2</span>"#), "{html}");
+        assert!(
+            html.contains(r#"<pre class="code" title="rust">"#),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn shows_math_in_image_descriptions_as_source() {
+        let html = render("![area $\\pi r^2$](https://example.com/circle.png) and $y$");
+        assert!(html.contains(r#"alt="area $\pi r^2$""#), "{html}");
+        assert!(!html.contains("pi r^2</span>"), "{html}");
+        assert!(html.contains(r#"<span class="katex">y</span>"#), "{html}");
+    }
+}
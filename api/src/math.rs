@@ -0,0 +1,294 @@
+//! Finding TeX math in Markdown message text.
+//!
+//! Math is cut out of the source before the Markdown is parsed, so Markdown escapes
+//! and emphasis never reach the TeX, and is replaced by placeholders that come back
+//! out of the parser as text. Math inside code spans and fences is left alone.
+
+/// A formula found in a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Math {
+    pub tex: String,
+    /// `$$...$$` and `\[...\]` are display math, `$...$` and `\(...\)` inline.
+    pub display: bool,
+    /// The formula as written, delimiters included.
+    pub source: String,
+}
+
+/// A run of text or a formula, as found by [`split_placeholders`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    Math(&'a Math),
+}
+
+// Private-use characters pass through Markdown untouched and do not occur in real text.
+const OPEN: char = '\u{E000}';
+const CLOSE: char = '\u{E001}';
+
+/// Replace the math in `source` with placeholders. Returns the new source and the
+/// formulas in the order they appear.
+pub fn extract_math(source: &str) -> (String, Vec<Math>) {
+    let mut out = String::with_capacity(source.len());
+    let mut math = Vec::new();
+    let mut rest = source;
+    let mut line_start = true;
+    while let Some(c) = rest.chars().next() {
+        let len = if line_start {
+            fenced_block_len(rest)
+        } else {
+            None
+        };
+        let (copy, found) = match (len, c) {
+            (Some(len), _) => (len, None),
+            (None, '`') => (code_span_len(rest), None),
+            (None, '\\') => match bracket_math(rest) {
+                Some((found, len)) => (len, Some(found)),
+                // Copy escapes whole so `\$` stays a literal dollar sign.
+                None => (1 + rest[1..].chars().next().map_or(0, char::len_utf8), None),
+            },
+            (None, '$') => match dollar_math(rest) {
+                Some((found, len)) => (len, Some(found)),
+                None => (1, None),
+            },
+            (None, c) => (c.len_utf8(), None),
+        };
+        match found {
+            Some(found) => {
+                out.push(OPEN);
+                out.push_str(&math.len().to_string());
+                out.push(CLOSE);
+                math.push(found);
+            }
+            None => out.push_str(&rest[..copy]),
+        }
+        line_start = rest[..copy].ends_with('\n');
+        rest = &rest[copy..];
+    }
+    (out, math)
+}
+
+/// Split text that came out of the Markdown parser into text and the formulas its
+/// placeholders stand for.
+pub fn split_placeholders<'a>(text: &'a str, math: &'a [Math]) -> Vec<Piece<'a>> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        let after = &rest[start + OPEN.len_utf8()..];
+        let found = after
+            .find(CLOSE)
+            .and_then(|end| Some((end, math.get(after[..end].parse::<usize>().ok()?)?)));
+        let Some((end, formula)) = found else {
+            break;
+        };
+        if start > 0 {
+            pieces.push(Piece::Text(&rest[..start]));
+        }
+        pieces.push(Piece::Math(formula));
+        rest = &after[end + CLOSE.len_utf8()..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+    pieces
+}
+
+/// Put the math back into text that is not rendered as Markdown, such as indented code.
+pub fn restore_placeholders(text: &str, math: &[Math]) -> String {
+    split_placeholders(text, math)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text,
+            Piece::Math(m) => &m.source,
+        })
+        .collect()
+}
+
+/// Whether `text` holds any placeholders.
+pub fn has_placeholders(text: &str) -> bool {
+    text.contains(OPEN)
+}
+
+/// Length of the fenced code block starting at `text`, through its closing fence or to
+/// the end of the text if it is never closed.
+fn fenced_block_len(text: &str) -> Option<usize> {
+    let first_line_end = text.find('\n').map_or(text.len(), |i| i + 1);
+    let opening = text[..first_line_end].trim_start_matches(' ');
+    if first_line_end - opening.len() > 3 {
+        return None;
+    }
+    let marker = opening.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let width = opening.chars().take_while(|c| *c == marker).count();
+    if width < 3 {
+        return None;
+    }
+    let mut end = first_line_end;
+    for line in text[first_line_end..].split_inclusive('\n') {
+        end += line.len();
+        let closing = line.trim_start_matches(' ');
+        let fence = closing.chars().take_while(|c| *c == marker).count();
+        if line.len() - closing.len() <= 3 && fence >= width && closing[fence..].trim().is_empty() {
+            return Some(end);
+        }
+    }
+    Some(text.len())
+}
+
+/// Length of the code span starting at `text`, or of its opening backticks if it is
+/// never closed.
+fn code_span_len(text: &str) -> usize {
+    let width = text.chars().take_while(|c| *c == '`').count();
+    let mut offset = width;
+    while let Some(start) = text[offset..].find('`') {
+        let start = offset + start;
+        let run = text[start..].chars().take_while(|c| *c == '`').count();
+        if run == width {
+            return start + run;
+        }
+        offset = start + run;
+    }
+    width
+}
+
+/// `\(...\)` or `\[...\]` at the start of `text`, with the length it takes up.
+fn bracket_math(text: &str) -> Option<(Math, usize)> {
+    let (close, display) = match text.get(..2)? {
+        "\\(" => ("\\)", false),
+        "\\[" => ("\\]", true),
+        _ => return None,
+    };
+    let end = text[2..].find(close)? + 2;
+    let tex = text[2..end].trim();
+    (!tex.is_empty()).then(|| {
+        let len = end + close.len();
+        let math = Math {
+            tex: tex.to_string(),
+            display,
+            source: text[..len].to_string(),
+        };
+        (math, len)
+    })
+}
+
+/// `$$...$$` or `$...$` at the start of `text`, with the length it takes up. Inline
+/// math must not start or end with a space or be followed by a digit, so prices like
+/// "$5 and $10" stay text.
+fn dollar_math(text: &str) -> Option<(Math, usize)> {
+    if let Some(body) = text.strip_prefix("$$") {
+        let end = body.find("$$")?;
+        let tex = body[..end].trim();
+        return (!tex.is_empty()).then(|| {
+            let math = Math {
+                tex: tex.to_string(),
+                display: true,
+                source: text[..end + 4].to_string(),
+            };
+            (math, end + 4)
+        });
+    }
+    let body = &text[1..];
+    if body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut prev = None;
+    for (i, c) in body.char_indices() {
+        match c {
+            '$' if i > 0 => {
+                let closes = !prev.is_some_and(|p: char| p.is_whitespace() || p == '\\')
+                    && !body[i + 1..].starts_with(|n: char| n.is_ascii_digit());
+                if closes {
+                    let math = Math {
+                        tex: body[..i].to_string(),
+                        display: false,
+                        source: text[..i + 2].to_string(),
+                    };
+                    return Some((math, i + 2));
+                }
+            }
+            // Inline math stays within a paragraph.
+            '\n' if prev == Some('\n') => return None,
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The TeX of every formula found in `source`, with whether it is display math.
+    fn found(source: &str) -> Vec<(String, bool)> {
+        extract_math(source)
+            .1
+            .into_iter()
+            .map(|m| (m.tex, m.display))
+            .collect()
+    }
+
+    #[test]
+    fn finds_each_kind_of_delimiter() {
+        assert_eq!(
+            found(r"$x^2$, $$\sum_i i$$, \(a+b\) and \[c\]"),
+            [
+                ("x^2".to_string(), false),
+                (r"\sum_i i".to_string(), true),
+                ("a+b".to_string(), false),
+                ("c".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_prices_alone() {
+        assert!(found("It costs $5 and $10.").is_empty());
+        assert!(found("From $5 to $10 and $20").is_empty());
+        assert!(found("$ x$").is_empty());
+    }
+
+    #[test]
+    fn leaves_escaped_dollars_alone() {
+        let source = r"A literal \$x\$ and \$5.";
+        let (out, math) = extract_math(source);
+        assert!(math.is_empty());
+        assert_eq!(out, source);
+        assert_eq!(found(r"$a\$b$"), [(r"a\$b".to_string(), false)]);
+    }
+
+    #[test]
+    fn leaves_code_spans_and_fences_alone() {
+        assert!(found("Run `echo $HOME$` or ``a `$x$` b``").is_empty());
+        assert!(found("```sh\necho $x$\n```\n").is_empty());
+        assert!(found("~~~\n$$x$$\n~~~").is_empty());
+        // An unclosed fence runs to the end of the message.
+        assert!(found("```\n$x$").is_empty());
+        assert_eq!(found("```\n$a$\n```\n$b$"), [("b".to_string(), false)]);
+    }
+
+    #[test]
+    fn unterminated_display_math_stays_text() {
+        let source = "$$x + y and more";
+        let (out, math) = extract_math(source);
+        assert!(math.is_empty());
+        assert_eq!(out, source);
+    }
+
+    #[test]
+    fn inline_math_stays_within_a_paragraph() {
+        assert!(found("$a\n\nb$").is_empty());
+        assert_eq!(found("$a\nb$"), [("a\nb".to_string(), false)]);
+    }
+
+    #[test]
+    fn placeholders_round_trip() {
+        let source = r"Area $\pi r^2$ and $$E = mc^2$$ done";
+        let (out, math) = extract_math(source);
+        assert!(has_placeholders(&out));
+        assert!(!out.contains('$'));
+        assert_eq!(restore_placeholders(&out, &math), source);
+        let pieces = split_placeholders(&out, &math);
+        assert_eq!(pieces.len(), 5);
+        assert_eq!(pieces[1], Piece::Math(&math[0]));
+        assert_eq!(pieces[4], Piece::Text(" done"));
+    }
+}
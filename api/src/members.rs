@@ -0,0 +1,279 @@
+//! Conversations shared with other users, who join as editors or viewers.
+//!
+//! Presence and typing indicators go out on the conversation's broadcast
+//! channel next to its changes, but are not part of the change log.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use crate::ConversationId;
+
+#[cfg(feature = "server")]
+use crate::{session, Conversation, ConversationEvent, UserEvent};
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use tokio::sync::watch;
+
+/// What a member may do in a conversation, from least to most.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+    Serialize,
+    Deserialize,
+    Display,
+    EnumString,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum Role {
+    /// Reads the conversation.
+    Viewer,
+    /// Also writes messages.
+    Editor,
+    /// Also manages members, sharing and the conversation itself.
+    #[default]
+    Owner,
+}
+
+impl Role {
+    /// Roles that can be given to a new member.
+    pub const INVITABLE: [Role; 2] = [Role::Editor, Role::Viewer];
+}
+
+/// Someone with access to a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Someone who has a conversation open right now.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Participant {
+    pub name: String,
+    pub typing: bool,
+}
+
+/// A caller with the conversation open, possibly in several tabs.
+#[cfg(feature = "server")]
+pub(crate) struct Presence {
+    name: String,
+    streams: usize,
+    typing: bool,
+    /// Set when the caller loses access, which ends their open streams.
+    removed: watch::Sender<bool>,
+}
+
+/// Presence keyed by [`session::Caller::key`].
+#[cfg(feature = "server")]
+pub(crate) type PresenceMap = HashMap<String, Presence>;
+
+#[cfg(feature = "server")]
+impl Conversation {
+    fn broadcast_presence(&self) {
+        let mut participants: Vec<Participant> = self
+            .present
+            .values()
+            .map(|p| Participant {
+                name: p.name.clone(),
+                typing: p.typing,
+            })
+            .collect();
+        participants.sort_by(|a, b| a.name.cmp(&b.name));
+        let _ = self.tx.send(ConversationEvent::Presence(participants));
+    }
+
+    /// Count another open stream for the caller. The stream should end once the returned
+    /// receiver changes or closes, when the caller is removed from the conversation.
+    pub(crate) fn join(&mut self, caller: &session::Caller) -> watch::Receiver<bool> {
+        let presence = self
+            .present
+            .entry(caller.key())
+            .or_insert_with(|| Presence {
+                name: caller.display_name(),
+                streams: 0,
+                typing: false,
+                removed: watch::channel(false).0,
+            });
+        presence.streams += 1;
+        let removed = presence.removed.subscribe();
+        self.broadcast_presence();
+        removed
+    }
+
+    /// End the open streams of a caller who no longer has access.
+    fn kick(&mut self, key: &str) {
+        if let Some(presence) = self.present.remove(key) {
+            let _ = presence.removed.send(true);
+            self.broadcast_presence();
+        }
+    }
+
+    /// Count one stream fewer for the caller, unless it was opened before they were
+    /// removed, which already took them out.
+    fn leave(&mut self, key: &str, joined: &watch::Receiver<bool>) {
+        let current = |p: &&mut Presence| p.removed.subscribe().same_channel(joined);
+        if let Some(presence) = self.present.get_mut(key).filter(current) {
+            presence.streams -= 1;
+            if presence.streams == 0 {
+                self.present.remove(key);
+            }
+            self.broadcast_presence();
+        }
+    }
+}
+
+/// Marks the caller as gone when their message stream is dropped.
+#[cfg(feature = "server")]
+pub(crate) struct PresenceGuard {
+    pub(crate) conv_id: ConversationId,
+    pub(crate) key: String,
+    /// What [`Conversation::join`] returned for the stream.
+    pub(crate) joined: watch::Receiver<bool>,
+}
+
+#[cfg(feature = "server")]
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        let conv_id = self.conv_id;
+        let key = std::mem::take(&mut self.key);
+        let joined = self.joined.clone();
+        tokio::spawn(async move {
+            if let Some(conv) = crate::CHAT_HISTORY.write().await.get_mut(&conv_id) {
+                conv.leave(&key, &joined);
+            }
+        });
+    }
+}
+
+/// Everyone with access to a conversation, the owner first.
+#[server(ListMembers)]
+pub async fn list_members(
+    session: String,
+    conv_id: ConversationId,
+) -> Result<Vec<Member>, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let history = crate::CHAT_HISTORY.read().await;
+    let conv = crate::accessible_conversation(&history, &key, conv_id, Role::Viewer)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    let mut members: Vec<Member> = conv
+        .members
+        .iter()
+        .map(|(key, role)| Member {
+            name: session::name_for_key(key),
+            role: *role,
+        })
+        .collect();
+    members.sort_by(|a, b| b.role.cmp(&a.role).then(a.name.cmp(&b.name)));
+    members.insert(
+        0,
+        Member {
+            name: session::name_for_key(&conv.owner),
+            role: Role::Owner,
+        },
+    );
+    Ok(members)
+}
+
+/// Give a registered user access to one of the caller's conversations, or change their role.
+#[server(AddMember)]
+pub async fn add_member(
+    session: String,
+    conv_id: ConversationId,
+    username: String,
+    role: Role,
+) -> Result<(), ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    if role == Role::Owner {
+        return Err(ServerFnError::new("A conversation has one owner"));
+    }
+    let username = username.trim().to_string();
+    if !crate::USERS.read().await.contains_key(&username) {
+        return Err(ServerFnError::new(format!("No user named {username}")));
+    }
+    let key = session::Caller::User(username).key();
+    if key == owner {
+        return Err(ServerFnError::new("You already own this conversation"));
+    }
+    let mut history = crate::CHAT_HISTORY.write().await;
+    let conv = crate::owned_conversation_mut(&mut history, &owner, conv_id)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    let event = match conv.members.insert(key.clone(), role) {
+        Some(_) => UserEvent::ConversationUpdated(conv.summary(conv_id, &key)),
+        None => UserEvent::ConversationCreated(conv.summary(conv_id, &key)),
+    };
+    crate::events::publish(&key, event).await;
+    Ok(())
+}
+
+/// Take a member out of a conversation. Owners can remove anyone and members can leave.
+/// The member's open streams of the conversation end.
+#[server(RemoveMember)]
+pub async fn remove_member(
+    session: String,
+    conv_id: ConversationId,
+    username: String,
+) -> Result<(), ServerFnError> {
+    let caller = session::caller(&session).await?.key();
+    let key = session::Caller::User(username).key();
+    let mut history = crate::CHAT_HISTORY.write().await;
+    let conv = crate::accessible_conversation_mut(&mut history, &caller, conv_id, Role::Viewer)
+        .filter(|c| c.owner == caller || key == caller)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    if conv.members.remove(&key).is_some() {
+        conv.kick(&key);
+        crate::events::publish(&key, UserEvent::ConversationDeleted(conv_id)).await;
+    }
+    Ok(())
+}
+
+/// Tell the others in a conversation whether the caller is typing.
+#[server(SetTyping)]
+pub async fn set_typing(
+    session: String,
+    conv_id: ConversationId,
+    typing: bool,
+) -> Result<(), ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let mut history = crate::CHAT_HISTORY.write().await;
+    let conv = crate::accessible_conversation_mut(&mut history, &key, conv_id, Role::Editor)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    if let Some(presence) = conv.present.get_mut(&key).filter(|p| p.typing != typing) {
+        presence.typing = typing;
+        conv.broadcast_presence();
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_a_member_ends_their_streams() {
+        let mut conv = Conversation::new("user:owner".to_string());
+        let member = session::Caller::User("member".to_string());
+        conv.members.insert(member.key(), Role::Editor);
+        let first = conv.join(&member);
+        let second = conv.join(&member);
+        let owner = conv.join(&session::Caller::User("owner".to_string()));
+
+        conv.kick(&member.key());
+        assert!(*first.borrow() && *second.borrow());
+        assert!(!conv.present.contains_key(&member.key()));
+        assert!(!owner.has_changed().unwrap());
+
+        // A stream from before the removal closing does not count against a new one.
+        let rejoined = conv.join(&member);
+        conv.leave(&member.key(), &first);
+        assert_eq!(conv.present[&member.key()].streams, 1);
+        conv.leave(&member.key(), &rejoined);
+        assert!(!conv.present.contains_key(&member.key()));
+    }
+}
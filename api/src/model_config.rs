@@ -1,10 +1,14 @@
-use std::{fs, path::Path};
-
 use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 
+use crate::generation::GenerationParams;
+
+/// The model registry, embedded so it is available on both client and server.
+const MODELS_JSON: &str = include_str!("../models.json");
+
 /// Represents different AI model providers
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
 pub enum Provider {
     #[strum(serialize = "openai")]
     OpenAI,
@@ -23,6 +27,7 @@ pub enum Provider {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
 pub enum Company {
     #[strum(serialize = "openai")]
     OpenAI,
@@ -40,24 +45,54 @@ pub enum Company {
 
 /// Represents the capabilities of a model
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
 pub struct Capabilities {
     pub text: bool,
+    #[serde(alias = "image_gen")]
     pub image_generation: bool,
+    #[serde(alias = "image_analysis")]
     pub image_understanding: bool,
     pub web_search: bool,
+    #[serde(alias = "pdf_analysis")]
     pub file_upload: bool,
     pub function_calling: bool,
+    /// Whether the model thinks before answering and accepts reasoning parameters.
+    pub reasoning: bool,
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct Pricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl Pricing {
+    /// Cost in USD of a request with the given token counts.
+    pub fn cost(&self, prompt_tokens: u32, completion_tokens: u32) -> f64 {
+        (f64::from(prompt_tokens) * self.prompt + f64::from(completion_tokens) * self.completion)
+            / 1_000_000.0
+    }
 }
 
 /// Configuration for an AI model
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ModelConfig {
+    #[serde(alias = "model_name")]
     pub name: String,
     pub provider: Provider,
     pub company: Company,
     pub max_tokens: usize,
+    #[serde(flatten)]
     pub capabilities: Capabilities,
+    #[serde(default)]
     pub description: String,
+    /// Generation parameters used when a request leaves them unset.
+    #[serde(default)]
+    pub defaults: GenerationParams,
+    /// `None` for models without a known price; their usage is still counted.
+    #[serde(default)]
+    pub pricing: Option<Pricing>,
 }
 
 impl Default for ModelConfig {
@@ -69,19 +104,27 @@ impl Default for ModelConfig {
             max_tokens: 128000,
             capabilities: Capabilities::default(),
             description: "OpenAI's most advanced model".to_string(),
+            defaults: GenerationParams::default(),
+            pricing: None,
         }
     }
 }
 
 impl ModelConfig {
-    // Load models from a JSON file
+    // Load models from the embedded registry
     pub fn load_models() -> Result<Vec<Self>, Box<dyn std::error::Error>> {
-        let json_path = Path::new("models.json");
-        let json_content = fs::read_to_string(json_path)?;
-        let models: Vec<ModelConfig> = serde_json::from_str(&json_content)?;
+        let models: Vec<ModelConfig> = serde_json::from_str(MODELS_JSON)?;
         Ok(models)
     }
 
+    /// The registry's model called `name`, if there is one.
+    pub fn find(name: &str) -> Option<Self> {
+        Self::load_models()
+            .ok()?
+            .into_iter()
+            .find(|m| m.name == name)
+    }
+
     // Get the default model (first one in the list)
     pub fn default_model() -> Self {
         Self::load_models()
@@ -0,0 +1,288 @@
+//! Operator-provided fallback keys and the limits that protect them.
+//!
+//! When a user has not stored their own key, requests use the operator's key
+//! from `<PROVIDER>_API_KEY` (e.g. `OPENAI_API_KEY`). Every caller is rate
+//! limited; daily token quotas only apply to requests paid for by the operator.
+//! Each anonymous session has its own limits, and since sessions cost nothing to
+//! create, all of them together are also held to a shared cap. Limits can be tuned
+//! with `USER_REQUESTS_PER_MINUTE`, `USER_DAILY_TOKENS`, `ANON_REQUESTS_PER_MINUTE`,
+//! `ANON_DAILY_TOKENS`, `ANON_TOTAL_REQUESTS_PER_MINUTE` and `ANON_TOTAL_DAILY_TOKENS`.
+//!
+//! Work the server does to show messages, such as highlighting code and drawing
+//! diagrams, has rate limits of its own, tuned with `RENDER_REQUESTS_PER_MINUTE` and
+//! `ANON_TOTAL_RENDER_REQUESTS_PER_MINUTE`, so rendering a long conversation does not
+//! use up the limits for chat.
+//!
+//! An operator-key request reserves the most tokens it can use before it is
+//! sent, so parallel requests cannot overshoot the quota, and settles the
+//! reservation with the tokens it actually used.
+
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::generation::ChatError;
+use crate::model_config::Provider;
+use crate::session::Caller;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits applied to one kind of caller.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    requests_per_minute: usize,
+    daily_tokens: u64,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+static USER_LIMITS: Lazy<Limits> = Lazy::new(|| Limits {
+    requests_per_minute: env_or("USER_REQUESTS_PER_MINUTE", 20),
+    daily_tokens: env_or("USER_DAILY_TOKENS", 200_000),
+});
+
+/// Per anonymous session.
+static ANON_LIMITS: Lazy<Limits> = Lazy::new(|| Limits {
+    requests_per_minute: env_or("ANON_REQUESTS_PER_MINUTE", 10),
+    daily_tokens: env_or("ANON_DAILY_TOKENS", 50_000),
+});
+
+/// Shared by every anonymous session, on top of their own limits.
+static ANON_TOTAL_LIMITS: Lazy<Limits> = Lazy::new(|| Limits {
+    requests_per_minute: env_or("ANON_TOTAL_REQUESTS_PER_MINUTE", 60),
+    daily_tokens: env_or("ANON_TOTAL_DAILY_TOKENS", 1_000_000),
+});
+
+/// Key of the state all anonymous sessions count against. Callers' own keys have a
+/// `user:` or `anon:` prefix, so it cannot clash with them.
+const ANON_TOTAL_KEY: &str = "anon";
+
+/// The quota states a caller counts against, by key, with their limits: their own, and
+/// for anonymous sessions also the one they all share.
+fn buckets(caller: &Caller) -> Vec<(String, Limits)> {
+    match caller {
+        Caller::User(_) => vec![(caller.key(), *USER_LIMITS)],
+        Caller::Anonymous(_) => vec![
+            (caller.key(), *ANON_LIMITS),
+            (ANON_TOTAL_KEY.to_string(), *ANON_TOTAL_LIMITS),
+        ],
+    }
+}
+
+/// Rendering requests per minute, per caller.
+static RENDER_LIMITS: Lazy<Limits> = Lazy::new(|| Limits {
+    requests_per_minute: env_or("RENDER_REQUESTS_PER_MINUTE", 120),
+    daily_tokens: 0,
+});
+
+/// Rendering requests per minute, shared by every anonymous session.
+static ANON_TOTAL_RENDER_LIMITS: Lazy<Limits> = Lazy::new(|| Limits {
+    requests_per_minute: env_or("ANON_TOTAL_RENDER_REQUESTS_PER_MINUTE", 600),
+    daily_tokens: 0,
+});
+
+/// The states a caller's rendering requests count against, like [`buckets`] for chat.
+fn render_buckets(caller: &Caller) -> Vec<(String, Limits)> {
+    let mut buckets = vec![(format!("render:{}", caller.key()), *RENDER_LIMITS)];
+    if let Caller::Anonymous(_) = caller {
+        buckets.push((
+            format!("render:{ANON_TOTAL_KEY}"),
+            *ANON_TOTAL_RENDER_LIMITS,
+        ));
+    }
+    buckets
+}
+
+#[derive(Default)]
+struct QuotaState {
+    recent_requests: VecDeque<Instant>,
+    /// UTC day `tokens_today` belongs to.
+    day: String,
+    tokens_today: u64,
+    /// Tokens held by operator-key requests that have not finished yet.
+    reserved: u64,
+}
+
+impl QuotaState {
+    /// Forget requests that have left the rate window.
+    fn prune(&mut self, now: Instant) {
+        while self
+            .recent_requests
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW)
+        {
+            self.recent_requests.pop_front();
+        }
+    }
+
+    /// Start counting tokens afresh when the UTC day changes.
+    fn roll_over(&mut self) {
+        let day = today();
+        if self.day != day {
+            self.day = day;
+            self.tokens_today = 0;
+        }
+    }
+}
+
+type QuotaStates = HashMap<String, QuotaState>;
+
+/// In-memory quota state keyed as in [`buckets`].
+static QUOTAS: Lazy<Arc<RwLock<QuotaStates>>> = Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// The operator's key for `provider`, if one is configured.
+pub(crate) fn operator_key(provider: Provider) -> Option<String> {
+    let name = format!("{}_API_KEY", provider.to_string().to_uppercase());
+    std::env::var(name).ok().filter(|k| !k.is_empty())
+}
+
+/// Tokens held against daily quotas while an operator-key request runs. Pass it to
+/// [`settle`] once the request is over, whether or not it succeeded.
+#[must_use]
+pub(crate) struct Reservation {
+    keys: Vec<String>,
+    tokens: u64,
+}
+
+/// Count a request against the caller's rate limits. When the request will use an
+/// operator key, also reserve `tokens` of the daily quotas, failing if they do not fit.
+/// Nothing is counted unless every limit allows the request.
+pub(crate) async fn check(
+    caller: &Caller,
+    operator_key: bool,
+    tokens: u64,
+) -> Result<Option<Reservation>, ChatError> {
+    count(buckets(caller), operator_key, tokens).await
+}
+
+/// Count a request to highlight code or draw a diagram against the caller's rendering
+/// rate limits.
+pub(crate) async fn check_rendering(caller: &Caller) -> Result<(), ChatError> {
+    count(render_buckets(caller), false, 0).await.map(|_| ())
+}
+
+/// Count a request against every one of `buckets`, as [`check`] describes.
+async fn count(
+    buckets: Vec<(String, Limits)>,
+    operator_key: bool,
+    tokens: u64,
+) -> Result<Option<Reservation>, ChatError> {
+    let mut quotas = QUOTAS.write().await;
+    let now = Instant::now();
+    for (key, limits) in &buckets {
+        let state = quotas.entry(key.clone()).or_default();
+        state.prune(now);
+        if state.recent_requests.len() >= limits.requests_per_minute {
+            let oldest = state.recent_requests.front().copied().unwrap_or(now);
+            let retry_after = RATE_WINDOW.saturating_sub(now.duration_since(oldest));
+            return Err(ChatError::RateLimited {
+                retry_after_secs: retry_after.as_secs().max(1),
+            });
+        }
+        if operator_key {
+            state.roll_over();
+            if state.tokens_today + state.reserved + tokens > limits.daily_tokens {
+                return Err(ChatError::QuotaExceeded {
+                    used: state.tokens_today + state.reserved,
+                    limit: limits.daily_tokens,
+                });
+            }
+        }
+    }
+
+    for (key, _) in &buckets {
+        let state = quotas.entry(key.clone()).or_default();
+        state.recent_requests.push_back(now);
+        if operator_key {
+            state.reserved += tokens;
+        }
+    }
+    Ok(operator_key.then(|| Reservation {
+        keys: buckets.into_iter().map(|(key, _)| key).collect(),
+        tokens,
+    }))
+}
+
+/// Release a reservation and charge the tokens the request actually used.
+pub(crate) async fn settle(reservation: Reservation, used: u64) {
+    let mut quotas = QUOTAS.write().await;
+    for key in reservation.keys {
+        let state = quotas.entry(key).or_default();
+        state.reserved = state.reserved.saturating_sub(reservation.tokens);
+        state.roll_over();
+        state.tokens_today += used;
+    }
+}
+
+/// Rough token count of `text`, for reserving quota before the provider has counted it.
+pub(crate) fn estimate_tokens(text: &str) -> u64 {
+    text.len().div_ceil(4) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anonymous(token: &str) -> Caller {
+        Caller::Anonymous(format!("quota-test-{token}"))
+    }
+
+    #[tokio::test]
+    async fn limits_anonymous_sessions_separately_and_together() {
+        let first = anonymous("first");
+        for _ in 0..ANON_LIMITS.requests_per_minute {
+            check(&first, false, 0).await.unwrap();
+        }
+        assert!(matches!(
+            check(&first, false, 0).await,
+            Err(ChatError::RateLimited { .. })
+        ));
+        // Another session is not held back by the first one's limit.
+        check(&anonymous("second"), false, 0).await.unwrap();
+
+        // Until all of them together reach the shared cap.
+        let sessions = ANON_TOTAL_LIMITS
+            .requests_per_minute
+            .div_ceil(ANON_LIMITS.requests_per_minute);
+        for i in 0..sessions {
+            let caller = anonymous(&format!("many-{i}"));
+            while check(&caller, false, 0).await.is_ok() {}
+        }
+        assert!(check(&anonymous("last"), false, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn limits_rendering_apart_from_chat() {
+        let caller = Caller::User("quota-render-test".to_string());
+        for _ in 0..RENDER_LIMITS.requests_per_minute {
+            check_rendering(&caller).await.unwrap();
+        }
+        assert!(check_rendering(&caller).await.is_err());
+        check(&caller, false, 0).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn releases_reserved_tokens_when_settled() {
+        let caller = Caller::User("quota-test".to_string());
+        let limit = USER_LIMITS.daily_tokens;
+        let reservation = check(&caller, true, limit).await.unwrap().unwrap();
+        assert!(matches!(
+            check(&caller, true, 1).await,
+            Err(ChatError::QuotaExceeded { .. })
+        ));
+        settle(reservation, 10).await;
+        let reservation = check(&caller, true, limit - 10).await.unwrap();
+        settle(reservation.unwrap(), 0).await;
+    }
+}
@@ -0,0 +1,68 @@
+//! Server-side rendering of message text to standalone HTML.
+//!
+//! Everything is rendered up front so the output needs no scripts: code is
+//! highlighted into classes styled by [`crate::highlight::light_css`], Graphviz
+//! diagrams are drawn as SVG and math becomes MathML.
+use crate::graphviz::graphviz_figure;
+use crate::highlight::{highlight_code, Diagram};
+use crate::markdown::render_markdown;
+
+/// Escape text for use in HTML content or attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark::escape::escape_html(&mut escaped, text).ok();
+    escaped
+}
+
+/// Render a TeX expression to MathML, or show the source if it does not parse.
+pub(crate) fn render_math(expr: &str, display: bool) -> String {
+    let opts = katex::Opts::builder()
+        .display_mode(display)
+        .output_type(katex::OutputType::Mathml)
+        .build();
+    opts.ok()
+        .and_then(|opts| katex::render_with_opts(expr, &opts).ok())
+        .unwrap_or_else(|| format!("<code>{}</code>", escape_html(expr)))
+}
+
+/// Render a message the way the chat shows it: Markdown with TeX math in `$`, `$$`,
+/// `\(` and `\[` delimiters. Raw HTML in the source is shown as text.
+pub(crate) fn message_to_html(text: &str) -> String {
+    render_markdown(
+        text,
+        |m| render_math(&m.tex, m.display),
+        |code, lang| {
+            match Diagram::from_fence(lang) {
+                Some(Diagram::Graphviz) => graphviz_figure(code),
+                _ => None,
+            }
+            .unwrap_or_else(|| highlight_code(code, lang))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_raw_html_as_text() {
+        let html = message_to_html(concat!(
+            "<button data-action=download data-filename=x.html>Download</button>\n\n",
+            "<div class=\"fixed inset-0 z-50\" id=\"msg-0\">cover</div>",
+        ));
+        assert!(!html.contains("<button"), "{html}");
+        assert!(!html.contains("<div"), "{html}");
+        assert!(html.contains("&lt;button"), "{html}");
+    }
+
+    #[test]
+    fn keeps_rendered_code_and_math() {
+        let html = message_to_html("$x^2$\n\n```rust\nfn main() {}\n```");
+        assert!(html.contains("<math"), "{html}");
+        assert!(
+            html.contains(r#"<pre class="hl-code"><code class="language-rust">"#),
+            "{html}"
+        );
+    }
+}
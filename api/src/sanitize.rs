@@ -0,0 +1,391 @@
+//! Cleaning the HTML that rendered messages turn into.
+//!
+//! Message text comes from models and, in shared conversations, from other people.
+//! The HTML Markdown makes of it goes through a strict allowlist before it is put
+//! into the page or an exported file: no classes, ids, buttons or data attributes,
+//! so a message cannot pass for the app's own markup, and nothing that runs code.
+//! The HTML the app makes itself, such as code block toolbars and formulas, is set
+//! aside in a [`TrustedHtml`] while the rest is cleaned and put back afterwards.
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+
+/// MathML elements KaTeX produces, with the attributes it sets on them.
+const MATHML: &[(&str, &[&str])] = &[
+    ("math", &["display", "xmlns"]),
+    ("semantics", &[]),
+    ("annotation", &["encoding"]),
+    ("mrow", &[]),
+    ("mi", &["mathvariant"]),
+    ("mn", &["mathvariant"]),
+    (
+        "mo",
+        &[
+            "fence",
+            "separator",
+            "stretchy",
+            "lspace",
+            "rspace",
+            "minsize",
+            "maxsize",
+            "movablelimits",
+        ],
+    ),
+    ("ms", &[]),
+    ("mtext", &["mathvariant"]),
+    ("mspace", &["width", "height", "depth"]),
+    ("msup", &[]),
+    ("msub", &[]),
+    ("msubsup", &[]),
+    ("mfrac", &["linethickness"]),
+    ("msqrt", &[]),
+    ("mroot", &[]),
+    ("mover", &["accent"]),
+    ("munder", &["accentunder"]),
+    ("munderover", &[]),
+    (
+        "mtable",
+        &[
+            "columnalign",
+            "rowspacing",
+            "columnspacing",
+            "columnlines",
+            "rowlines",
+        ],
+    ),
+    ("mtr", &[]),
+    ("mtd", &["columnalign"]),
+    (
+        "mstyle",
+        &["displaystyle", "scriptlevel", "mathcolor", "mathsize"],
+    ),
+    (
+        "mpadded",
+        &["width", "height", "depth", "lspace", "voffset"],
+    ),
+    ("mphantom", &[]),
+    ("menclose", &["notation"]),
+];
+
+/// SVG KaTeX draws stretchy arrows, braces and square roots with.
+const SVG: &[(&str, &[&str])] = &[
+    (
+        "svg",
+        &["width", "height", "viewBox", "preserveAspectRatio", "xmlns"],
+    ),
+    ("path", &["d"]),
+    ("line", &["x1", "y1", "x2", "y2", "stroke-width"]),
+];
+
+/// Inline styles KaTeX uses for layout. Positioning is left out so a formula
+/// cannot cover the rest of the page.
+const STYLE_PROPERTIES: &[&str] = &[
+    "color",
+    "background-color",
+    "height",
+    "width",
+    "min-width",
+    "max-width",
+    "vertical-align",
+    "top",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "margin-bottom",
+    "padding-left",
+    "border-bottom-width",
+    "border-top-width",
+];
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::default();
+    builder
+        .filter_style_properties(["text-align"].into())
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("th", ["style"])
+        .add_tag_attributes("td", ["style"]);
+    // Task list checkboxes are the only inputs Markdown makes, and they are read-only.
+    builder.attribute_filter(|element, attribute, value| match (element, attribute) {
+        ("input", "type") if value != "checkbox" => None,
+        _ => Some(value.into()),
+    });
+    builder.set_tag_attribute_value("input", "disabled", "");
+    builder
+});
+
+static MATH_SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .add_tags(["span"])
+        .add_generic_attributes(["class", "style", "aria-hidden"])
+        .filter_style_properties(STYLE_PROPERTIES.iter().copied().collect());
+    for (tag, attributes) in MATHML.iter().chain(SVG) {
+        builder
+            .add_tags([*tag])
+            .add_tag_attributes(tag, attributes.iter().copied());
+    }
+    builder
+});
+
+/// Strip everything from `html` that is not on the allowlist of tags, attributes,
+/// styles and URL schemes that Markdown uses.
+pub fn sanitize_html(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+/// Strip everything from KaTeX output that is not MathML, SVG or the classes and
+/// layout styles of its spans.
+pub fn sanitize_math_html(html: &str) -> String {
+    MATH_SANITIZER.clean(html).to_string()
+}
+
+// Private-use characters around the index of a trusted fragment. Message text has
+// them removed, so every placeholder in the output is one the renderer put there.
+const OPEN: char = '\u{E002}';
+const CLOSE: char = '\u{E003}';
+
+/// Fragments of HTML the renderer made itself, kept out of [`sanitize_html`].
+#[derive(Default)]
+pub struct TrustedHtml(Vec<String>);
+
+impl TrustedHtml {
+    /// `text` without the characters placeholders are made of.
+    pub fn strip_markers(text: &str) -> Cow<'_, str> {
+        if text.contains([OPEN, CLOSE]) {
+            Cow::Owned(text.replace([OPEN, CLOSE], ""))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    /// Set `html` aside and return the placeholder to render in its place.
+    pub fn insert(&mut self, html: String) -> String {
+        self.0.push(html);
+        format!("{OPEN}{}{CLOSE}", self.0.len() - 1)
+    }
+
+    /// Sanitize `html`, then put the fragments set aside back in place of their
+    /// placeholders. Only placeholders in element content are replaced; ones that
+    /// ended up inside a tag, such as in an attribute value, are dropped.
+    pub fn sanitize(&self, html: &str) -> String {
+        let clean = sanitize_html(html);
+        let mut output = String::with_capacity(clean.len());
+        // The sanitizer escapes `<` in text and `"` in double-quoted attribute values.
+        let (mut in_tag, mut in_value) = (false, false);
+        let mut rest = clean.as_str();
+        while let Some(c) = rest.chars().next() {
+            if c == OPEN {
+                let (index, after) = rest[OPEN.len_utf8()..]
+                    .split_once(CLOSE)
+                    .unwrap_or(("", ""));
+                let fragment = index.parse::<usize>().ok().and_then(|i| self.0.get(i));
+                if let Some(fragment) = fragment.filter(|_| !in_tag) {
+                    output.push_str(fragment);
+                }
+                rest = after;
+                continue;
+            }
+            match c {
+                '<' if !in_tag => in_tag = true,
+                '"' if in_tag => in_value = !in_value,
+                '>' if in_tag && !in_value => in_tag = false,
+                _ => {}
+            }
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puts_trusted_html_back_only_in_element_content() {
+        let mut trusted = TrustedHtml::default();
+        let placeholder = trusted.insert("<b>\"x\"</b>".to_string());
+        let html = trusted.sanitize(&format!(
+            "<p title=\"a > {placeholder}\">{placeholder}</p><img src=\"u\" alt=\"{placeholder}\">"
+        ));
+        assert_eq!(
+            html,
+            "<p title=\"a &gt; \"><b>\"x\"</b></p><img src=\"u\" alt=\"\">"
+        );
+    }
+
+    #[test]
+    fn removes_script_elements_and_their_content() {
+        let html = sanitize_html("<p>hi</p><script>alert(1)</script>");
+        assert_eq!(html, "<p>hi</p>");
+    }
+
+    #[test]
+    fn removes_event_handlers() {
+        let html =
+            sanitize_html(r#"<img src="x" onerror="alert(1)"><svg onload="alert(1)"></svg>"#);
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(!html.contains("onload"), "{html}");
+        assert!(!html.contains("alert"), "{html}");
+    }
+
+    #[test]
+    fn removes_script_urls() {
+        for href in [
+            "javascript:alert(1)",
+            "JaVaScRiPt:alert(1)",
+            " javascript:alert(1)",
+            "java&#x09;script:alert(1)",
+            "vbscript:msgbox(1)",
+            "data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==",
+        ] {
+            let html = sanitize_html(&format!(r#"<a href="{href}">x</a>"#));
+            assert!(!html.contains("href"), "{href} kept in {html}");
+        }
+        let html = sanitize_html(r#"<img src="javascript:alert(1)">"#);
+        assert!(!html.contains("javascript"), "{html}");
+    }
+
+    #[test]
+    fn removes_embedding_and_form_elements() {
+        let html = sanitize_html(concat!(
+            r#"<iframe src="https://example.com"></iframe>"#,
+            r#"<object data="x.swf"></object><embed src="x.swf">"#,
+            r#"<form action="https://example.com"><button type="submit" formaction="https://example.com">Go</button></form>"#,
+            r#"<meta http-equiv="refresh" content="0;url=https://example.com">"#,
+            r#"<base href="https://example.com/">"#,
+            r#"<link rel="stylesheet" href="https://example.com/x.css">"#,
+            "<style>body { display: none }</style>",
+        ));
+        for tag in [
+            "iframe", "object", "embed", "form", "meta", "base", "link", "style",
+        ] {
+            assert!(!html.contains(&format!("<{tag}")), "<{tag}> kept in {html}");
+        }
+        assert!(!html.contains("display"), "{html}");
+        assert!(!html.contains("submit"), "{html}");
+        assert!(!html.contains("formaction"), "{html}");
+    }
+
+    #[test]
+    fn removes_svg_and_mathml_script_vectors() {
+        let html = concat!(
+            r#"<svg><script>alert(1)</script><a href="javascript:alert(1)">x</a></svg>"#,
+            r#"<svg><animate attributeName="href" to="javascript:alert(1)"/></svg>"#,
+            r#"<math><mtext><img src=x onerror=alert(1)></mtext></math>"#,
+            r#"<math href="javascript:alert(1)"><mi>x</mi></math>"#,
+        );
+        for clean in [sanitize_html(html), sanitize_math_html(html)] {
+            assert!(!clean.contains("alert"), "{clean}");
+            assert!(!clean.contains("<animate"), "{clean}");
+        }
+    }
+
+    #[test]
+    fn filters_styles() {
+        let html = sanitize_math_html(
+            r#"<span style="position: fixed; top: 0; color: red; background-image: url(javascript:alert(1))">x</span>"#,
+        );
+        assert!(!html.contains("position"), "{html}");
+        assert!(!html.contains("url("), "{html}");
+        assert!(html.contains("color:red"), "{html}");
+    }
+
+    #[test]
+    fn only_keeps_disabled_checkboxes() {
+        let html = sanitize_html(r#"<input type="text" value="x" onfocus="alert(1)" autofocus>"#);
+        assert!(!html.contains("text"), "{html}");
+        assert!(!html.contains("onfocus"), "{html}");
+        let html = sanitize_html(r#"<input type="checkbox" checked>"#);
+        assert!(html.contains(r#"type="checkbox""#), "{html}");
+        assert!(html.contains("disabled"), "{html}");
+    }
+
+    #[test]
+    fn adds_rel_to_links() {
+        let html = sanitize_html(r#"<a href="https://example.com" target="_top">x</a>"#);
+        assert_eq!(
+            html,
+            r#"<a href="https://example.com" rel="noopener noreferrer">x</a>"#
+        );
+    }
+
+    #[test]
+    fn keeps_markdown_output() {
+        let html = concat!(
+            "<h2>Title</h2>\n<p><strong>bold</strong> <em>it</em> <del>gone</del> ",
+            "<code>code</code> <a href=\"https://example.com\" rel=\"noopener noreferrer\">link</a></p>\n",
+            "<table><thead><tr><th>a</th></tr></thead><tbody><tr><td>1</td></tr></tbody></table>\n",
+            "<ul>\n<li><input disabled=\"\" type=\"checkbox\"> todo</li>\n</ul>\n",
+            "<table><thead><tr><th style=\"text-align:right\">n</th></tr></thead></table>\n",
+            "<pre><code>fn main() {}\n</code></pre>\n",
+        );
+        assert_eq!(sanitize_html(html), html);
+    }
+
+    #[test]
+    fn removes_app_markup() {
+        let html = sanitize_html(concat!(
+            r#"<button type="button" data-action="download" data-filename="x.html">Download</button>"#,
+            r#"<div class="code-block fixed inset-0 z-50" id="msg-0" data-kind="mermaid">x</div>"#,
+            r##"<sup id="fn"><a href="#msg-1">1</a></sup>"##,
+        ));
+        for dropped in ["<button", "data-", "class", "id=", "fixed"] {
+            assert!(!html.contains(dropped), "{dropped} kept in {html}");
+        }
+    }
+
+    #[test]
+    fn puts_trusted_html_back_after_sanitizing() {
+        let toolbar = r#"<div class="code-block"><button type="button" data-action="copy">Copy</button></div>"#;
+        let mut trusted = TrustedHtml::default();
+        let placeholder = trusted.insert(toolbar.to_string());
+        let html = trusted.sanitize(&format!(
+            "<p>before</p>{placeholder}<div class=\"fixed\">after</div>"
+        ));
+        assert_eq!(html, format!("<p>before</p>{toolbar}<div>after</div>"));
+    }
+
+    #[test]
+    fn message_text_cannot_forge_placeholders() {
+        let mut trusted = TrustedHtml::default();
+        let placeholder = trusted.insert("<button>Copy</button>".to_string());
+        let text = format!("<a href=\"{placeholder}\">x</a>");
+        let html = trusted.sanitize(&TrustedHtml::strip_markers(&text));
+        assert!(!html.contains("<button"), "{html}");
+    }
+
+    #[test]
+    fn keeps_katex_output() {
+        let html = concat!(
+            r#"<span class="katex"><span class="katex-mathml"><math xmlns="http://www.w3.org/1998/Math/MathML">"#,
+            r#"<semantics><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow>"#,
+            r#"<annotation encoding="application/x-tex">x^2</annotation></semantics></math></span>"#,
+            r#"<span class="katex-html" aria-hidden="true"><span class="base">"#,
+            r#"<span class="strut" style="height:0.8141em;"></span>"#,
+            r#"<span class="mord"><span class="mord mathnormal">x</span><span class="msupsub">"#,
+            r#"<span class="vlist" style="height:0.8141em;"><span style="top:-3.063em;margin-right:0.05em;">"#,
+            r#"<span class="pstrut" style="height:2.7em;"></span></span></span></span></span></span>"#,
+            r#"<span class="hide-tail" style="min-width:0.853em;height:1.08em;">"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="400em" height="1.08em" viewBox="0 0 400000 1080" "#,
+            r#"preserveAspectRatio="xMinYMin slice"><path d="M95,702 c-2.7,0,-7.17,-2.7,-13.5,-8z"></path></svg>"#,
+            r#"</span></span></span>"#,
+        );
+        let clean = sanitize_math_html(html);
+        for kept in [
+            "<math",
+            "<msup><mi>x</mi><mn>2</mn></msup>",
+            r#"encoding="application/x-tex""#,
+            r#"class="katex-html""#,
+            r#"aria-hidden="true""#,
+            "height:0.8141em",
+            "top:-3.063em",
+            r#"viewBox="0 0 400000 1080""#,
+            r#"<path d="M95,702"#,
+        ] {
+            assert!(clean.contains(kept), "{kept} dropped from {clean}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Full-text search over message content.
+//!
+//! Messages are indexed as they are stored in an in-memory inverted index from
+//! lowercased words to message ids, and taken out again when they are edited or
+//! deleted. Query words match by prefix, every
+//! word must match, and results come back as snippets split into highlighted
+//! and plain parts so the client never has to render HTML.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ConversationId;
+
+#[cfg(feature = "server")]
+use crate::{session, MessageId, Role};
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+/// Most results returned for one query.
+#[cfg(feature = "server")]
+const MAX_HITS: usize = 50;
+/// Characters of context shown before the first match.
+#[cfg(feature = "server")]
+const SNIPPET_CONTEXT: usize = 40;
+/// Characters in a snippet.
+#[cfg(feature = "server")]
+const SNIPPET_LEN: usize = 160;
+
+/// A piece of a snippet; `highlight` marks text that matched the query.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+/// A message that matched a search.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+    pub conv_id: ConversationId,
+    pub conv_title: Option<String>,
+    /// Position of the message within its conversation.
+    pub message_index: usize,
+    pub snippet: Vec<SnippetPart>,
+}
+
+#[cfg(feature = "server")]
+type Posting = (ConversationId, MessageId);
+
+#[cfg(feature = "server")]
+type SearchIndex = BTreeMap<String, HashSet<Posting>>;
+
+/// Word to `(conversation id, message id)` postings.
+#[cfg(feature = "server")]
+static SEARCH_INDEX: Lazy<Arc<RwLock<SearchIndex>>> =
+    Lazy::new(|| Arc::new(RwLock::new(BTreeMap::new())));
+
+/// Split text into words, returning each word's byte range and lowercased form.
+#[cfg(feature = "server")]
+fn words(text: &str) -> impl Iterator<Item = (usize, usize, String)> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(move |w| {
+            let start = w.as_ptr() as usize - text.as_ptr() as usize;
+            (start, start + w.len(), w.to_lowercase())
+        })
+}
+
+/// Add a stored message's text to the index.
+#[cfg(feature = "server")]
+pub(crate) async fn index_message(conv_id: ConversationId, msg_id: MessageId, text: &str) {
+    let mut index = SEARCH_INDEX.write().await;
+    for (_, _, word) in words(text) {
+        index.entry(word).or_default().insert((conv_id, msg_id));
+    }
+}
+
+/// Take a message's postings out of the index. `text` is the text it was indexed with.
+#[cfg(feature = "server")]
+pub(crate) async fn unindex_message(conv_id: ConversationId, msg_id: MessageId, text: &str) {
+    let mut index = SEARCH_INDEX.write().await;
+    for (_, _, word) in words(text) {
+        if let Some(postings) = index.get_mut(&word) {
+            postings.remove(&(conv_id, msg_id));
+            if postings.is_empty() {
+                index.remove(&word);
+            }
+        }
+    }
+}
+
+/// Take every posting of the given conversations out of the index.
+#[cfg(feature = "server")]
+pub(crate) async fn unindex_conversations(conv_ids: &HashSet<ConversationId>) {
+    if conv_ids.is_empty() {
+        return;
+    }
+    let mut index = SEARCH_INDEX.write().await;
+    index.retain(|_, postings| {
+        postings.retain(|(conv_id, _)| !conv_ids.contains(conv_id));
+        !postings.is_empty()
+    });
+}
+
+/// Messages containing a word starting with each of `terms`.
+#[cfg(feature = "server")]
+async fn candidates(terms: &[String]) -> HashSet<Posting> {
+    let index = SEARCH_INDEX.read().await;
+    let mut result: Option<HashSet<Posting>> = None;
+    for term in terms {
+        let matches: HashSet<Posting> = index
+            .range(term.clone()..)
+            .take_while(|(word, _)| word.starts_with(term.as_str()))
+            .flat_map(|(_, postings)| postings.iter().copied())
+            .collect();
+        result = Some(match result {
+            Some(found) => found.intersection(&matches).copied().collect(),
+            None => matches,
+        });
+    }
+    result.unwrap_or_default()
+}
+
+/// Cut a snippet around the first match, returning it with the number of matching words.
+#[cfg(feature = "server")]
+fn snippet(text: &str, terms: &[String]) -> Option<(usize, Vec<SnippetPart>)> {
+    let matches: Vec<(usize, usize)> = words(text)
+        .filter(|(_, _, word)| terms.iter().any(|t| word.starts_with(t.as_str())))
+        .map(|(start, end, _)| (start, end))
+        .collect();
+    let first = matches.first()?.0;
+    let begin = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let end = text[begin..]
+        .char_indices()
+        .nth(SNIPPET_LEN)
+        .map_or(text.len(), |(i, _)| begin + i);
+
+    let mut parts = Vec::new();
+    let mut push = |text: &str, highlight: bool| {
+        if !text.is_empty() {
+            parts.push(SnippetPart {
+                text: text.replace('\n', " "),
+                highlight,
+            });
+        }
+    };
+    if begin > 0 {
+        push("…", false);
+    }
+    let mut cursor = begin;
+    for &(start, stop) in matches.iter().filter(|(s, e)| *s >= begin && *e <= end) {
+        push(&text[cursor..start], false);
+        push(&text[start..stop], true);
+        cursor = stop;
+    }
+    push(&text[cursor..end], false);
+    if end < text.len() {
+        push("…", false);
+    }
+    Some((matches.len(), parts))
+}
+
+/// Search the text of every message in the caller's conversations. Results are ordered by
+/// number of matching words, then by the conversation's last activity.
+#[server(SearchMessages)]
+pub async fn search_messages(
+    session: String,
+    query: String,
+) -> Result<Vec<SearchHit>, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let terms: Vec<String> = words(&query).map(|(_, _, word)| word).collect();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let candidates = candidates(&terms).await;
+
+    let history = crate::CHAT_HISTORY.read().await;
+    let mut hits: Vec<(usize, chrono::DateTime<chrono::Utc>, SearchHit)> = candidates
+        .into_iter()
+        .filter_map(|(conv_id, msg_id)| {
+            let conv = crate::accessible_conversation(&history, &key, conv_id, Role::Viewer)?;
+            let message_index = conv.messages.iter().position(|m| m.id == msg_id)?;
+            let text = conv.messages[message_index].text.as_deref()?;
+            let (score, snippet) = snippet(text, &terms)?;
+            Some((
+                score,
+                conv.updated_at,
+                SearchHit {
+                    conv_id,
+                    conv_title: conv.title.clone(),
+                    message_index,
+                    snippet,
+                },
+            ))
+        })
+        .collect();
+    hits.sort_by(|(a_score, a_updated, a), (b_score, b_updated, b)| {
+        b_score
+            .cmp(a_score)
+            .then(b_updated.cmp(a_updated))
+            .then(b.message_index.cmp(&a.message_index))
+    });
+    Ok(hits
+        .into_iter()
+        .take(MAX_HITS)
+        .map(|(_, _, hit)| hit)
+        .collect())
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    async fn found(query: &str) -> HashSet<Posting> {
+        candidates(&words(query).map(|(_, _, word)| word).collect::<Vec<_>>()).await
+    }
+
+    #[tokio::test]
+    async fn edited_and_deleted_messages_leave_no_postings() {
+        let conv_id = ConversationId::new_v4();
+        let (first, second) = (MessageId::new_v4(), MessageId::new_v4());
+        index_message(conv_id, first, "zebrafish quagga").await;
+        index_message(conv_id, second, "zebrafish okapi").await;
+        assert_eq!(found("zebrafish").await.len(), 2);
+
+        unindex_message(conv_id, first, "zebrafish quagga").await;
+        index_message(conv_id, first, "narwhal").await;
+        assert!(found("quagga").await.is_empty());
+        assert_eq!(found("zebrafish").await, HashSet::from([(conv_id, second)]));
+        assert!(found("zebrafish quagga").await.is_empty());
+
+        unindex_conversations(&HashSet::from([conv_id])).await;
+        assert!(found("zebrafish").await.is_empty());
+        assert!(found("narwhal").await.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Session tokens that identify the caller of a server function.
+//!
+//! Every client holds a token. Anonymous tokens are handed out on first visit
+//! and replaced by a user-bound token on login.
+use dioxus::prelude::*;
+
+#[cfg(feature = "server")]
+use base64::Engine;
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use rand::RngCore;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+#[cfg(feature = "server")]
+type Sessions = HashMap<String, Option<String>>;
+
+/// In-memory map of session tokens to the logged-in username, if any.
+#[cfg(feature = "server")]
+static SESSIONS: Lazy<Arc<RwLock<Sessions>>> = Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+/// Who is calling a server function.
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Caller {
+    User(String),
+    Anonymous(String),
+}
+
+#[cfg(feature = "server")]
+impl Caller {
+    /// Stable key used to attribute data to this caller.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Caller::User(name) => format!("user:{name}"),
+            Caller::Anonymous(token) => format!("anon:{token}"),
+        }
+    }
+
+    /// Username of a logged-in caller.
+    pub(crate) fn username(&self) -> Option<String> {
+        match self {
+            Caller::User(name) => Some(name.clone()),
+            Caller::Anonymous(_) => None,
+        }
+    }
+
+    /// Name shown to the other members of a conversation.
+    pub(crate) fn display_name(&self) -> String {
+        self.username().unwrap_or_else(|| "Guest".to_string())
+    }
+}
+
+/// [`Caller::display_name`] of the caller with the given [`Caller::key`].
+#[cfg(feature = "server")]
+pub(crate) fn name_for_key(key: &str) -> String {
+    key.strip_prefix("user:").unwrap_or("Guest").to_string()
+}
+
+/// Generate an unguessable URL-safe token.
+#[cfg(feature = "server")]
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Resolve a session token to its caller.
+#[cfg(feature = "server")]
+pub(crate) async fn caller(session: &str) -> Result<Caller, ServerFnError> {
+    let sessions = SESSIONS.read().await;
+    match sessions.get(session) {
+        Some(Some(user)) => Ok(Caller::User(user.clone())),
+        Some(None) => Ok(Caller::Anonymous(session.to_string())),
+        None => Err(ServerFnError::new("Invalid session")),
+    }
+}
+
+/// Create a session bound to `username` and return its token.
+#[cfg(feature = "server")]
+pub(crate) async fn create_user_session(username: String) -> String {
+    let token = random_token();
+    SESSIONS.write().await.insert(token.clone(), Some(username));
+    token
+}
+
+/// Return `existing` if the server still knows it, otherwise start a new anonymous session.
+#[server(StartSession)]
+pub async fn start_session(existing: Option<String>) -> Result<String, ServerFnError> {
+    let mut sessions = SESSIONS.write().await;
+    if let Some(token) = existing {
+        if sessions.contains_key(&token) {
+            return Ok(token);
+        }
+    }
+    let token = random_token();
+    sessions.insert(token.clone(), None);
+    Ok(token)
+}
+
+/// Return the username bound to the session, or `None` for anonymous sessions.
+#[server(CurrentUser)]
+pub async fn current_user(session: String) -> Result<Option<String>, ServerFnError> {
+    Ok(match caller(&session).await? {
+        Caller::User(name) => Some(name),
+        Caller::Anonymous(_) => None,
+    })
+}
@@ -0,0 +1,201 @@
+//! Public read-only snapshots of conversations.
+//!
+//! Sharing copies the conversation's messages at that moment under an
+//! unguessable token, so later messages are not exposed. Links can expire and
+//! can be revoked by the session that created them.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ChatMessage;
+
+#[cfg(feature = "server")]
+use crate::session::{self, random_token};
+#[cfg(feature = "server")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+/// Longest a share link can be set to last, in days.
+pub const MAX_SHARE_DAYS: u32 = 365;
+
+/// A share link as shown to the person who created it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShareLink {
+    pub token: String,
+    /// UTC time the snapshot was taken.
+    pub created_at: String,
+    /// UTC time the link stops working, or `None` if it never expires.
+    pub expires_at: Option<String>,
+}
+
+/// A frozen copy of a conversation, as seen through a share link.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedConversation {
+    pub title: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub created_at: String,
+}
+
+#[cfg(feature = "server")]
+struct Share {
+    /// [`session::Caller::key`] of the creator, who may list and revoke the link.
+    owner: String,
+    conv_id: crate::ConversationId,
+    title: Option<String>,
+    messages: Vec<ChatMessage>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(feature = "server")]
+impl Share {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    fn link(&self, token: &str) -> ShareLink {
+        ShareLink {
+            token: token.to_string(),
+            created_at: format_time(self.created_at),
+            expires_at: self.expires_at.map(format_time),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+type Shares = HashMap<String, Share>;
+
+/// In-memory snapshots keyed by share token.
+#[cfg(feature = "server")]
+static SHARES: Lazy<Arc<RwLock<Shares>>> = Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+#[cfg(feature = "server")]
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// When a link created at `created_at` that lasts `days` expires, or `None` if it never
+/// does. Fails unless `days` is between 1 and [`MAX_SHARE_DAYS`].
+#[cfg(feature = "server")]
+fn expiry(
+    created_at: DateTime<Utc>,
+    days: Option<u32>,
+) -> Result<Option<DateTime<Utc>>, ServerFnError> {
+    let Some(days) = days else {
+        return Ok(None);
+    };
+    if !(1..=MAX_SHARE_DAYS).contains(&days) {
+        return Err(ServerFnError::new(format!(
+            "Links can last between 1 and {MAX_SHARE_DAYS} days"
+        )));
+    }
+    created_at
+        .checked_add_signed(chrono::Duration::days(days.into()))
+        .map(Some)
+        .ok_or_else(|| ServerFnError::new("Invalid expiry"))
+}
+
+/// Snapshot a conversation and return a link to it. The link expires after
+/// `expires_in_days`, at most [`MAX_SHARE_DAYS`], if given.
+#[server(ShareConversation)]
+pub async fn share_conversation(
+    session: String,
+    conv_id: crate::ConversationId,
+    expires_in_days: Option<u32>,
+) -> Result<ShareLink, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let created_at = Utc::now();
+    let expires_at = expiry(created_at, expires_in_days)?;
+    let (title, messages) =
+        crate::owned_conversation(&*crate::CHAT_HISTORY.read().await, &caller.key(), conv_id)
+            .map(|c| (c.title.clone(), c.messages.clone()))
+            .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    if messages.is_empty() {
+        return Err(ServerFnError::new("Nothing to share yet"));
+    }
+    let share = Share {
+        owner: caller.key(),
+        conv_id,
+        title,
+        messages,
+        created_at,
+        expires_at,
+    };
+    let token = random_token();
+    let link = share.link(&token);
+    SHARES.write().await.insert(token, share);
+    Ok(link)
+}
+
+/// Fetch the snapshot behind a share link. Anyone with the token can read it.
+#[server(GetSharedConversation)]
+pub async fn get_shared_conversation(token: String) -> Result<SharedConversation, ServerFnError> {
+    let mut shares = SHARES.write().await;
+    let now = Utc::now();
+    if shares.get(&token).is_some_and(|s| s.is_expired(now)) {
+        shares.remove(&token);
+    }
+    shares
+        .get(&token)
+        .map(|s| SharedConversation {
+            title: s.title.clone(),
+            messages: s.messages.clone(),
+            created_at: format_time(s.created_at),
+        })
+        .ok_or_else(|| ServerFnError::new("This link has expired or been revoked"))
+}
+
+/// Links the caller has created for a conversation that are still live.
+#[server(ListShares)]
+pub async fn list_shares(session: String, conv_id: crate::ConversationId) -> Result<Vec<ShareLink>, ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let now = Utc::now();
+    let mut shares = SHARES.write().await;
+    shares.retain(|_, s| !s.is_expired(now));
+    let mut links: Vec<(DateTime<Utc>, ShareLink)> = shares
+        .iter()
+        .filter(|(_, s)| s.owner == owner && s.conv_id == conv_id)
+        .map(|(token, s)| (s.created_at, s.link(token)))
+        .collect();
+    links.sort_by_key(|(created_at, _)| *created_at);
+    Ok(links.into_iter().map(|(_, link)| link).collect())
+}
+
+/// Disable a share link created by the caller.
+#[server(RevokeShare)]
+pub async fn revoke_share(session: String, token: String) -> Result<(), ServerFnError> {
+    let owner = session::caller(&session).await?.key();
+    let mut shares = SHARES.write().await;
+    match shares.get(&token) {
+        Some(share) if share.owner == owner => {
+            shares.remove(&token);
+            Ok(())
+        }
+        _ => Err(ServerFnError::new("Share link not found")),
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_within_the_allowed_range() {
+        let now = Utc::now();
+        assert_eq!(expiry(now, None).unwrap(), None);
+        assert_eq!(
+            expiry(now, Some(7)).unwrap(),
+            Some(now + chrono::Duration::days(7))
+        );
+        assert!(expiry(now, Some(MAX_SHARE_DAYS)).unwrap().is_some());
+        assert!(expiry(now, Some(0)).is_err());
+        assert!(expiry(now, Some(MAX_SHARE_DAYS + 1)).is_err());
+        assert!(expiry(now, Some(u32::MAX)).is_err());
+    }
+}
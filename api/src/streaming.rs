@@ -0,0 +1,261 @@
+//! Completions streamed from the provider as they are generated.
+//!
+//! The provider's server-sent events are turned into [`CompletionChunk`]s and sent on
+//! as JSON lines. Usage comes with the last events and is priced and charged once the
+//! stream ends, as for [`chat_completion`](crate::chat_completion). A stream that
+//! breaks off, or that the client leaves, is charged an estimate of what was generated.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::{StreamingText, TextStream};
+
+use crate::{ChatError, GenerationParams, MessageId, Usage};
+
+#[cfg(feature = "server")]
+use crate::model_config::Provider;
+#[cfg(feature = "server")]
+use crate::ModelConfig;
+#[cfg(feature = "server")]
+use crate::{quota, session};
+#[cfg(feature = "server")]
+use futures::StreamExt;
+#[cfg(feature = "server")]
+use tokio::sync::mpsc;
+#[cfg(feature = "server")]
+use tokio_stream::wrappers::ReceiverStream;
+
+/// What [`stream_completion`] delivers.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CompletionChunk {
+    /// More of the answer.
+    Text(String),
+    /// More of the model's thinking trace.
+    Reasoning(String),
+    /// The completion is finished. Nothing follows.
+    Done(Option<Usage>),
+    /// The request failed. Nothing follows.
+    Failed(ChatError),
+}
+
+/// Ask the model to answer `prompt` and stream the answer, one JSON [`CompletionChunk`]
+/// per line. Resolves `model`, checks and charges the caller and holds the usage for
+/// `reply_id` as [`chat_completion`](crate::chat_completion) does, but reports failures as
+/// [`CompletionChunk::Failed`].
+#[server(StreamCompletion, output = StreamingText)]
+pub async fn stream_completion(
+    session: String,
+    prompt: String,
+    model: String,
+    params: GenerationParams,
+    reply_id: MessageId,
+) -> Result<TextStream, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let result = match crate::registry_model(&model) {
+            Ok(model) => forward(&caller, &model, &params.or(&model.defaults), &prompt, &tx).await,
+            Err(e) => Err(e),
+        };
+        let last = match result {
+            Ok(usage) => {
+                if let Some(usage) = usage {
+                    crate::usage::hold_for_reply(&caller, reply_id, usage).await;
+                }
+                CompletionChunk::Done(usage)
+            }
+            Err(e) => CompletionChunk::Failed(e),
+        };
+        let _ = tx.send(last).await;
+    });
+    Ok(crate::json_lines(ReceiverStream::new(rx)))
+}
+
+/// Send the provider's answer to `tx` as it arrives and return its usage, priced.
+/// Stops early if the client goes away, charging for what was generated until then.
+#[cfg(feature = "server")]
+async fn forward(
+    caller: &session::Caller,
+    model: &ModelConfig,
+    params: &GenerationParams,
+    prompt: &str,
+    tx: &mpsc::Sender<CompletionChunk>,
+) -> Result<Option<Usage>, ChatError> {
+    params.validate(model)?;
+    let (api_key, operator_key) = crate::completion_key(caller, model).await?;
+    let tokens = crate::max_request_tokens(model, params, prompt);
+    let reservation = quota::check(caller, operator_key, tokens).await?;
+
+    let mut usage = None;
+    let mut generated = String::new();
+    let result = relay(
+        model,
+        params,
+        &api_key,
+        prompt,
+        tx,
+        &mut usage,
+        &mut generated,
+    )
+    .await;
+    let mut usage = usage_or_estimate(usage, prompt, &generated);
+    match &mut usage {
+        Some(usage) => crate::charge_usage(caller, model, usage, reservation).await,
+        None => {
+            if let Some(reservation) = reservation {
+                quota::settle(reservation, 0).await;
+            }
+        }
+    }
+    result.map(|()| usage)
+}
+
+/// The usage to charge: what the provider reported, with anything it did not get to
+/// report estimated from `prompt` and the `generated` text. `None` if the provider
+/// reported nothing and generated nothing.
+#[cfg(feature = "server")]
+fn usage_or_estimate(reported: Option<Usage>, prompt: &str, generated: &str) -> Option<Usage> {
+    if generated.is_empty() {
+        return reported;
+    }
+    let estimate = |text: &str| u32::try_from(quota::estimate_tokens(text)).unwrap_or(u32::MAX);
+    let mut usage = reported.unwrap_or_default();
+    if usage.prompt_tokens == 0 {
+        usage.prompt_tokens = estimate(prompt);
+    }
+    if usage.completion_tokens == 0 {
+        usage.completion_tokens = estimate(generated);
+    }
+    Some(usage)
+}
+
+/// Stream the provider's answer to `tx`, noting its token counts in `usage` and the
+/// text and reasoning it generates in `generated`. Stops if the client goes away.
+#[cfg(feature = "server")]
+async fn relay(
+    model: &ModelConfig,
+    params: &GenerationParams,
+    api_key: &str,
+    prompt: &str,
+    tx: &mpsc::Sender<CompletionChunk>,
+    usage: &mut Option<Usage>,
+    generated: &mut String,
+) -> Result<(), ChatError> {
+    let res = crate::completion_request(model, params, api_key, prompt, true)?
+        .send()
+        .await
+        .map_err(|e| ChatError::Provider(e.to_string()))?;
+    if !res.status().is_success() {
+        let json: serde_json::Value = res.json().await.unwrap_or_default();
+        return Err(crate::provider_error(&json));
+    }
+
+    let mut buffer = Vec::new();
+    let mut body = res.bytes_stream();
+    while let Some(bytes) = body.next().await {
+        buffer.extend_from_slice(&bytes.map_err(|e| ChatError::Provider(e.to_string()))?);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(data) else {
+                continue;
+            };
+            for chunk in parse_event(model.provider, &json, usage)? {
+                if let CompletionChunk::Text(text) | CompletionChunk::Reasoning(text) = &chunk {
+                    generated.push_str(text);
+                }
+                if tx.send(chunk).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The chunks one server-sent event from the provider adds, noting any token counts
+/// in `usage`.
+#[cfg(feature = "server")]
+fn parse_event(
+    provider: Provider,
+    json: &serde_json::Value,
+    usage: &mut Option<Usage>,
+) -> Result<Vec<CompletionChunk>, ChatError> {
+    if json["error"].is_object() {
+        return Err(crate::provider_error(json));
+    }
+    let tokens = |value: &serde_json::Value| value.as_u64().unwrap_or_default() as u32;
+    let mut chunks = Vec::new();
+    let mut push = |chunk: fn(String) -> CompletionChunk, text: &serde_json::Value| {
+        if let Some(text) = text.as_str().filter(|t| !t.is_empty()) {
+            chunks.push(chunk(text.to_string()));
+        }
+    };
+    match provider {
+        Provider::Anthropic => match json["type"].as_str() {
+            Some("message_start") => {
+                let counts = &json["message"]["usage"];
+                usage.get_or_insert_with(Usage::default).prompt_tokens =
+                    tokens(&counts["input_tokens"]);
+            }
+            Some("content_block_delta") => {
+                let delta = &json["delta"];
+                push(CompletionChunk::Text, &delta["text"]);
+                push(CompletionChunk::Reasoning, &delta["thinking"]);
+            }
+            Some("message_delta") => {
+                usage.get_or_insert_with(Usage::default).completion_tokens =
+                    tokens(&json["usage"]["output_tokens"]);
+            }
+            _ => {}
+        },
+        _ => {
+            let delta = &json["choices"][0]["delta"];
+            push(CompletionChunk::Reasoning, &delta["reasoning"]);
+            push(CompletionChunk::Reasoning, &delta["reasoning_content"]);
+            push(CompletionChunk::Text, &delta["content"]);
+            if json["usage"].is_object() {
+                *usage = Some(Usage {
+                    prompt_tokens: tokens(&json["usage"]["prompt_tokens"]),
+                    completion_tokens: tokens(&json["usage"]["completion_tokens"]),
+                    cost: None,
+                });
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_reported_usage_as_it_is() {
+        let reported = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 20,
+            cost: None,
+        };
+        assert_eq!(
+            usage_or_estimate(Some(reported), "prompt", "answer"),
+            Some(reported)
+        );
+        assert_eq!(usage_or_estimate(None, "prompt", ""), None);
+    }
+
+    #[test]
+    fn estimates_usage_the_provider_did_not_report() {
+        let usage = usage_or_estimate(None, "12345678", "1234").unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (2, 1));
+
+        // Anthropic counts the prompt first and the answer only at the end.
+        let prompt_only = Usage {
+            prompt_tokens: 7,
+            ..Usage::default()
+        };
+        let usage = usage_or_estimate(Some(prompt_only), "12345678", "1234").unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (7, 1));
+    }
+}
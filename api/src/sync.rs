@@ -0,0 +1,92 @@
+//! Keeping conversations in step across devices.
+//!
+//! Every change to a conversation is appended to its change log under the next
+//! version number. Clients remember the last version they applied, ask for the
+//! changes after it and send along the messages they queued while offline.
+//!
+//! The log names messages rather than copying them, and forgets the ones that were
+//! deleted and text that was replaced, so changes are sent with the messages as they
+//! are now and their versions can skip numbers.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::members::Participant;
+use crate::{ChatMessage, ConversationId, MessageId};
+
+#[cfg(feature = "server")]
+use crate::{session, Role, UserEvent};
+
+/// One entry in a conversation's change log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub version: u64,
+    pub kind: ChangeKind,
+}
+
+/// What a [`Change`] did to the conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChangeKind {
+    MessageAdded(ChatMessage),
+    /// A message's text was replaced; the message keeps its id and position.
+    MessageEdited(ChatMessage),
+    MessagesDeleted(Vec<MessageId>),
+}
+
+/// What [`stream_messages`](crate::stream_messages) delivers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConversationEvent {
+    Changed(Change),
+    /// Everyone who has the conversation open now, replacing the previous list.
+    Presence(Vec<Participant>),
+}
+
+/// The server's answer to [`sync_conversation`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncResponse {
+    /// Version of the last change the server has.
+    pub version: u64,
+    /// Changes after the version the client asked for, oldest first. Versions can skip
+    /// changes that no longer matter; the client is at `version` once it applies them.
+    pub changes: Vec<Change>,
+    /// The client is ahead of the server, e.g. because the server restarted, or missed
+    /// deletions the server no longer keeps. `changes` then holds the whole log and the
+    /// client should drop its copy.
+    pub reset: bool,
+}
+
+/// Store `pending` messages as written by the caller, skipping ids the conversation
+/// already has, and return the changes after version `since`, including the ones for
+/// `pending`.
+#[server(SyncConversation)]
+pub async fn sync_conversation(
+    session: String,
+    conv_id: ConversationId,
+    since: u64,
+    pending: Vec<ChatMessage>,
+) -> Result<SyncResponse, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let role = if pending.is_empty() {
+        Role::Viewer
+    } else {
+        Role::Editor
+    };
+    let mut received = Vec::with_capacity(pending.len());
+    for msg in pending {
+        received.push(crate::received_message(&caller, msg).await);
+    }
+    let mut history = crate::CHAT_HISTORY.write().await;
+    let conv = crate::accessible_conversation_mut(&mut history, &caller.key(), conv_id, role)
+        .ok_or_else(|| ServerFnError::new("Conversation not found"))?;
+    let reset = conv.needs_reset(since);
+    for msg in received {
+        if conv.add_message(conv_id, msg).await {
+            conv.updated_at = chrono::Utc::now();
+            conv.publish(conv_id, |_| UserEvent::MessageAdded(conv_id)).await;
+        }
+    }
+    Ok(SyncResponse {
+        version: conv.version,
+        changes: conv.changes_since(if reset { 0 } else { since }),
+        reset,
+    })
+}
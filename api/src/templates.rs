@@ -0,0 +1,231 @@
+//! Saved prompt templates, kept per user.
+//!
+//! A template's text can contain variables written as `{{name}}`, which are filled
+//! in when the template is put into the composer.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "server")]
+use crate::session;
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+/// Stable identifier of a template.
+pub type TemplateId = uuid::Uuid;
+
+/// Longest template name, in characters.
+pub const MAX_TEMPLATE_NAME_LEN: usize = 60;
+
+/// Longest template text, in characters.
+pub const MAX_TEMPLATE_TEXT_LEN: usize = 20_000;
+
+/// Most templates one caller can keep.
+pub const MAX_TEMPLATES: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PromptTemplate {
+    /// Nil for a template that has not been saved yet.
+    pub id: TemplateId,
+    pub name: String,
+    pub text: String,
+}
+
+impl PromptTemplate {
+    /// The `{{name}}` spans in the text as byte ranges with their trimmed names.
+    fn placeholders(&self) -> Vec<(usize, usize, &str)> {
+        let mut found = Vec::new();
+        let mut rest = 0;
+        while let Some(open) = self.text[rest..].find("{{").map(|i| rest + i) {
+            let Some(close) = self.text[open + 2..].find("}}").map(|i| open + 2 + i) else {
+                break;
+            };
+            let name = self.text[open + 2..close].trim();
+            if !name.is_empty() && !name.contains('{') {
+                found.push((open, close + 2, name));
+            }
+            rest = close + 2;
+        }
+        found
+    }
+
+    /// Names of the variables in the text, each once, in order of appearance.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (_, _, name) in self.placeholders() {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    /// The text with every variable replaced by its value in `values`. Variables
+    /// without a value are left as they are.
+    pub fn fill(&self, values: &[(String, String)]) -> String {
+        let mut text = String::with_capacity(self.text.len());
+        let mut cursor = 0;
+        for (start, end, name) in self.placeholders() {
+            if let Some((_, value)) = values.iter().find(|(n, _)| n == name) {
+                text.push_str(&self.text[cursor..start]);
+                text.push_str(value);
+                cursor = end;
+            }
+        }
+        text.push_str(&self.text[cursor..]);
+        text
+    }
+}
+
+#[cfg(feature = "server")]
+type TemplateStore = HashMap<String, Vec<PromptTemplate>>;
+
+/// Templates by [`session::Caller::key`], in the order they were created.
+#[cfg(feature = "server")]
+static TEMPLATES: Lazy<Arc<RwLock<TemplateStore>>> =
+    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+/// The caller's templates, sorted by name.
+#[server(ListTemplates)]
+pub async fn list_templates(session: String) -> Result<Vec<PromptTemplate>, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let mut templates = TEMPLATES
+        .read()
+        .await
+        .get(&key)
+        .cloned()
+        .unwrap_or_default();
+    templates.sort_by_key(|t| t.name.to_lowercase());
+    Ok(templates)
+}
+
+/// Add a template, or replace the caller's template with the same id, and return it
+/// as stored.
+#[server(SaveTemplate)]
+pub async fn save_template(
+    session: String,
+    template: PromptTemplate,
+) -> Result<PromptTemplate, ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    let mut template = template;
+    template.name = template.name.trim().to_string();
+    if template.name.is_empty() || template.text.trim().is_empty() {
+        return Err(ServerFnError::new("A template needs a name and text"));
+    }
+    if template.name.chars().count() > MAX_TEMPLATE_NAME_LEN {
+        return Err(ServerFnError::new(format!(
+            "Template names are at most {MAX_TEMPLATE_NAME_LEN} characters"
+        )));
+    }
+    if template.text.chars().count() > MAX_TEMPLATE_TEXT_LEN {
+        return Err(ServerFnError::new(format!(
+            "Templates are at most {MAX_TEMPLATE_TEXT_LEN} characters"
+        )));
+    }
+    let mut store = TEMPLATES.write().await;
+    let templates = store.entry(key).or_default();
+    let full = templates.len() >= MAX_TEMPLATES;
+    match templates
+        .iter_mut()
+        .find(|t| t.id == template.id && !t.id.is_nil())
+    {
+        Some(existing) => *existing = template.clone(),
+        None if full => {
+            return Err(ServerFnError::new(format!(
+                "You can keep at most {MAX_TEMPLATES} templates"
+            )));
+        }
+        None => {
+            template.id = TemplateId::new_v4();
+            templates.push(template.clone());
+        }
+    }
+    Ok(template)
+}
+
+/// Delete one of the caller's templates.
+#[server(DeleteTemplate)]
+pub async fn delete_template(session: String, id: TemplateId) -> Result<(), ServerFnError> {
+    let key = session::caller(&session).await?.key();
+    if let Some(templates) = TEMPLATES.write().await.get_mut(&key) {
+        templates.retain(|t| t.id != id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> PromptTemplate {
+        PromptTemplate {
+            id: TemplateId::nil(),
+            name: "t".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn lists_repeated_variables_once() {
+        let t = template("{{a}} and {{ b }} and {{a}}");
+        assert_eq!(t.variables(), ["a", "b"]);
+        assert_eq!(t.fill(&values(&[("a", "1"), ("b", "2")])), "1 and 2 and 1");
+    }
+
+    #[test]
+    fn leaves_missing_variables_as_they_are() {
+        let t = template("Dear {{name}}, re {{topic}}");
+        assert_eq!(
+            t.fill(&values(&[("name", "Ada")])),
+            "Dear Ada, re {{topic}}"
+        );
+        assert_eq!(t.fill(&[]), t.text);
+    }
+
+    #[test]
+    fn ignores_nested_empty_and_unclosed_braces() {
+        let t = template("{{outer {{inner}} }} {{ }} {{open");
+        assert_eq!(t.variables(), Vec::<String>::new());
+        assert_eq!(t.fill(&values(&[("inner", "x")])), t.text);
+    }
+
+    #[test]
+    fn does_not_fill_values_in_again() {
+        let t = template("{{a}} {{b}}");
+        assert_eq!(t.fill(&values(&[("a", "{{b}}"), ("b", "2")])), "{{b}} 2");
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn caps_template_length_and_count() {
+        let session = session::start_session(None).await.unwrap();
+        let too_long = template(&"x".repeat(MAX_TEMPLATE_TEXT_LEN + 1));
+        assert!(save_template(session.clone(), too_long).await.is_err());
+
+        for _ in 0..MAX_TEMPLATES {
+            save_template(session.clone(), template("text"))
+                .await
+                .unwrap();
+        }
+        assert!(save_template(session.clone(), template("text"))
+            .await
+            .is_err());
+
+        // Replacing an existing template is still allowed.
+        let mut first = list_templates(session.clone()).await.unwrap().remove(0);
+        first.text = "new text".to_string();
+        assert!(save_template(session, first).await.is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+//! Token usage and spend accounting per message, conversation and caller.
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "server")]
+use crate::session::{self, Caller};
+#[cfg(feature = "server")]
+use crate::MessageId;
+#[cfg(feature = "server")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "server")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+
+/// Token counts reported by the provider for one completion.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// Cost in USD at the model's price when the request was made, if the model has a price.
+    pub cost: Option<f64>,
+}
+
+/// Usage summed over several completions.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct UsageTotals {
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    pub fn add(&mut self, usage: &Usage) {
+        self.requests += 1;
+        self.prompt_tokens += u64::from(usage.prompt_tokens);
+        self.completion_tokens += u64::from(usage.completion_tokens);
+        self.cost += usage.cost.unwrap_or_default();
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// A caller's usage grouped by model and by UTC day.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    /// Sorted by model name.
+    pub by_model: Vec<(String, UsageTotals)>,
+    /// `YYYY-MM-DD` days, most recent first.
+    pub by_day: Vec<(String, UsageTotals)>,
+}
+
+/// One completion in a caller's ledger.
+#[cfg(feature = "server")]
+struct UsageRecord {
+    model: String,
+    day: String,
+    usage: Usage,
+}
+
+#[cfg(feature = "server")]
+type UsageLedger = HashMap<String, Vec<UsageRecord>>;
+
+/// In-memory usage ledger keyed by [`Caller::key`].
+#[cfg(feature = "server")]
+static USAGE_LEDGER: Lazy<Arc<RwLock<UsageLedger>>> =
+    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+/// Add a completion to the caller's running totals.
+#[cfg(feature = "server")]
+pub(crate) async fn record(caller: &Caller, model: &str, usage: Usage) {
+    let record = UsageRecord {
+        model: model.to_string(),
+        day: chrono::Utc::now().format("%Y-%m-%d").to_string(),
+        usage,
+    };
+    USAGE_LEDGER
+        .write()
+        .await
+        .entry(caller.key())
+        .or_default()
+        .push(record);
+}
+
+/// How long the usage of a completion waits for the client to store its reply.
+#[cfg(feature = "server")]
+const REPLY_USAGE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(feature = "server")]
+type ReplyUsage = HashMap<(String, MessageId), (Instant, Usage)>;
+
+/// Usage of completions whose reply has not been stored yet, keyed by [`Caller::key`]
+/// and the id the client chose for the reply.
+#[cfg(feature = "server")]
+static REPLY_USAGE: Lazy<Arc<RwLock<ReplyUsage>>> =
+    Lazy::new(|| Arc::new(RwLock::new(HashMap::new())));
+
+/// Keep a completion's priced usage until the caller stores the reply with `reply_id`.
+#[cfg(feature = "server")]
+pub(crate) async fn hold_for_reply(caller: &Caller, reply_id: MessageId, usage: Usage) {
+    if reply_id.is_nil() {
+        return;
+    }
+    let now = Instant::now();
+    let mut held = REPLY_USAGE.write().await;
+    held.retain(|_, (at, _)| now.duration_since(*at) < REPLY_USAGE_TTL);
+    held.insert((caller.key(), reply_id), (now, usage));
+}
+
+/// The usage held for the caller's reply `reply_id`, which is then forgotten.
+#[cfg(feature = "server")]
+pub(crate) async fn take_for_reply(caller: &Caller, reply_id: MessageId) -> Option<Usage> {
+    REPLY_USAGE
+        .write()
+        .await
+        .remove(&(caller.key(), reply_id))
+        .map(|(_, usage)| usage)
+}
+
+/// Summarize the calling session's usage by model and by day.
+#[server(GetUsage)]
+pub async fn get_usage(session: String) -> Result<UsageReport, ServerFnError> {
+    let caller = session::caller(&session).await?;
+    let ledger = USAGE_LEDGER.read().await;
+    let mut report = UsageReport::default();
+    let mut by_model = BTreeMap::<String, UsageTotals>::new();
+    let mut by_day = BTreeMap::<String, UsageTotals>::new();
+    for record in ledger.get(&caller.key()).into_iter().flatten() {
+        report.total.add(&record.usage);
+        by_model
+            .entry(record.model.clone())
+            .or_default()
+            .add(&record.usage);
+        by_day
+            .entry(record.day.clone())
+            .or_default()
+            .add(&record.usage);
+    }
+    report.by_model = by_model.into_iter().collect();
+    report.by_day = by_day.into_iter().rev().collect();
+    Ok(report)
+}
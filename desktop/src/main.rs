@@ -11,6 +11,8 @@ enum Route {
     #[layout(DesktopNavbar)]
     #[route("/")]
     Home {},
+    #[route("/blog/:id")]
+    Blog { id: i32 },
 }
 
 fn main() {
@@ -11,6 +11,8 @@ enum Route {
     #[layout(MobileNavbar)]
     #[route("/")]
     Home {},
+    #[route("/blog/:id")]
+    Blog { id: i32 },
 }
 
 fn main() {
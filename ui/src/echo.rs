@@ -3,7 +3,7 @@ use dioxus::prelude::*;
 /// Echo component that demonstrates fullstack server functions.
 #[component]
 pub fn Echo() -> Element {
-    let mut response = use_signal(|| String::new());
+    let mut response = use_signal(String::new);
 
     rsx! {
         div { class: "w-90 mx-auto mt-12 bg-gray-800 p-5 rounded",
@@ -0,0 +1,66 @@
+//! Offline copies of server data in the browser's IndexedDB, stored as strings by key.
+//! Nothing is cached outside the web build; desktop and mobile have no offline cache yet.
+
+#[cfg(feature = "web")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "web")]
+#[wasm_bindgen(inline_js = r#"
+function request(mode, run) {
+  return new Promise((resolve, reject) => {
+    const open = indexedDB.open('chat-cache', 1);
+    open.onupgradeneeded = () => open.result.createObjectStore('entries');
+    open.onerror = () => reject(open.error);
+    open.onsuccess = () => {
+      const req = run(open.result.transaction('entries', mode).objectStore('entries'));
+      req.onsuccess = () => resolve(req.result);
+      req.onerror = () => reject(req.error);
+    };
+  });
+}
+export function cache_get(key) {
+  return request('readonly', (store) => store.get(key)).then((v) => v ?? null, () => null);
+}
+export function cache_put(key, value) {
+  return request('readwrite', (store) => store.put(value, key)).then(() => {}, () => {});
+}
+export function cache_retain_prefix(prefix) {
+  return request('readwrite', (store) => store.getAllKeys()).then((keys) => Promise.all(
+    keys
+      .filter((key) => !String(key).startsWith(prefix))
+      .map((key) => request('readwrite', (store) => store.delete(key)))
+  )).then(() => {}, () => {});
+}
+"#)]
+extern "C" {
+    async fn cache_get(key: &str) -> JsValue;
+    async fn cache_put(key: &str, value: &str);
+    async fn cache_retain_prefix(prefix: &str);
+}
+
+#[cfg(feature = "web")]
+pub async fn load_from_cache(key: &str) -> Option<String> {
+    cache_get(key).await.as_string()
+}
+
+#[cfg(not(feature = "web"))]
+pub async fn load_from_cache(_key: &str) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "web")]
+pub async fn save_to_cache(key: &str, value: &str) {
+    cache_put(key, value).await;
+}
+
+#[cfg(not(feature = "web"))]
+pub async fn save_to_cache(_key: &str, _value: &str) {}
+
+/// Delete every cached entry whose key does not start with `prefix`.
+#[cfg(feature = "web")]
+pub async fn retain_in_cache(prefix: &str) {
+    cache_retain_prefix(prefix).await;
+}
+
+#[cfg(not(feature = "web"))]
+pub async fn retain_in_cache(_prefix: &str) {}
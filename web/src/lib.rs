@@ -1,5 +1,9 @@
+pub mod cache;
 pub mod routes;
+pub mod session;
 pub mod speech;
+pub mod storage;
+pub mod sync;
 pub mod views;
 
 pub use routes::Route;
@@ -1,10 +1,16 @@
+use api::ConversationId;
 use dioxus::prelude::*;
+#[cfg(target_arch = "wasm32")]
 use web_sys::window;
 
+mod cache;
+mod session;
 mod speech;
+mod storage;
+mod sync;
 mod views;
 use crate::views::Theme;
-use views::{Chat, ChatShare, Login, NotFound, Settings};
+use views::{Chat, ChatShare, Login, NotFound, Settings, SharedChat, Usage};
 
 #[derive(Debug, Clone, Routable, PartialEq)]
 #[rustfmt::skip]
@@ -12,9 +18,13 @@ enum Route {
     #[route("/")]
     Chat {},
     #[route("/chat/:id")]
-    ChatShare { id: usize },
+    ChatShare { id: ConversationId },
+    #[route("/share/:token")]
+    SharedChat { token: String },
     #[route("/settings")]
     Settings {},
+    #[route("/settings/usage")]
+    Usage {},
     #[route("/login")]
     Login {},
     #[route("/:..route")]
@@ -24,12 +34,15 @@ enum Route {
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 
 fn main() {
+    // Refuse to start with an invalid key secret rather than losing stored keys on restart.
+    #[cfg(feature = "server")]
+    api::keys::check_encryption_secret();
     dioxus::launch(App);
 }
 
+#[cfg(target_arch = "wasm32")]
 fn apply_theme(theme: Theme) {
-    #[cfg(target_arch = "wasm32")]
-    if let (Some(win), Some(doc)) = (window(), web_sys::window().and_then(|w| w.document())) {
+    if let Some(doc) = window().and_then(|w| w.document()) {
         if let Some(html) = doc.document_element() {
             if matches!(theme, Theme::Dark) {
                 let _ = html.class_list().add_1("dark");
@@ -69,7 +82,9 @@ fn App() -> Element {
     };
 
     // This violates the rules of hooks (can't use signal inside context provider this way)
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
     let theme = use_context_provider(|| use_signal(|| initial_theme));
+    session::use_session_provider();
 
     use_effect(move || {
         #[cfg(target_arch = "wasm32")]
@@ -88,6 +103,12 @@ fn App() -> Element {
         }
     });
 
+    let code_css = format!(
+        "{}{}",
+        api::highlight::light_css(),
+        api::highlight::dark_css()
+    );
+
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
         document::Stylesheet { href: "https://fonts.googleapis.com/css2?family=Proxima+Vara:wght@100..900&display=swap" }
@@ -95,8 +116,7 @@ fn App() -> Element {
             href: asset!("/assets/tailwind.css"),
         }
         document::Stylesheet { href: "https://cdn.jsdelivr.net/npm/katex@0.12.0/dist/katex.min.css" }
-        document::Stylesheet { href: "https://cdn.jsdelivr.net/npm/highlight.js@11.8.0/styles/github-dark.min.css" }
-        document::Script { src: "https://cdn.jsdelivr.net/npm/highlight.js@11.8.0/lib/common.min.js" }
+        document::Style { {code_css} }
 
         Router::<Route> {}
     }
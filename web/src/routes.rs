@@ -3,6 +3,9 @@ use crate::views::ChatShare;
 use crate::views::Login;
 use crate::views::NotFound;
 use crate::views::Settings;
+use crate::views::SharedChat;
+use crate::views::Usage;
+use api::ConversationId;
 use dioxus::prelude::*;
 
 #[derive(Debug, Clone, Routable, PartialEq)]
@@ -11,9 +14,13 @@ pub enum Route {
     #[route("/")]
     Chat {},
     #[route("/chat/:id")]
-    ChatShare { id: usize },
+    ChatShare { id: ConversationId },
+    #[route("/share/:token")]
+    SharedChat { token: String },
     #[route("/settings")]
     Settings {},
+    #[route("/settings/usage")]
+    Usage {},
     #[route("/login")]
     Login {},
     #[route("/:..route")]
@@ -0,0 +1,43 @@
+use dioxus::prelude::*;
+
+use crate::storage::{load_from_storage, save_to_storage};
+use crate::sync;
+use crate::views::forget_other_drafts;
+
+const SESSION_KEY: &str = "session";
+
+/// The caller's session token, provided as context by the app root.
+#[derive(Clone, Copy)]
+pub struct Session(pub Signal<Option<String>>);
+
+/// Provide the session context and make sure the stored token is still valid on the server.
+pub fn use_session_provider() -> Signal<Option<String>> {
+    let session = use_context_provider(|| Session(Signal::new(None)));
+    use_future(move || async move {
+        let mut signal = session.0;
+        let stored = load_from_storage(SESSION_KEY);
+        match api::start_session(stored.clone()).await {
+            Ok(token) => set_session(&mut signal, token),
+            // Offline: keep the stored token so cached conversations can be read.
+            Err(ServerFnError::Request(_)) if stored.is_some() => signal.set(stored),
+            Err(e) => log::error!("Failed to start session: {}", e),
+        }
+    });
+    session.0
+}
+
+/// The current session token, `None` until the server has confirmed one or, while
+/// offline, until the stored one is loaded.
+pub fn use_session() -> Signal<Option<String>> {
+    use_context::<Session>().0
+}
+
+/// Replace the session token, e.g. after logging in. Whatever was cached and the
+/// drafts typed for the previous token are deleted.
+pub fn set_session(session: &mut Signal<Option<String>>, token: String) {
+    save_to_storage(SESSION_KEY, &token);
+    forget_other_drafts(&token);
+    let current = token.clone();
+    spawn(async move { sync::forget_other_sessions(&current).await });
+    session.set(Some(token));
+}
@@ -1,53 +1,234 @@
+//! Voice mode: dictation with the browser's speech recognition and replies read aloud
+//! with its speech synthesis. Neither does anything outside the browser.
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+use crate::storage::{load_from_storage, save_to_storage};
+
+#[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_futures::JsFuture;
+
+const VOICE_SETTINGS_KEY: &str = "voice";
+
+/// Voice mode preferences, kept in this browser.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceSettings {
+    /// Voice mode is opt-in: until it is on there is no microphone and nothing is read.
+    pub enabled: bool,
+    /// BCP 47 tag of the language spoken and read, such as `en-US`.
+    pub language: String,
+    /// Name of the voice replies are read with; `None` for the browser's default.
+    pub voice: Option<String>,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            language: "en-US".to_string(),
+            voice: None,
+        }
+    }
+}
+
+impl VoiceSettings {
+    pub fn load() -> Self {
+        load_from_storage(VOICE_SETTINGS_KEY)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Ok(json) = serde_json::to_string(self) {
+            save_to_storage(VOICE_SETTINGS_KEY, &json);
+        }
+    }
+}
+
+/// Speech recognition that is listening, from [`start_stt`].
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub struct Dictation {
+    #[cfg(target_arch = "wasm32")]
+    recognition: web_sys::SpeechRecognition,
+}
+
+impl Dictation {
+    /// Stop listening early. What was said so far is still returned.
+    pub fn stop(&self) {
+        #[cfg(target_arch = "wasm32")]
+        self.recognition.stop();
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
-use wasm_bindgen::{closure::Closure, JsCast};
+fn synthesis() -> Option<web_sys::SpeechSynthesis> {
+    web_sys::window()?.speech_synthesis().ok()
+}
 
 #[cfg(target_arch = "wasm32")]
-fn window() -> web_sys::Window {
-    web_sys::window().expect("no global `window`")
+fn all_voices(synth: &web_sys::SpeechSynthesis) -> Vec<web_sys::SpeechSynthesisVoice> {
+    synth
+        .get_voices()
+        .iter()
+        .filter_map(|voice| voice.dyn_into().ok())
+        .collect()
 }
 
+/// Names and languages of the voices the browser can read with. Browsers load the
+/// list lazily, so it can be empty until [`voices_changed`] resolves.
 #[cfg(target_arch = "wasm32")]
-pub fn speak(text: &str) {
-    let win = window();
-    if let Ok(synth) = win.speech_synthesis() {
-        if let Ok(utter) = web_sys::SpeechSynthesisUtterance::new_with_text(text) {
-            synth.speak(&utter);
-        }
+pub fn voices() -> Vec<(String, String)> {
+    synthesis()
+        .map(|synth| {
+            all_voices(&synth)
+                .into_iter()
+                .map(|v| (v.name(), v.lang()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Resolves the next time the list of [`voices`] changes.
+#[cfg(target_arch = "wasm32")]
+pub async fn voices_changed() {
+    let Some(synth) = synthesis() else {
+        return std::future::pending().await;
+    };
+    let changed = js_sys::Promise::new(&mut |resolve, _| {
+        synth.set_onvoiceschanged(Some(&resolve));
+    });
+    let _ = JsFuture::from(changed).await;
+}
+
+/// Read `text` aloud as `settings` say, after anything already being read. Resolves
+/// once it has been read, or cut short by [`stop_speaking`].
+#[cfg(target_arch = "wasm32")]
+pub async fn speak(text: &str, settings: &VoiceSettings) {
+    let (Some(synth), Ok(utter)) = (
+        synthesis(),
+        web_sys::SpeechSynthesisUtterance::new_with_text(text),
+    ) else {
+        return;
+    };
+    utter.set_lang(&settings.language);
+    if let Some(name) = &settings.voice {
+        let voice = all_voices(&synth).into_iter().find(|v| v.name() == *name);
+        utter.set_voice(voice.as_ref());
+    }
+    // Browsers fire `error` rather than `end` when reading is cancelled.
+    let done = js_sys::Promise::new(&mut |resolve, _| {
+        utter.set_onend(Some(&resolve));
+        utter.set_onerror(Some(&resolve));
+    });
+    synth.speak(&utter);
+    let _ = JsFuture::from(done).await;
+}
+
+/// Stop reading, dropping anything still queued.
+#[cfg(target_arch = "wasm32")]
+pub fn stop_speaking() {
+    if let Some(synth) = synthesis() {
+        synth.cancel();
     }
 }
 
+/// Listen in `language` until the speaker pauses. The future resolves to what was
+/// said, empty if nothing was, or to the browser's error code if listening failed.
+/// `None` if the browser cannot recognize speech.
 #[cfg(target_arch = "wasm32")]
-pub fn start_stt<F: FnMut(String) + 'static>(mut callback: F) {
+pub fn start_stt(
+    language: &str,
+) -> Option<(Dictation, impl Future<Output = Result<String, String>>)> {
     use web_sys::{
         SpeechRecognition, SpeechRecognitionAlternative, SpeechRecognitionEvent,
         SpeechRecognitionResult,
     };
 
-    if let Ok(rec) = SpeechRecognition::new() {
-        rec.set_lang("en-US");
-        rec.set_interim_results(false);
-        let closure = Closure::<dyn FnMut(web_sys::Event)>::new(move |e: web_sys::Event| {
-            if let Ok(event) = e.dyn_into::<SpeechRecognitionEvent>() {
-                if let Some(list) = event.results() {
-                    if let Some(res) = list.get(0) {
-                        let res: SpeechRecognitionResult = res.dyn_into().unwrap();
-                        if let Some(alt) = res.get(0) {
-                            let alt: SpeechRecognitionAlternative = alt.dyn_into().unwrap();
-                            callback(alt.transcript());
-                        }
+    let recognition = SpeechRecognition::new().ok()?;
+    recognition.set_lang(language);
+    recognition.set_interim_results(false);
+
+    let transcript = Rc::new(RefCell::new(String::new()));
+    let on_result = {
+        let transcript = transcript.clone();
+        Closure::<dyn FnMut(web_sys::Event)>::new(move |e: web_sys::Event| {
+            let Ok(event) = e.dyn_into::<SpeechRecognitionEvent>() else {
+                return;
+            };
+            let Some(results) = event.results() else {
+                return;
+            };
+            for index in event.result_index()..results.length() {
+                let alternative = results
+                    .get(index)
+                    .and_then(|r| r.dyn_into::<SpeechRecognitionResult>().ok())
+                    .and_then(|r| r.get(0))
+                    .and_then(|a| a.dyn_into::<SpeechRecognitionAlternative>().ok());
+                if let Some(alternative) = alternative {
+                    let mut transcript = transcript.borrow_mut();
+                    if !transcript.is_empty() {
+                        transcript.push(' ');
                     }
+                    transcript.push_str(alternative.transcript().trim());
                 }
             }
-        });
-        rec.set_onresult(Some(closure.as_ref().unchecked_ref()));
-        rec.start().ok();
-        closure.forget();
-        std::mem::forget(rec);
-    }
+        })
+    };
+
+    // Hearing nothing, or being stopped, is not a failure.
+    let error = Rc::new(RefCell::new(None::<String>));
+    let on_error = {
+        let error = error.clone();
+        Closure::<dyn FnMut(web_sys::Event)>::new(move |e: web_sys::Event| {
+            *error.borrow_mut() = js_sys::Reflect::get(&e, &JsValue::from_str("error"))
+                .ok()
+                .and_then(|code| code.as_string())
+                .filter(|code| code != "no-speech" && code != "aborted");
+        })
+    };
+
+    recognition.set_onresult(Some(on_result.into_js_value().unchecked_ref()));
+    recognition.set_onerror(Some(on_error.into_js_value().unchecked_ref()));
+    let ended = js_sys::Promise::new(&mut |resolve, _| {
+        recognition.set_onend(Some(&resolve));
+    });
+    recognition.start().ok()?;
+
+    let heard = async move {
+        let _ = JsFuture::from(ended).await;
+        let failed = error.borrow_mut().take();
+        match failed {
+            Some(code) => Err(code),
+            None => Ok(transcript.take()),
+        }
+    };
+    Some((Dictation { recognition }, heard))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn voices() -> Vec<(String, String)> {
+    Vec::new()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn voices_changed() {
+    std::future::pending().await
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn speak(_text: &str) {}
+pub async fn speak(_text: &str, _settings: &VoiceSettings) {}
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn start_stt<F: FnMut(String) + 'static>(_callback: F) {}
+pub fn stop_speaking() {}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn start_stt(
+    _language: &str,
+) -> Option<(Dictation, impl Future<Output = Result<String, String>>)> {
+    None::<(Dictation, std::future::Ready<Result<String, String>>)>
+}
@@ -0,0 +1,55 @@
+//! Browser `localStorage` helpers. They are no-ops outside the web build.
+
+#[cfg(feature = "web")]
+pub fn save_to_storage(key: &str, value: &str) {
+    if let Some(win) = web_sys::window() {
+        if let Ok(Some(storage)) = win.local_storage() {
+            let _ = storage.set_item(key, value);
+        }
+    }
+}
+
+#[cfg(not(feature = "web"))]
+pub fn save_to_storage(_key: &str, _value: &str) {}
+
+#[cfg(feature = "web")]
+pub fn load_from_storage(key: &str) -> Option<String> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .and_then(|s| s.get_item(key).ok().flatten())
+}
+
+#[cfg(not(feature = "web"))]
+pub fn load_from_storage(_key: &str) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "web")]
+pub fn remove_from_storage(key: &str) {
+    if let Some(win) = web_sys::window() {
+        if let Ok(Some(storage)) = win.local_storage() {
+            let _ = storage.remove_item(key);
+        }
+    }
+}
+
+#[cfg(not(feature = "web"))]
+pub fn remove_from_storage(_key: &str) {}
+
+/// Remove every key that starts with `prefix` but not with `keep`.
+#[cfg(feature = "web")]
+pub fn remove_from_storage_except(prefix: &str, keep: &str) {
+    let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) else {
+        return;
+    };
+    let keys: Vec<String> = (0..storage.length().unwrap_or(0))
+        .filter_map(|i| storage.key(i).ok().flatten())
+        .filter(|key| key.starts_with(prefix) && !key.starts_with(keep))
+        .collect();
+    for key in keys {
+        let _ = storage.remove_item(&key);
+    }
+}
+
+#[cfg(not(feature = "web"))]
+pub fn remove_from_storage_except(_prefix: &str, _keep: &str) {}
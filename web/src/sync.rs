@@ -0,0 +1,183 @@
+//! The browser's side of conversation sync: a cached copy of the open conversation,
+//! the messages written while offline, and reconciling both with the server.
+//!
+//! Cache keys start with the session token, so one account's conversations are never
+//! shown to the next one to use the browser.
+use api::{Change, ChangeKind, ChatMessage, ConversationId, ConversationSummary, SyncResponse};
+use dioxus::prelude::*;
+use futures_util::{future, stream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::TextStream;
+
+use crate::cache::{load_from_cache, retain_in_cache, save_to_cache};
+
+/// A conversation as this device knows it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocalConversation {
+    /// Server version `confirmed` is at.
+    pub version: u64,
+    /// Messages the server has stored, in its order.
+    pub confirmed: Vec<ChatMessage>,
+    /// Messages written on this device that the server has not confirmed yet.
+    pub pending: Vec<ChatMessage>,
+}
+
+impl LocalConversation {
+    /// Confirmed messages followed by the pending ones.
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        self.confirmed
+            .iter()
+            .chain(&self.pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Apply a change from the server. Returns `false` if changes before it were
+    /// missed, in which case the conversation needs a full [`sync`].
+    pub fn apply(&mut self, change: Change) -> bool {
+        if change.version <= self.version {
+            return true;
+        }
+        if change.version != self.version + 1 {
+            return false;
+        }
+        self.apply_kind(change.kind);
+        self.version = change.version;
+        true
+    }
+
+    fn apply_kind(&mut self, kind: ChangeKind) {
+        match kind {
+            ChangeKind::MessageAdded(msg) => {
+                self.pending.retain(|m| m.id != msg.id);
+                if !self.confirmed.iter().any(|m| m.id == msg.id) {
+                    self.confirmed.push(msg);
+                }
+            }
+            ChangeKind::MessageEdited(msg) => {
+                if let Some(existing) = self.confirmed.iter_mut().find(|m| m.id == msg.id) {
+                    *existing = msg;
+                }
+            }
+            ChangeKind::MessagesDeleted(ids) => {
+                self.confirmed.retain(|m| !ids.contains(&m.id));
+            }
+        }
+    }
+
+    /// Apply the changes from a sync. They are complete even where their versions skip
+    /// numbers, as the server leaves out changes that no longer matter.
+    fn apply_sync(&mut self, response: SyncResponse) {
+        if response.reset {
+            self.version = 0;
+            self.confirmed.clear();
+        }
+        for change in response.changes {
+            if change.version > self.version {
+                self.apply_kind(change.kind);
+            }
+        }
+        self.version = self.version.max(response.version);
+    }
+}
+
+/// Prefix of every cache key belonging to `session`.
+fn session_prefix(session: &str) -> String {
+    format!("{session}/")
+}
+
+fn conversations_key(session: &str) -> String {
+    format!("{}conversations", session_prefix(session))
+}
+
+fn conversation_key(session: &str, conv_id: ConversationId) -> String {
+    format!("{}conversation:{conv_id}", session_prefix(session))
+}
+
+/// Drop everything cached for sessions other than `session`, e.g. after logging in.
+pub async fn forget_other_sessions(session: &str) {
+    retain_in_cache(&session_prefix(session)).await;
+}
+
+/// The cached copy of a conversation, empty if there is none.
+pub async fn load_conversation(session: &str, conv_id: ConversationId) -> LocalConversation {
+    load_from_cache(&conversation_key(session, conv_id))
+        .await
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Cache the current state of `local`.
+pub async fn save_conversation(
+    session: &str,
+    conv_id: ConversationId,
+    local: Signal<LocalConversation>,
+) {
+    let json = serde_json::to_string(&*local.peek());
+    if let Ok(json) = json {
+        save_to_cache(&conversation_key(session, conv_id), &json).await;
+    }
+}
+
+/// The conversation list from the last time it was fetched.
+pub async fn load_conversations(session: &str) -> Option<Vec<ConversationSummary>> {
+    let json = load_from_cache(&conversations_key(session)).await?;
+    serde_json::from_str(&json).ok()
+}
+
+pub async fn save_conversations(session: &str, list: &[ConversationSummary]) {
+    if let Ok(json) = serde_json::to_string(list) {
+        save_to_cache(&conversations_key(session), &json).await;
+    }
+}
+
+/// Whether `error` says the server has no such conversation, e.g. because it restarted
+/// and lost its in-memory store. Nothing queued for it can be delivered then.
+pub fn is_gone(error: &ServerFnError) -> bool {
+    matches!(error, ServerFnError::ServerError(e) if e == "Conversation not found")
+}
+
+/// Send the pending messages, apply the changes made elsewhere and cache the result.
+/// Fails while offline, leaving the pending messages queued. When the server no longer
+/// has the conversation the pending messages are dropped instead; see [`is_gone`].
+pub async fn sync(
+    session: String,
+    conv_id: ConversationId,
+    mut local: Signal<LocalConversation>,
+) -> Result<(), ServerFnError> {
+    let (since, pending) = {
+        let local = local.peek();
+        (local.version, local.pending.clone())
+    };
+    match api::sync::sync_conversation(session.clone(), conv_id, since, pending).await {
+        Ok(response) => local.write().apply_sync(response),
+        Err(e) if is_gone(&e) => {
+            local.write().pending.clear();
+            save_conversation(&session, conv_id, local).await;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    }
+    save_conversation(&session, conv_id, local).await;
+    Ok(())
+}
+
+/// Parse the JSON lines sent by a streaming server function, ending at the first error.
+pub fn json_lines<T: DeserializeOwned>(stream: TextStream) -> impl Stream<Item = T> {
+    let mut buffer = String::new();
+    stream
+        .into_inner()
+        .take_while(|chunk| future::ready(chunk.is_ok()))
+        .flat_map(move |chunk| {
+            buffer.push_str(&chunk.unwrap_or_default());
+            let mut items = Vec::new();
+            while let Some(end) = buffer.find('\n') {
+                let line: String = buffer.drain(..=end).collect();
+                if let Ok(item) = serde_json::from_str(&line) {
+                    items.push(item);
+                }
+            }
+            stream::iter(items)
+        })
+}
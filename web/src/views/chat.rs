@@ -1,48 +1,27 @@
-use crate::speech::speak;
+use crate::session::use_session;
+use crate::speech::{self, Dictation, VoiceSettings};
+use crate::sync::{self, LocalConversation};
+use super::compare::{self, Comparison};
+use super::composer::{self, Command, TemplatePicker};
+use super::export::ExportMenu;
+use super::markdown::{self, CodeBlockScripts, Markdown};
+use super::members::MembersPanel;
+use super::share::SharePanel;
 use crate::Route;
 use api::model_config::ModelConfig;
-use api::{Attachment, ChatMessage, MessageSender};
+use api::generation::ReasoningEffort;
+use api::search::SearchHit;
+use api::model_config::Provider;
+use api::{
+    Attachment, ChatError, ChatMessage, ConversationId, ConversationSummary, GenerationParams,
+    ConversationEvent, MessageId, MessageSender, Participant, Role, UsageTotals, UserEvent, MAX_TITLE_LEN,
+};
 use dioxus::prelude::*;
 use futures_util::StreamExt;
-use katex_wasmbind::KaTeXOptions;
-use pulldown_cmark::{html, Options, Parser};
-
-#[cfg(feature = "web")]
-use wasm_bindgen::prelude::*;
-
-#[cfg(feature = "web")]
-#[wasm_bindgen(
-    inline_js = "export function highlight_all() { if (window.hljs) { window.hljs.highlightAll(); } }"
-)]
-extern "C" {
-    fn highlight_all();
-}
 
 pub const MAX_ATTACHMENT_SIZE: u64 = 1024 * 1024;
-
-fn markdown_to_html(text: &str) -> String {
-    let mut opts = Options::empty();
-    opts.insert(Options::ENABLE_TABLES);
-    opts.insert(Options::ENABLE_FOOTNOTES);
-    opts.insert(Options::ENABLE_STRIKETHROUGH);
-    opts.insert(Options::ENABLE_TASKLISTS);
-    let parser = Parser::new_ext(text, opts);
-    let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
-    html_output
-}
-
-#[cfg(feature = "web")]
-fn load_from_storage(key: &str) -> Option<String> {
-    web_sys::window()
-        .and_then(|w| w.local_storage().ok().flatten())
-        .and_then(|s| s.get_item(key).ok().flatten())
-}
-
-#[cfg(not(feature = "web"))]
-fn load_from_storage(_key: &str) -> Option<String> {
-    None
-}
+/// Lines the message input grows to before it scrolls.
+const MAX_INPUT_ROWS: usize = 8;
 
 fn render_model_selector(
     mut model: Signal<Option<ModelConfig>>,
@@ -79,8 +58,290 @@ fn render_model_selector(
     }
 }
 
-fn render_message_list(messages: &[ChatMessage], katex_opts: &KaTeXOptions) -> Element {
+/// Parse an optional numeric field, treating blank input as unset.
+fn parse_optional<T: std::str::FromStr>(value: &str) -> Option<T> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn render_generation_params(
+    mut params: Signal<GenerationParams>,
+    model: Option<ModelConfig>,
+) -> Element {
+    let defaults = model
+        .as_ref()
+        .map(|m| m.defaults.clone())
+        .unwrap_or_default();
+    let error = model
+        .as_ref()
+        .and_then(|m| params().or(&m.defaults).validate(m).err());
+    let current = params();
+    let temperature = current.temperature.map(|t| t.to_string()).unwrap_or_default();
+    let max_tokens = current.max_tokens.map(|n| n.to_string()).unwrap_or_default();
+    let top_p = current.top_p.map(|p| p.to_string()).unwrap_or_default();
+    let stop = current.stop.join(", ");
+    let default_temperature = defaults.temperature.map(|t| t.to_string()).unwrap_or_default();
+    let default_max_tokens = defaults.max_tokens.map(|n| n.to_string()).unwrap_or_default();
+    let default_top_p = defaults.top_p.map(|p| p.to_string()).unwrap_or_default();
+    let reasoning = model.as_ref().is_some_and(|m| m.capabilities.reasoning);
+    let anthropic = model.as_ref().is_some_and(|m| m.provider == Provider::Anthropic);
+    let effort = current
+        .reasoning_effort
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    let default_effort = defaults
+        .reasoning_effort
+        .map(|e| format!("Default ({e})"))
+        .unwrap_or_else(|| "Default".to_string());
+    let thinking_budget = current.thinking_budget.map(|n| n.to_string()).unwrap_or_default();
+    let system = current.system.clone().unwrap_or_default();
+
+    rsx! {
+        div { class: "grid grid-cols-2 gap-2 border border-gray-700 rounded p-2 mt-2 text-sm",
+            label { "Temperature" }
+            input {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                r#type: "number",
+                step: "0.1",
+                min: "0",
+                placeholder: "{default_temperature}",
+                value: "{temperature}",
+                oninput: move |e| params.with_mut(|p| p.temperature = parse_optional(&e.value())),
+            }
+            label { "Max tokens" }
+            input {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                r#type: "number",
+                min: "1",
+                placeholder: "{default_max_tokens}",
+                value: "{max_tokens}",
+                oninput: move |e| params.with_mut(|p| p.max_tokens = parse_optional(&e.value())),
+            }
+            label { "Top P" }
+            input {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                r#type: "number",
+                step: "0.05",
+                min: "0",
+                max: "1",
+                placeholder: "{default_top_p}",
+                value: "{top_p}",
+                oninput: move |e| params.with_mut(|p| p.top_p = parse_optional(&e.value())),
+            }
+            label { "Stop sequences" }
+            input {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                placeholder: "comma separated",
+                value: "{stop}",
+                oninput: move |e| {
+                    params.with_mut(|p| {
+                        p.stop = e
+                            .value()
+                            .split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect();
+                    })
+                },
+            }
+            if reasoning && !anthropic {
+                label { "Reasoning effort" }
+                select {
+                    class: "p-1 border border-gray-700 rounded bg-transparent",
+                    value: "{effort}",
+                    onchange: move |e| {
+                        params.with_mut(|p| p.reasoning_effort = e.value().parse::<ReasoningEffort>().ok())
+                    },
+                    option { value: "", "{default_effort}" }
+                    option { value: "low", "Low" }
+                    option { value: "medium", "Medium" }
+                    option { value: "high", "High" }
+                }
+            }
+            if reasoning && anthropic {
+                label { "Thinking budget" }
+                input {
+                    class: "p-1 border border-gray-700 rounded bg-transparent",
+                    r#type: "number",
+                    min: "{api::generation::MIN_THINKING_BUDGET}",
+                    placeholder: "off",
+                    value: "{thinking_budget}",
+                    oninput: move |e| params.with_mut(|p| p.thinking_budget = parse_optional(&e.value())),
+                }
+            }
+            label { "System prompt" }
+            textarea {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                rows: 2,
+                placeholder: "none",
+                value: "{system}",
+                oninput: move |e| params.with_mut(|p| p.system = Some(e.value()).filter(|s| !s.is_empty())),
+            }
+            button {
+                class: "col-span-2 underline text-left",
+                onclick: move |_| params.set(GenerationParams::default()),
+                "Reset to model defaults"
+            }
+            if let Some(error) = error {
+                p { class: "col-span-2 text-red-500", "{error}" }
+            }
+        }
+    }
+}
+
+/// What can be done with the messages of the open conversation.
+pub(super) struct MessageActions {
+    role: Role,
+    user: Option<String>,
+    /// Messages before this index are stored on the server; the rest are still pending.
+    confirmed: usize,
+    /// Message being edited and its draft text.
+    editing: Signal<Option<(MessageId, String)>>,
+    edit: EventHandler<(MessageId, String)>,
+    delete: EventHandler<MessageId>,
+    quote: EventHandler<String>,
+}
+
+impl MessageActions {
+    /// Whether the user may edit or delete the message at `idx`, which goes with the
+    /// prompt it answers. Mirrors the server: owners may change anything, editors what
+    /// they wrote.
+    fn can_change(&self, messages: &[ChatMessage], idx: usize) -> bool {
+        let prompt = match messages[idx].sender {
+            MessageSender::User => Some(&messages[idx]),
+            MessageSender::AI => idx
+                .checked_sub(1)
+                .map(|i| &messages[i])
+                .filter(|m| m.sender == MessageSender::User),
+        };
+        idx < self.confirmed
+            && match self.role {
+                Role::Owner => true,
+                Role::Editor => prompt.is_some_and(|m| m.author.is_some() && m.author == self.user),
+                Role::Viewer => false,
+            }
+    }
+}
+
+fn render_message_actions(messages: &[ChatMessage], idx: usize, actions: &MessageActions) -> Element {
+    let msg = &messages[idx];
+    let text = msg.text.clone().unwrap_or_default();
+    let can_change = actions.can_change(messages, idx);
+    let mut editing = actions.editing;
+    let (delete, quote) = (actions.delete, actions.quote);
+    let id = msg.id;
+    rsx! {
+        div { class: "flex gap-2 mb-2 text-xs text-gray-400",
+            button {
+                class: "underline",
+                onclick: {
+                    let text = text.clone();
+                    move |_| {
+                        let text = serde_json::to_string(&text).unwrap_or_default();
+                        document::eval(&format!("navigator.clipboard.writeText({text})"));
+                    }
+                },
+                "Copy"
+            }
+            if actions.role >= Role::Editor {
+                button {
+                    class: "underline",
+                    onclick: {
+                        let text = text.clone();
+                        move |_| {
+                            let text = text.clone();
+                            async move {
+                                // Quote the selected part of this message, or all of it.
+                                let selection = document::eval(&format!(
+                                    "const s = window.getSelection();\
+                                     const msg = document.getElementById('msg-{id}');\
+                                     return s.rangeCount && msg?.contains(s.anchorNode) ? s.toString() : '';"
+                                ))
+                                .join::<String>()
+                                .await
+                                .unwrap_or_default();
+                                let snippet = if selection.trim().is_empty() { text } else { selection };
+                                quote.call(snippet);
+                            }
+                        }
+                    },
+                    "Quote"
+                }
+            }
+            if can_change && msg.sender == MessageSender::User {
+                button {
+                    class: "underline",
+                    onclick: {
+                        let text = text.clone();
+                        move |_| editing.set(Some((id, text.clone())))
+                    },
+                    "Edit"
+                }
+            }
+            if can_change {
+                button {
+                    class: "underline text-red-500",
+                    onclick: move |_| delete.call(id),
+                    "Delete"
+                }
+            }
+        }
+    }
+}
+
+fn render_message_editor(actions: &MessageActions) -> Element {
+    let mut editing = actions.editing;
+    let edit = actions.edit;
+    let Some((id, draft)) = editing() else {
+        return rsx! {};
+    };
+    rsx! {
+        div { class: "flex flex-col gap-1 mb-2 w-96 max-w-full",
+            textarea {
+                class: "p-1 border border-gray-700 rounded bg-transparent",
+                rows: 4,
+                value: "{draft}",
+                oninput: move |e| editing.set(Some((id, e.value()))),
+            }
+            div { class: "flex gap-2 text-sm",
+                button {
+                    class: "px-2 bg-blue-500 text-white rounded hover:bg-blue-600",
+                    disabled: draft.trim().is_empty(),
+                    onclick: {
+                        let draft = draft.clone();
+                        move |_| {
+                            editing.set(None);
+                            edit.call((id, draft.trim().to_string()));
+                        }
+                    },
+                    "Save and resend"
+                }
+                button { class: "underline", onclick: move |_| editing.set(None), "Cancel" }
+            }
+        }
+    }
+}
+
+/// Each message gets an `msg-{id}` id so search results can scroll to it; `highlight`
+/// outlines one message. Rows are keyed by message id, so a message keeps its rendered
+/// state when others are added or removed. Without `actions` the messages are read-only.
+pub(super) fn render_message_list(
+    messages: &[ChatMessage],
+    highlight: Option<MessageId>,
+    actions: Option<MessageActions>,
+) -> Element {
+    // Name who wrote what once more than one person has.
+    let mut authors: Vec<&str> = messages.iter().filter_map(|m| m.author.as_deref()).collect();
+    authors.sort();
+    authors.dedup();
+    let show_authors = authors.len() > 1;
+    let editing = actions.as_ref().and_then(|a| a.editing.read().as_ref().map(|(id, _)| *id));
     rsx! {
+        CodeBlockScripts {}
         div {
             class: if messages.is_empty() {
                 "flex-1 border border-gray-700 p-2 overflow-y-auto flex items-center justify-center"
@@ -90,19 +351,32 @@ fn render_message_list(messages: &[ChatMessage], katex_opts: &KaTeXOptions) -> E
             if !messages.is_empty() {
                 for (idx, msg) in messages.iter().enumerate() {
                     div {
-                        key: "{idx}",
-                        class: if msg.sender == MessageSender::User {
-                            "flex justify-end"
-                        } else {
-                            "flex justify-start"
+                        key: "{msg.id}",
+                        id: "msg-{msg.id}",
+                        class: match (msg.sender == MessageSender::User, highlight == Some(msg.id)) {
+                            (true, false) => "flex flex-col items-end",
+                            (false, false) => "flex flex-col items-start",
+                            (true, true) => "flex flex-col items-end ring-2 ring-yellow-400 rounded",
+                            (false, true) => "flex flex-col items-start ring-2 ring-yellow-400 rounded",
                         },
-                        div {
-                            class: if msg.sender == MessageSender::User {
-                                "dark:bg-gray-700 dark:text-white rounded px-2 py-1 mb-2 max-w-md"
-                            } else {
-                                "mb-2 max-w-md"
-                            },
-                            {render_message(msg, katex_opts)}
+                        if let Some(actions) = actions.as_ref().filter(|_| editing == Some(msg.id)) {
+                            {render_message_editor(actions)}
+                        } else {
+                            div {
+                                class: match (msg.sender == MessageSender::User, actions.is_some()) {
+                                    (true, false) => "dark:bg-gray-700 dark:text-white rounded px-2 py-1 mb-2 max-w-md",
+                                    (true, true) => "dark:bg-gray-700 dark:text-white rounded px-2 py-1 mb-1 max-w-md",
+                                    (false, false) => "mb-2 max-w-md",
+                                    (false, true) => "mb-1 max-w-md",
+                                },
+                                if let Some(author) = msg.author.as_ref().filter(|_| show_authors) {
+                                    p { class: "text-xs text-gray-400", "{author}" }
+                                }
+                                {render_message(msg)}
+                            }
+                            if let Some(actions) = actions.as_ref() {
+                                {render_message_actions(messages, idx, actions)}
+                            }
                         }
                     }
                 }
@@ -115,21 +389,26 @@ fn render_message_input(
     mut input: Signal<String>,
     on_send: Callback<()>,
     attachment: Signal<Option<Attachment>>,
+    mut chat_error: Signal<Option<(String, bool)>>,
     is_empty: bool,
 ) -> Element {
+    // Grow with the text up to a few lines, then scroll.
+    let rows = input.read().split('\n').count().clamp(1, MAX_INPUT_ROWS);
     rsx! {
         div {
-            class: if is_empty { "flex gap-2 mt-4" } else { "flex gap-2 mt-2" },
-            input {
-                class: "flex-1 p-1 border border-gray-700 rounded",
+            class: if is_empty { "flex items-end gap-2 mt-4" } else { "flex items-end gap-2 mt-2" },
+            textarea {
+                class: "flex-1 p-1 border border-gray-700 rounded bg-transparent resize-none",
+                rows: "{rows}",
                 value: "{input}",
                 oninput: move |e| input.set(e.value()),
                 onkeydown: move |e| {
-                    if e.key() == Key::Enter {
+                    if e.key() == Key::Enter && !e.modifiers().shift() && !e.is_composing() {
+                        e.prevent_default();
                         on_send.call(());
                     }
                 },
-                placeholder: "Type a message...",
+                placeholder: "Message, or / for commands. Shift+Enter for a new line.",
             }
             input {
                 r#type: "file",
@@ -138,11 +417,21 @@ fn render_message_input(
                   async move {
                     if let Some(file_engine) = e.files() {
                         let files = file_engine.files();
-                        if let Some(file_name) = files.get(0) {
-                            let mut attach_sig = attachment.clone();
+                        if let Some(file_name) = files.first() {
+                            let mut attach_sig = attachment;
                               let file = file_engine.read_file_to_string(&(file_name.clone())).await;
+                              if file.as_ref().is_some_and(|f| f.len() as u64 > MAX_ATTACHMENT_SIZE) {
+                                  chat_error.set(Some((
+                                      format!(
+                                          "{file_name} is too large to attach. Files can be up to {} MB.",
+                                          MAX_ATTACHMENT_SIZE / (1024 * 1024)
+                                      ),
+                                      false,
+                                  )));
+                                  return;
+                              }
                               if let Some(file) = file {
-                              let content_type = match file_name.split('.').last() {
+                              let content_type = match file_name.split('.').next_back() {
                                 Some("pdf") => "application/pdf".to_string(),
                                 Some("png") => "image/png".to_string(),
                                 Some("jpg") => "image/jpeg".to_string(),
@@ -173,25 +462,46 @@ fn render_message_input(
     }
 }
 
-fn render_message(msg: &ChatMessage, opts: &KaTeXOptions) -> Element {
-    let text_content = msg.text.as_ref().map(|text| {
-        let is_math = (text.starts_with("$$") && text.ends_with("$$"))
-            || (text.starts_with('$') && text.ends_with('$'));
+/// Who else has the conversation open and who of them is typing. The caller is in
+/// `participants` too, under their own `name`.
+fn render_presence(participants: &[Participant], name: &str) -> Element {
+    let mut others = participants.to_vec();
+    if let Some(me) = others.iter().position(|p| p.name == name) {
+        others.remove(me);
+    }
+    let names = |typing_only: bool| {
+        others
+            .iter()
+            .filter(|p| p.typing || !typing_only)
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let here = names(false);
+    let typing = names(true);
+    rsx! {
+        if !here.is_empty() {
+            p { class: "mt-1 text-xs text-gray-400",
+                "Also here: {here}"
+                if !typing.is_empty() {
+                    " · {typing} typing…"
+                }
+            }
+        }
+    }
+}
 
-        if is_math {
-            let expr = if text.starts_with("$$") {
-                text[2..text.len() - 2].trim()
-            } else {
-                text[1..text.len() - 1].trim()
-            };
+fn render_message(msg: &ChatMessage) -> Element {
+    let text_content = msg.text.as_ref().map(|text| {
+        rsx! { Markdown { text: text.clone() } }
+    });
 
-            let str = opts.render(expr);
-            rsx! {
-              div {dangerous_inner_html: "{str}"}
+    let reasoning = msg.reasoning.as_ref().map(|trace| {
+        rsx! {
+            details { class: "mb-1 text-sm text-gray-400",
+                summary { class: "cursor-pointer select-none", "Thinking" }
+                div { class: "whitespace-pre-wrap border-l border-gray-600 pl-2 mt-1", "{trace}" }
             }
-        } else {
-            let html = markdown_to_html(text);
-            rsx! { div { dangerous_inner_html: "{html}" } }
         }
     });
 
@@ -204,50 +514,236 @@ fn render_message(msg: &ChatMessage, opts: &KaTeXOptions) -> Element {
         }
     });
 
+    let usage = msg.usage.as_ref().map(|usage| {
+        let cost = usage.cost.map(|c| format!(" · ${c:.4}")).unwrap_or_default();
+        rsx! {
+            div { class: "text-xs text-gray-500",
+                "{usage.prompt_tokens} in · {usage.completion_tokens} out{cost}"
+            }
+        }
+    });
+
     rsx! {
         div { class: "message-content",
+            {reasoning}
             {text_content}
             {attachment}
+            {usage}
         }
     }
 }
 
+fn render_search_hits(hits: &[SearchHit], on_select: impl FnMut(SearchHit) + Copy + 'static) -> Element {
+    if hits.is_empty() {
+        return rsx! {};
+    }
+    rsx! {
+        p { class: "mt-2 text-xs uppercase text-gray-400", "Messages" }
+        ul { class: "max-h-64 overflow-y-auto list-none p-0 text-sm",
+            for hit in hits.iter().cloned() {
+                li {
+                    key: "{hit.conv_id}-{hit.message_index}",
+                    class: "p-1 cursor-pointer hover:bg-gray-800",
+                    onclick: {
+                        let hit = hit.clone();
+                        let mut on_select = on_select;
+                        move |_| on_select(hit.clone())
+                    },
+                    p { class: "text-xs text-gray-400 truncate",
+                        {hit.conv_title.clone().unwrap_or_else(|| "New chat".to_string())}
+                    }
+                    p {
+                        for (i, part) in hit.snippet.iter().enumerate() {
+                            if part.highlight {
+                                mark { key: "{i}", class: "bg-yellow-400 text-black", "{part.text}" }
+                            } else {
+                                span { key: "{i}", "{part.text}" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Which conversations the sidebar lists.
+#[derive(Debug, Clone, PartialEq)]
+enum SidebarView {
+    All,
+    Folder(String),
+    Archived,
+}
+
+impl SidebarView {
+    fn from_value(value: &str) -> Self {
+        match value {
+            "archived" => SidebarView::Archived,
+            _ => match value.strip_prefix("folder:") {
+                Some(folder) => SidebarView::Folder(folder.to_string()),
+                None => SidebarView::All,
+            },
+        }
+    }
+
+    fn includes(&self, conv: &ConversationSummary) -> bool {
+        match self {
+            SidebarView::All => !conv.archived,
+            SidebarView::Folder(folder) => !conv.archived && conv.folder.as_ref() == Some(folder),
+            SidebarView::Archived => conv.archived,
+        }
+    }
+}
+
+/// Handlers for the controls under the selected conversation.
+struct ConversationActions {
+    toggle_pinned: EventHandler<ConversationSummary>,
+    toggle_archived: EventHandler<ConversationSummary>,
+    move_to_folder: EventHandler<ConversationSummary>,
+    delete: EventHandler<ConversationSummary>,
+}
+
+fn render_conversation_actions(conv: &ConversationSummary, actions: ConversationActions) -> Element {
+    let action = |handler: EventHandler<ConversationSummary>| {
+        let conv = conv.clone();
+        move |e: Event<MouseData>| {
+            e.stop_propagation();
+            handler.call(conv.clone());
+        }
+    };
+    rsx! {
+        div { class: "flex flex-wrap gap-2 text-xs text-gray-400",
+            button { class: "underline", onclick: action(actions.toggle_pinned),
+                if conv.pinned { "Unpin" } else { "Pin" }
+            }
+            button { class: "underline", onclick: action(actions.toggle_archived),
+                if conv.archived { "Unarchive" } else { "Archive" }
+            }
+            button { class: "underline", onclick: action(actions.move_to_folder),
+                if conv.folder.is_some() { "Move" } else { "Add to folder" }
+            }
+            button { class: "underline text-red-500", onclick: action(actions.delete), "Delete" }
+        }
+    }
+}
+
+fn conversation_title(conv: &ConversationSummary) -> String {
+    conv.title.clone().unwrap_or_else(|| "New chat".to_string())
+}
+
+/// Move a conversation to the top of its group, pinned or not, as the server orders them.
+fn move_to_top(list: &mut Vec<ConversationSummary>, conv: ConversationSummary) {
+    list.retain(|c| c.id != conv.id);
+    let at = if conv.pinned {
+        0
+    } else {
+        list.iter().take_while(|c| c.pinned).count()
+    };
+    list.insert(at, conv);
+}
+
+/// Apply a change made in this or another tab, or on another device, to the sidebar.
+fn apply_user_event(list: &mut Vec<ConversationSummary>, event: UserEvent) {
+    match event {
+        UserEvent::ConversationCreated(conv) | UserEvent::ConversationUpdated(conv) => {
+            match list.iter_mut().find(|c| c.id == conv.id) {
+                Some(existing) if existing.pinned == conv.pinned => *existing = conv,
+                _ => move_to_top(list, conv),
+            }
+        }
+        UserEvent::ConversationDeleted(id) => list.retain(|c| c.id != id),
+        UserEvent::MessageAdded(id) | UserEvent::MessagesChanged(id) => {
+            if let Some(conv) = list.iter().find(|c| c.id == id).cloned() {
+                move_to_top(list, conv);
+            }
+        }
+    }
+}
+
+fn set_title(mut conversations: Signal<Vec<ConversationSummary>>, conv_id: ConversationId, title: String) {
+    if let Some(conv) = conversations.write().iter_mut().find(|c| c.id == conv_id) {
+        conv.title = Some(title);
+    }
+}
+
 #[component]
-fn ChatBase(id: Option<usize>) -> Element {
-    let mut conversations = use_signal(Vec::<usize>::new);
+fn ChatBase(id: Option<ConversationId>) -> Element {
+    let mut conversations = use_signal(Vec::<ConversationSummary>::new);
     let mut current = use_signal(|| id);
-    let mut messages = use_signal(Vec::<ChatMessage>::new);
+    // The open conversation as this device knows it, including messages not sent yet.
+    let mut local = use_signal(LocalConversation::default);
+    let mut loaded = use_signal(|| None::<ConversationId>);
+    let messages = use_memo(move || local.read().messages());
+    // Who else has the conversation open, and whether they are typing.
+    let mut participants = use_signal(Vec::<Participant>::new);
+    let mut show_members = use_signal(|| false);
+    // Prompt of the last message queued offline, answered once the connection is back.
+    let mut unanswered = use_signal(|| None::<String>);
     let mut attachment = use_signal(|| None::<Attachment>);
     let mut input = use_signal(String::new);
     let mut search = use_signal(String::new);
+    // Conversation being renamed in the sidebar and its draft title.
+    let mut renaming = use_signal(|| None::<(ConversationId, String)>);
+    // Conversation being moved to a folder and the folder name typed so far.
+    let mut moving = use_signal(|| None::<(ConversationId, String)>);
+    let mut sidebar_view = use_signal(|| SidebarView::All);
+    // Last deleted conversation, offered for undo.
+    let mut deleted = use_signal(|| None::<ConversationSummary>);
+    // Message to scroll to after picking a search result.
+    let mut scroll_target = use_signal(|| None::<usize>);
     let mut model = use_signal(|| Some(ModelConfig::default()));
     let mut all_models = use_signal(|| ModelConfig::load_models().unwrap_or_default());
     let mut use_web_search = use_signal(|| false);
     let mut use_image_gen = use_signal(|| false);
-    let katex_opts = KaTeXOptions::inline_mode();
-    let api_key = use_signal(|| load_from_storage("api_key").unwrap_or_default());
-    let mut last_len = use_signal(|| 0usize);
+    let mut params = use_signal(GenerationParams::default);
+    let mut show_advanced = use_signal(|| false);
+    let mut show_share = use_signal(|| false);
+    let session = use_session();
+    // Error message for the last request, and whether adding a key in Settings would fix it.
+    let mut chat_error = use_signal(|| None::<(String, bool)>);
+    let editing = use_signal(|| None::<(MessageId, String)>);
+    // Compare mode sends each prompt to every model in `compare_models`.
+    let mut compare_mode = use_signal(|| false);
+    let compare_models = use_signal(Vec::<ModelConfig>::new);
+    let mut comparison = use_signal(|| None::<Comparison>);
+    // Voice mode as set in Settings. Hands-free listens again once a reply has been
+    // read and sends what it hears.
+    let voice = use_signal(VoiceSettings::load);
+    let mut dictation = use_signal(|| None::<Dictation>);
+    let mut hands_free = use_signal(|| false);
+    let mut speaking = use_signal(|| false);
 
-    let conv_res =
-        use_resource(|| async move { api::list_conversations().await.unwrap_or_default() });
+    // `None` until the session is known, so an empty list really means no conversations.
+    let mut conv_res = use_resource(move || async move {
+        let token = session()?;
+        match api::list_conversations(token.clone()).await {
+            Ok(list) => {
+                sync::save_conversations(&token, &list).await;
+                Some(list)
+            }
+            // Offline: show the list from the last visit.
+            Err(_) => sync::load_conversations(&token).await,
+        }
+    });
 
     let current_id = use_memo(move || {
         if let Some(id) = id {
             Some(id)
-        } else if let Some(list) = &*conv_res.read_unchecked() {
-            list.first().copied()
+        } else if let Some(Some(list)) = &*conv_res.read_unchecked() {
+            list.first().map(|c| c.id)
         } else {
             None
         }
     });
 
     use_effect(move || {
-        if let Some(list) = &*conv_res.read_unchecked() {
+        if let Some(Some(list)) = &*conv_res.read_unchecked() {
             conversations.set(list.clone());
 
             if list.is_empty() {
                 spawn(async move {
-                    if let Ok(id) = api::create_conversation().await {
+                    let token = session().unwrap_or_default();
+                    if let Ok(id) = api::create_conversation(token).await {
                         current.set(Some(id));
                     }
                 });
@@ -257,25 +753,256 @@ fn ChatBase(id: Option<usize>) -> Element {
 
     use_effect(move || {
         if let Some(id) = current_id() {
-            current.set(Some(id));
+            if current.peek().is_none() {
+                current.set(Some(id));
+            }
         }
     });
 
-    let current_id = current();
-    let msg_res = use_resource(move || {
-        let current_id = current_id;
-        async move {
-            if let Some(cid) = current_id {
-                api::get_messages(cid).await.unwrap_or_default()
+    // Bring back what was typed in a conversation when switching to it, and keep it
+    // as it changes.
+    use_effect(move || {
+        if let (Some(conv_id), Some(token)) = (current(), session()) {
+            input.set(composer::load_draft(&token, conv_id));
+        }
+    });
+    use_effect(move || {
+        let text = input();
+        if let (Some(conv_id), Some(token)) = (*current.peek(), session.peek().clone()) {
+            composer::save_draft(&token, conv_id, &text);
+        }
+    });
+
+    // Pick up a reply the server has stored, and title the conversation after it.
+    let finish_reply = use_callback(move |conv_id: ConversationId| {
+        spawn(async move {
+            let session_token = session().unwrap_or_default();
+            if let Err(e) = sync::sync(session_token.clone(), conv_id, local).await {
+                log::error!("Failed to sync: {}", e);
+            }
+
+            // Set before the next await, so hands-free does not start listening first.
+            let reply = local
+                .read()
+                .confirmed
+                .last()
+                .filter(|m| m.sender == MessageSender::AI)
+                .and_then(|m| m.text.clone());
+            if let Some(text) = reply.filter(|_| voice.peek().enabled) {
+                if let Some(listening) = &*dictation.peek() {
+                    listening.stop();
+                }
+                speaking.set(true);
+                spawn(async move {
+                    let settings = voice();
+                    speech::speak(&markdown::plain_text(&text), &settings).await;
+                    speaking.set(false);
+                });
+            }
+
+            let untitled = conversations
+                .read()
+                .iter()
+                .any(|c| c.id == conv_id && c.title.is_none());
+            if untitled {
+                if let Ok(Some(title)) = api::generate_title(session_token.clone(), conv_id).await {
+                    set_title(conversations, conv_id, title);
+                }
+            }
+            // The conversation moves to the top of the sidebar.
+            conv_res.restart();
+        });
+    });
+
+    // Ask the model to answer `prompt` and add the reply to the conversation.
+    let respond = use_callback(move |(conv_id, text): (ConversationId, String)| {
+        // `/image` asks for an image for this prompt only.
+        let generate_image = std::mem::take(&mut *use_image_gen.write());
+        spawn(async move {
+            let session_token = session().unwrap_or_default();
+            let current_model = match model() {
+                Some(m) => m,
+                None => {
+                    log::error!("No model selected");
+                    return;
+                }
+            };
+
+            if current_model.capabilities.image_generation && generate_image {
+                if let Ok(image_url) = api::generate_image(text.clone()).await {
+                    let image_message = ChatMessage {
+                        id: MessageId::nil(),
+                        text: Some(format!("Generated image for: {}", text)),
+                        attachment: Some(Attachment {
+                            filename: "generated_image.png".to_string(),
+                            content_type: "image/png".to_string(),
+                            data: image_url,
+                        }),
+                        sender: MessageSender::AI,
+                        author: None,
+                        reasoning: None,
+                        usage: None,
+                    };
+                    if let Err(e) = api::send_message(session_token.clone(), conv_id, image_message).await {
+                        log::error!("Failed to send generated image: {}", e);
+                    }
+                }
             } else {
-                Vec::new()
+                // The server attaches the completion's usage to the reply stored under this id.
+                let reply_id = MessageId::new_v4();
+                match api::chat_completion(
+                    session_token.clone(),
+                    text.clone(),
+                    current_model.name.clone(),
+                    params(),
+                    reply_id,
+                )
+                .await
+                {
+                    Ok(ai_response) => {
+                        let ai_message = ChatMessage {
+                            id: reply_id,
+                            text: Some(ai_response.text),
+                            attachment: None,
+                            sender: MessageSender::AI,
+                            author: None,
+                            reasoning: ai_response.reasoning,
+                            usage: None,
+                        };
+                        if let Err(e) = api::send_message(session_token.clone(), conv_id, ai_message).await {
+                            log::error!("Failed to send AI response: {}", e);
+                        }
+                    }
+                    Err(ServerFnError::WrappedServerError(e)) => {
+                        let fixable = matches!(
+                            e,
+                            ChatError::NoApiKey(_) | ChatError::QuotaExceeded { .. }
+                        );
+                        chat_error.set(Some((e.message(), fixable)));
+                    }
+                    Err(e) => {
+                        log::error!("Chat completion failed: {}", e);
+                        chat_error.set(Some((
+                            "Something went wrong. Please try again.".into(),
+                            false,
+                        )));
+                    }
+                }
             }
+
+            finish_reply(conv_id);
+        });
+    });
+
+    // Show the cached copy, catch up with the server, then follow its changes.
+    let mut sync_res = use_resource(move || async move {
+        participants.set(Vec::new());
+        let Some(conv_id) = current() else { return };
+        let Some(token) = session() else { return };
+        if *loaded.peek() != Some(conv_id) {
+            local.set(sync::load_conversation(&token, conv_id).await);
+            loaded.set(Some(conv_id));
         }
+        loop {
+            if sync::sync(token.clone(), conv_id, local).await.is_err() {
+                return;
+            }
+            let since = local.peek().version;
+            let Ok(stream) = api::stream_messages(token.clone(), conv_id, since).await else {
+                return;
+            };
+            let mut events = std::pin::pin!(sync::json_lines::<ConversationEvent>(stream));
+            let mut missed = false;
+            while let Some(event) = events.next().await {
+                match event {
+                    ConversationEvent::Changed(change) => {
+                        if !local.write().apply(change) {
+                            missed = true;
+                            break;
+                        }
+                        sync::save_conversation(&token, conv_id, local).await;
+                    }
+                    ConversationEvent::Presence(list) => participants.set(list),
+                }
+            }
+            if !missed {
+                return;
+            }
+        }
+    });
+
+    let user = use_resource(move || async move {
+        api::current_user(session()?).await.ok().flatten()
     });
 
+    // Let the others in the conversation know when the caller starts or stops typing.
+    let typing = use_memo(move || !input().trim().is_empty());
     use_effect(move || {
-        if let Some(list) = &*msg_res.read_unchecked() {
-            messages.set(list.clone());
+        let typing = typing();
+        let (Some(conv_id), Some(token)) = (*current.peek(), session.peek().clone()) else {
+            return;
+        };
+        if participants.peek().len() > 1 {
+            spawn(async move {
+                let _ = api::members::set_typing(token, conv_id, typing).await;
+            });
+        }
+    });
+
+    // Keep the sidebar in step with the caller's other tabs and devices.
+    let _events = use_resource(move || async move {
+        let Some(token) = session() else { return };
+        let Ok(stream) = api::events::stream_events(token).await else {
+            return;
+        };
+        let mut events = std::pin::pin!(sync::json_lines::<UserEvent>(stream));
+        while let Some(event) = events.next().await {
+            if let UserEvent::ConversationDeleted(id) = event {
+                if *current.peek() == Some(id) {
+                    let next = conversations
+                        .peek()
+                        .iter()
+                        .find(|c| c.id != id && !c.archived)
+                        .map(|c| c.id);
+                    current.set(next);
+                }
+            }
+            apply_user_event(&mut conversations.write(), event);
+        }
+    });
+
+    // Send what was queued and answer it when the browser comes back online.
+    use_future(move || async move {
+        let mut online = document::eval("window.addEventListener('online', () => dioxus.send(true));");
+        while online.recv::<bool>().await.is_ok() {
+            let (Some(conv_id), Some(token)) = (current(), session()) else {
+                continue;
+            };
+            match sync::sync(token, conv_id, local).await {
+                Ok(()) => {
+                    sync_res.restart();
+                    if let Some(prompt) = unanswered.take() {
+                        respond((conv_id, prompt));
+                    }
+                }
+                Err(e) if sync::is_gone(&e) => unanswered.set(None),
+                Err(_) => {}
+            }
+        }
+    });
+
+    // Refetch the running totals whenever the conversation or its length changes.
+    let conv_usage = use_resource(move || {
+        let current_id = current();
+        let token = session();
+        let _len = messages().len();
+        async move {
+            match (current_id, token) {
+                (Some(cid), Some(token)) => api::get_conversation_usage(token, cid)
+                    .await
+                    .unwrap_or_default(),
+                _ => UsageTotals::default(),
+            }
         }
     });
 
@@ -302,56 +1029,63 @@ fn ChatBase(id: Option<usize>) -> Element {
         }
     });
 
-    use_effect(move || {
-        let current_id = current();
-        let mut messages = messages.clone();
-
-        spawn(async move {
-            if let Some(cid) = current_id {
-                if let Ok(stream) = api::stream_messages(cid, messages().len()).await {
-                    let mut inner = stream.into_inner();
-                    while let Some(Ok(chunk)) = inner.next().await {
-                        messages.with_mut(|msgs| {
-                            if let Some(last_msg) = msgs.last_mut() {
-                                if last_msg.sender == MessageSender::AI {
-                                    if let Some(text) = &mut last_msg.text {
-                                        text.push_str(&chunk);
-                                    } else {
-                                        last_msg.text = Some(chunk);
-                                    }
-                                    return;
-                                }
-                            }
-
-                            msgs.push(ChatMessage {
-                                text: Some(chunk),
-                                attachment: None,
-                                sender: MessageSender::AI,
-                            });
-                        });
+    let on_send: EventHandler<()> = Callback::new(move |()| {
+        let mut text = input().trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+        let mut generate_image = false;
+        match composer::parse_command(&text) {
+            None => {}
+            Some(Err(usage)) => {
+                chat_error.set(Some((usage, false)));
+                return;
+            }
+            Some(Ok(Command::Model(name))) => {
+                match composer::find_model(&all_models(), &name) {
+                    Some(m) => {
+                        model.set(Some(m));
+                        input.set(String::new());
+                        chat_error.set(None);
                     }
+                    None => chat_error.set(Some((format!("No model matches \"{name}\""), false))),
                 }
+                return;
             }
-        });
-    });
-
-    use_effect(move || {
-        if messages().len() > last_len() {
-            if let Some(text) = messages().last() {
-                speak(text.text.as_ref().unwrap());
+            Some(Ok(Command::System(prompt))) => {
+                params.with_mut(|p| p.system = Some(prompt).filter(|p| !p.is_empty()));
+                input.set(String::new());
+                chat_error.set(None);
+                return;
+            }
+            Some(Ok(Command::Search(query))) => {
+                search.set(query);
+                input.set(String::new());
+                chat_error.set(None);
+                return;
+            }
+            Some(Ok(Command::Image(prompt))) => {
+                if !model().is_some_and(|m| m.capabilities.image_generation) {
+                    chat_error.set(Some(("The selected model cannot generate images".to_string(), false)));
+                    return;
+                }
+                generate_image = true;
+                text = prompt;
             }
-            last_len.set(messages().len());
         }
-    });
-
-    #[cfg(feature = "web")]
-    use_effect(move || {
-        highlight_all();
-    });
-
-    let on_send: EventHandler<()> = Callback::new(move |()| {
-        let text = input().trim().to_string();
-        if text.is_empty() {
+        if let Some(m) = model() {
+            if let Err(e) = params().or(&m.defaults).validate(&m) {
+                chat_error.set(Some((e.to_string(), false)));
+                show_advanced.set(true);
+                return;
+            }
+        }
+        let compared = Some(compare_models()).filter(|_| compare_mode() && !generate_image);
+        if compared.as_ref().is_some_and(|models| models.len() < compare::MIN_COMPARED) {
+            chat_error.set(Some((
+                format!("Pick at least {} models to compare.", compare::MIN_COMPARED),
+                false,
+            )));
             return;
         }
 
@@ -360,106 +1094,263 @@ fn ChatBase(id: Option<usize>) -> Element {
 
         input.set(String::new());
         attachment.set(None);
+        chat_error.set(None);
         use_web_search.set(false);
-        use_image_gen.set(false);
 
-        let user_message = ChatMessage {
+        local.write().pending.push(ChatMessage {
+            id: MessageId::new_v4(),
             text: Some(text.clone()),
-            attachment: None,
+            attachment: current_attachment,
             sender: MessageSender::User,
-        };
-
-        messages.with_mut(|msgs| {
-            msgs.push(user_message);
+            author: None,
+            reasoning: None,
+            usage: None,
         });
 
         spawn(async move {
             let Some(conv_id) = current_conv else { return };
-
-            let user_message = ChatMessage {
-                text: Some(text.clone()),
-                attachment: current_attachment,
-                sender: MessageSender::User,
-            };
-
-            if let Err(e) = api::send_message(conv_id, user_message.clone()).await {
-                log::error!("Failed to send user message: {}", e);
-                return;
-            }
-
-            if let Ok(all) = api::get_messages(conv_id).await {
-                messages.set(all);
+            let token = session().unwrap_or_default();
+            sync::save_conversation(&token, conv_id, local).await;
+            match sync::sync(token, conv_id, local).await {
+                Ok(()) => {}
+                Err(e) if sync::is_gone(&e) => {
+                    chat_error.set(Some((
+                        "This conversation is no longer on the server, so the message was not sent.".into(),
+                        false,
+                    )));
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Message queued until the connection is back: {}", e);
+                    unanswered.set(Some(text));
+                    return;
+                }
             }
-
-            let current_model = match model() {
-                Some(m) => m,
+            match compared {
+                Some(models) => {
+                    let token = session().unwrap_or_default();
+                    Comparison::start(comparison, token, conv_id, text, models, params());
+                }
                 None => {
-                    log::error!("No model selected");
-                    return;
+                    use_image_gen.set(generate_image);
+                    respond((conv_id, text));
                 }
-            };
+            }
+        });
+    });
 
-            if current_model.capabilities.image_generation && *use_image_gen.read() {
-                if let Ok(image_url) = api::generate_image(text.clone()).await {
-                    let image_message = ChatMessage {
-                        text: Some(format!("Generated image for: {}", text)),
-                        attachment: Some(Attachment {
-                            filename: "generated_image.png".to_string(),
-                            content_type: "image/png".to_string(),
-                            data: image_url,
-                        }),
-                        sender: MessageSender::AI,
-                    };
-                    if let Err(e) = api::send_message(conv_id, image_message).await {
-                        log::error!("Failed to send generated image: {}", e);
+    // Dictate into the composer until the speaker pauses. Hands-free sends what it heard.
+    let listen = use_callback(move |()| {
+        let Some((listening, heard)) = speech::start_stt(&voice.peek().language) else {
+            hands_free.set(false);
+            chat_error.set(Some(("This browser cannot recognize speech.".to_string(), false)));
+            return;
+        };
+        dictation.set(Some(listening));
+        spawn(async move {
+            let result = heard.await;
+            dictation.set(None);
+            match result {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => {
+                    input.with_mut(|current| {
+                        if !current.trim().is_empty() {
+                            current.push(' ');
+                        }
+                        current.push_str(&text);
+                    });
+                    if hands_free() {
+                        on_send(());
                     }
                 }
-            } else {
-                let key_sel = api_key();
-                if let Ok(ai_response) =
-                    api::chat_completion(key_sel, text.clone(), current_model.clone()).await
-                {
-                    let ai_message = ChatMessage {
-                        text: Some(ai_response),
-                        attachment: None,
-                        sender: MessageSender::AI,
-                    };
-                    if let Err(e) = api::send_message(conv_id, ai_message).await {
-                        log::error!("Failed to send AI response: {}", e);
-                    }
+                Err(code) => {
+                    hands_free.set(false);
+                    chat_error.set(Some((format!("Dictation failed: {code}"), false)));
                 }
             }
+        });
+    });
+
+    // Hands-free: listen whenever nothing is being heard, read or answered.
+    use_effect(move || {
+        let answering = messages()
+            .last()
+            .is_some_and(|m| m.sender == MessageSender::User);
+        if hands_free()
+            && dictation.read().is_none()
+            && !speaking()
+            && !answering
+            && comparison.read().is_none()
+        {
+            listen(());
+        }
+    });
+
+    // Store the kept answer of a comparison as the reply.
+    let on_keep: EventHandler<usize> = Callback::new(move |index: usize| {
+        let Some(kept) = comparison.take() else { return };
+        kept.cancel();
+        let Some(answer) = kept.answers.get(index) else { return };
+        let (conv_id, reply) = (kept.conv_id, answer.to_message());
+        spawn(async move {
+            if let Err(e) = api::send_message(session().unwrap_or_default(), conv_id, reply).await {
+                log::error!("Failed to send the kept answer: {}", e);
+            }
+            finish_reply(conv_id);
+        });
+    });
+
+    let on_discard: EventHandler<()> = Callback::new(move |()| {
+        if let Some(discarded) = comparison.take() {
+            discarded.cancel();
+        }
+    });
+
+    // Replace a prompt and ask for a new answer to it.
+    let on_edit: EventHandler<(MessageId, String)> = Callback::new(move |(msg_id, text): (MessageId, String)| {
+        let Some(conv_id) = current() else { return };
+        spawn(async move {
+            let token = session().unwrap_or_default();
+            if let Err(e) = api::edit_message(token.clone(), conv_id, msg_id, text.clone()).await {
+                chat_error.set(Some((format!("Could not edit the message: {e}"), false)));
+                return;
+            }
+            if let Err(e) = sync::sync(token, conv_id, local).await {
+                log::warn!("Failed to sync after editing: {}", e);
+            }
+            respond((conv_id, text));
+        });
+    });
 
-            if let Ok(all) = api::get_messages(conv_id).await {
-                messages.set(all);
+    let on_delete_message: EventHandler<MessageId> = Callback::new(move |msg_id: MessageId| {
+        let Some(conv_id) = current() else { return };
+        spawn(async move {
+            let token = session().unwrap_or_default();
+            if let Err(e) = api::delete_message(token.clone(), conv_id, msg_id).await {
+                chat_error.set(Some((format!("Could not delete the message: {e}"), false)));
+                return;
             }
+            if let Err(e) = sync::sync(token, conv_id, local).await {
+                log::warn!("Failed to sync after deleting: {}", e);
+            }
+        });
+    });
 
-            input.set(String::new());
-            attachment.set(None);
+    // Add a snippet to the input as a Markdown quote.
+    let on_quote: EventHandler<String> = Callback::new(move |snippet: String| {
+        let quoted: String = snippet.trim().lines().map(|line| format!("> {line}\n")).collect();
+        input.with_mut(|text| {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(&quoted);
+            text.push('\n');
         });
     });
 
-    let on_new_conv = move |_| {
-        let mut convs = conversations.clone();
-        let mut cur = current.clone();
-        async move {
-            if let Ok(id) = api::create_conversation().await {
-                let mut list = convs();
-                list.push(id);
-                convs.set(list);
-                cur.set(Some(id));
+    let on_new_conv = move |_| async move {
+        if let Ok(id) = api::create_conversation(session().unwrap_or_default()).await {
+            current.set(Some(id));
+            sidebar_view.set(SidebarView::All);
+            conv_res.restart();
+        }
+    };
+
+    let on_delete = move |conv: ConversationSummary| async move {
+        let token = session().unwrap_or_default();
+        if api::delete_conversation(token, conv.id).await.is_ok() {
+            if current() == Some(conv.id) {
+                current.set(
+                    conversations
+                        .read()
+                        .iter()
+                        .find(|c| c.id != conv.id && !c.archived)
+                        .map(|c| c.id),
+                );
             }
+            deleted.set(Some(conv));
+            conv_res.restart();
         }
     };
 
-    let filtered: Vec<usize> = conversations()
+    let on_undo_delete = move |_| async move {
+        let Some(conv) = deleted() else { return };
+        let token = session().unwrap_or_default();
+        if api::restore_conversation(token, conv.id).await.is_ok() {
+            deleted.set(None);
+            current.set(Some(conv.id));
+            conv_res.restart();
+        }
+    };
+
+    let mut save_folder = move || {
+        let Some((conv_id, folder)) = moving() else {
+            return;
+        };
+        moving.set(None);
+        spawn(async move {
+            let token = session().unwrap_or_default();
+            if api::set_conversation_folder(token, conv_id, Some(folder))
+                .await
+                .is_ok()
+            {
+                conv_res.restart();
+            }
+        });
+    };
+
+    let message_hits = use_resource(move || async move {
+        let query = search();
+        match session() {
+            Some(token) if query.trim().chars().count() >= 2 => {
+                api::search::search_messages(token, query).await.unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    });
+
+    use_effect(move || {
+        let Some(idx) = scroll_target() else { return };
+        if let Some(id) = messages().get(idx).map(|m| m.id) {
+            document::eval(&format!(
+                "document.getElementById('msg-{id}')?.scrollIntoView({{block: 'center'}})"
+            ));
+        }
+    });
+
+    let role = conversations()
         .into_iter()
-        .filter(|cid| {
-            cid.to_string()
-                .to_lowercase()
-                .contains(&search().to_lowercase())
-        })
+        .find(|c| Some(c.id) == current())
+        .map(|c| c.role)
+        .unwrap_or_default();
+    let user_name = user().flatten();
+
+    let query = search().to_lowercase();
+    let view = sidebar_view();
+    let filtered: Vec<ConversationSummary> = conversations()
+        .into_iter()
+        .filter(|c| view.includes(c))
+        .filter(|c| conversation_title(c).to_lowercase().contains(&query))
+        .collect();
+    let mut folders: Vec<String> = conversations()
+        .into_iter()
+        .filter_map(|c| c.folder)
         .collect();
+    folders.sort();
+    folders.dedup();
+
+    let mut save_rename = move || {
+        let Some((conv_id, title)) = renaming() else {
+            return;
+        };
+        renaming.set(None);
+        spawn(async move {
+            let token = session().unwrap_or_default();
+            if let Ok(title) = api::rename_conversation(token, conv_id, title).await {
+                set_title(conversations, conv_id, title);
+            }
+        });
+    };
 
     let sidebar = rsx! {
         div { class: "w-48 border-r border-gray-700 p-2 flex flex-col h-full",
@@ -477,16 +1368,119 @@ fn ChatBase(id: Option<usize>) -> Element {
                 value: "{search}",
                 oninput: move |e| search.set(e.value()),
             }
+            select {
+                class: "mb-2 p-1 border rounded bg-transparent text-sm",
+                onchange: move |e| sidebar_view.set(SidebarView::from_value(&e.value())),
+                option { value: "all", selected: view == SidebarView::All, "All chats" }
+                for folder in folders.iter() {
+                    option {
+                        value: "folder:{folder}",
+                        selected: view == SidebarView::Folder(folder.clone()),
+                        "{folder}"
+                    }
+                }
+                option { value: "archived", selected: view == SidebarView::Archived, "Archived" }
+            }
+            datalist { id: "conversation-folders",
+                for folder in folders.iter() {
+                    option { value: "{folder}" }
+                }
+            }
+            if let Some(conv) = deleted() {
+                div { class: "mb-2 p-1 text-sm border border-gray-700 rounded",
+                    "Deleted “{conversation_title(&conv)}”. "
+                    button {
+                        class: "underline",
+                        onclick: on_undo_delete,
+                        "Undo"
+                    }
+                }
+            }
             ul { class: "flex-1 overflow-y-auto list-none p-0",
-                for cid in filtered.iter().cloned() {
+                for conv in filtered.iter().cloned() {
                     li {
-                        key: "{cid}",
-                        class: if Some(cid) == current() { "bg-gray-800 p-1" } else { "p-1" },
-                        onclick: move |_| current.set(Some(cid)),
-                        "Conversation {cid}"
+                        key: "{conv.id}",
+                        class: if Some(conv.id) == current() { "bg-gray-800 p-1" } else { "p-1" },
+                        title: "Double-click to rename",
+                        onclick: move |_| {
+                            current.set(Some(conv.id));
+                            scroll_target.set(None);
+                        },
+                        ondoubleclick: {
+                            let title = conversation_title(&conv);
+                            move |_| renaming.set(Some((conv.id, title.clone())))
+                        },
+                        if let Some((_, draft)) = renaming().filter(|(id, _)| *id == conv.id) {
+                            input {
+                                class: "w-full bg-transparent border-b border-gray-300 outline-none",
+                                value: "{draft}",
+                                maxlength: MAX_TITLE_LEN as i64,
+                                autofocus: true,
+                                oninput: move |e| renaming.set(Some((conv.id, e.value()))),
+                                onkeydown: move |e| match e.key() {
+                                    Key::Enter => save_rename(),
+                                    Key::Escape => renaming.set(None),
+                                    _ => {}
+                                },
+                                onblur: move |_| save_rename(),
+                            }
+                        } else {
+                            if conv.pinned {
+                                "📌 "
+                            }
+                            if conv.role != Role::Owner {
+                                "👥 "
+                            }
+                            "{conversation_title(&conv)}"
+                        }
+                        if let Some((_, folder)) = moving().filter(|(id, _)| *id == conv.id) {
+                            input {
+                                class: "w-full text-sm bg-transparent border-b border-gray-300 outline-none",
+                                list: "conversation-folders",
+                                placeholder: "Folder (blank to remove)",
+                                value: "{folder}",
+                                autofocus: true,
+                                oninput: move |e| moving.set(Some((conv.id, e.value()))),
+                                onkeydown: move |e| match e.key() {
+                                    Key::Enter => save_folder(),
+                                    Key::Escape => moving.set(None),
+                                    _ => {}
+                                },
+                                onblur: move |_| save_folder(),
+                            }
+                        } else if Some(conv.id) == current() && conv.role == Role::Owner {
+                            {render_conversation_actions(&conv, ConversationActions {
+                                toggle_pinned: EventHandler::new(move |conv: ConversationSummary| {
+                                    spawn(async move {
+                                        let token = session().unwrap_or_default();
+                                        if api::set_conversation_pinned(token, conv.id, !conv.pinned).await.is_ok() {
+                                            conv_res.restart();
+                                        }
+                                    });
+                                }),
+                                toggle_archived: EventHandler::new(move |conv: ConversationSummary| {
+                                    spawn(async move {
+                                        let token = session().unwrap_or_default();
+                                        if api::set_conversation_archived(token, conv.id, !conv.archived).await.is_ok() {
+                                            conv_res.restart();
+                                        }
+                                    });
+                                }),
+                                move_to_folder: EventHandler::new(move |conv: ConversationSummary| {
+                                    moving.set(Some((conv.id, conv.folder.unwrap_or_default())));
+                                }),
+                                delete: EventHandler::new(move |conv: ConversationSummary| {
+                                    spawn(on_delete(conv));
+                                }),
+                            })}
+                        }
                     }
                 }
             }
+            {render_search_hits(&message_hits().unwrap_or_default(), move |hit: SearchHit| {
+                current.set(Some(hit.conv_id));
+                scroll_target.set(Some(hit.message_index));
+            })}
             Link { to: Route::Settings {}, class: "mt-2 text-left text-sm", "Account" }
         }
     };
@@ -499,11 +1493,100 @@ fn ChatBase(id: Option<usize>) -> Element {
                 {sidebar}
                 div {
                     div {
-                      {render_model_selector(model.clone(), all_models())}
-                        {render_message_list(&messages(), &katex_opts)}
-                        {render_message_input(input.clone(), on_send, attachment.clone(), messages().is_empty())}
+                        div { class: "flex items-center gap-2",
+                            if !compare_mode() {
+                                {render_model_selector(model, all_models())}
+                            }
+                            button {
+                                class: "underline text-sm",
+                                onclick: move |_| show_advanced.set(!show_advanced()),
+                                if show_advanced() { "Hide advanced" } else { "Advanced" }
+                            }
+                            button {
+                                class: "underline text-sm",
+                                onclick: move |_| compare_mode.set(!compare_mode()),
+                                if compare_mode() { "Stop comparing" } else { "Compare models" }
+                            }
+                            if let Some(conv_id) = current() {
+                                ExportMenu { key: "{conv_id}", conv_id }
+                            }
+                        }
+                        if compare_mode() {
+                            {compare::render_compare_picker(all_models(), compare_models)}
+                        }
+                        if show_advanced() {
+                            {render_generation_params(params, model())}
+                        }
+                        {render_message_list(&messages(), scroll_target().and_then(|i| messages().get(i).map(|m| m.id)), Some(MessageActions {
+                            role,
+                            user: user_name.clone(),
+                            confirmed: local.read().confirmed.len(),
+                            editing,
+                            edit: on_edit,
+                            delete: on_delete_message,
+                            quote: on_quote,
+                        }))}
+                        if let Some((error, fixable)) = chat_error() {
+                            div { class: "mt-2 p-2 border border-red-500 text-red-500 rounded text-sm",
+                                "{error} "
+                                if fixable {
+                                    Link { to: Route::Settings {}, class: "underline", "Open Settings" }
+                                }
+                            }
+                        }
+                        {render_presence(&participants(), user_name.as_deref().unwrap_or("Guest"))}
+                        if let Some(open) = comparison().filter(|c| Some(c.conv_id) == current()) {
+                            {compare::render_comparison(&open, on_keep, on_discard)}
+                        } else if role >= Role::Editor {
+                            {render_message_input(input, on_send, attachment, chat_error, messages().is_empty())}
+                            {composer::render_command_hints(&input())}
+                        } else {
+                            p { class: "mt-2 text-sm text-gray-400",
+                                "You can read this conversation but not write in it."
+                            }
+                        }
                     }
                     div { class: "flex items-center gap-4 mt-2",
+                        if role >= Role::Editor {
+                            TemplatePicker { input }
+                        }
+                        if voice.read().enabled && role >= Role::Editor {
+                            button {
+                                class: "underline text-sm",
+                                onclick: move |_| {
+                                    let listening = dictation.peek().as_ref().map(Dictation::stop).is_some();
+                                    if listening {
+                                        hands_free.set(false);
+                                    } else {
+                                        listen(());
+                                    }
+                                },
+                                if dictation.read().is_some() { "Stop dictating" } else { "Dictate" }
+                            }
+                            label { class: "flex items-center gap-2 cursor-pointer text-sm text-gray-400",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: hands_free(),
+                                    onchange: move |e: Event<FormData>| {
+                                        let on = e.value().parse().unwrap_or(false);
+                                        hands_free.set(on);
+                                        if !on {
+                                            if let Some(listening) = &*dictation.peek() {
+                                                listening.stop();
+                                            }
+                                        }
+                                    },
+                                }
+                                "Hands-free"
+                            }
+                        }
+                        if speaking() {
+                            button {
+                                class: "underline text-sm",
+                                onclick: move |_| speech::stop_speaking(),
+                                "Stop reading"
+                            }
+                        }
                         label {
                             class: "flex items-center gap-2 cursor-pointer text-sm text-gray-400",
                             input {
@@ -534,12 +1617,20 @@ fn ChatBase(id: Option<usize>) -> Element {
                                 "Image Generation"
                             }
                         }
-                        Link {
-                            to: Route::ChatShare {
-                                id: current().unwrap_or(0),
-                            },
+                        if let Some(totals) = conv_usage().filter(|t| t.requests > 0) {
+                            span { class: "text-sm text-gray-400",
+                                "{totals.total_tokens()} tokens · ${totals.cost:.4}"
+                            }
+                        }
+                        button {
+                            class: "underline text-sm",
+                            onclick: move |_| show_share.set(!show_share()),
+                            if show_share() { "Hide sharing" } else { "Share" }
+                        }
+                        button {
                             class: "underline text-sm",
-                            "Share"
+                            onclick: move |_| show_members.set(!show_members()),
+                            if show_members() { "Hide members" } else { "Members" }
                         }
                         Link {
                             to: Route::Settings {},
@@ -547,6 +1638,12 @@ fn ChatBase(id: Option<usize>) -> Element {
                             "Settings"
                         }
                     }
+                    if let Some(conv_id) = current().filter(|_| show_share() && role == Role::Owner) {
+                        SharePanel { key: "{conv_id}", conv_id }
+                    }
+                    if let Some(conv_id) = current().filter(|_| show_members()) {
+                        MembersPanel { key: "{conv_id}", conv_id, role, user: user_name.clone() }
+                    }
                 }
             }
         }
@@ -561,6 +1658,6 @@ pub fn Chat() -> Element {
 }
 
 #[component]
-pub fn ChatShare(id: usize) -> Element {
+pub fn ChatShare(id: ConversationId) -> Element {
     rsx!(ChatBase { id: Some(id) })
 }
@@ -0,0 +1,239 @@
+//! Compare mode: one prompt answered by several models at once, side by side. The
+//! answer the user keeps becomes the conversation's reply; the others are dropped.
+//! The models are sent the prompt alone, without the conversation before it, and the
+//! picker says so.
+use api::model_config::ModelConfig;
+use api::streaming::CompletionChunk;
+use api::{ChatMessage, ConversationId, GenerationParams, MessageId, MessageSender, Usage};
+use dioxus::prelude::*;
+use futures_util::StreamExt;
+
+use super::markdown::Markdown;
+use crate::sync::json_lines;
+
+/// Fewest models a comparison makes sense with.
+pub(super) const MIN_COMPARED: usize = 2;
+
+/// One model's answer, filled in as it streams.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Answer {
+    /// Id the reply is stored under if this answer is kept, so the server can attach its usage.
+    pub id: MessageId,
+    pub model: ModelConfig,
+    pub text: String,
+    pub reasoning: Option<String>,
+    pub usage: Option<Usage>,
+    /// Nothing more is coming, whether the answer finished or failed.
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+impl Answer {
+    fn can_keep(&self) -> bool {
+        self.done && self.error.is_none() && !self.text.is_empty()
+    }
+
+    /// The answer as a reply in the conversation.
+    pub(super) fn to_message(&self) -> ChatMessage {
+        ChatMessage {
+            id: self.id,
+            text: Some(self.text.clone()),
+            attachment: None,
+            sender: MessageSender::AI,
+            author: None,
+            reasoning: self.reasoning.clone(),
+            usage: None,
+        }
+    }
+}
+
+/// A prompt sent to several models, waiting for the user to keep one answer.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Comparison {
+    pub conv_id: ConversationId,
+    pub answers: Vec<Answer>,
+    tasks: Vec<Task>,
+}
+
+impl Comparison {
+    /// Send `prompt` to every one of `models` at once and stream their answers into
+    /// `comparison`, replacing the comparison that was there.
+    pub(super) fn start(
+        mut comparison: Signal<Option<Comparison>>,
+        session: String,
+        conv_id: ConversationId,
+        prompt: String,
+        models: Vec<ModelConfig>,
+        params: GenerationParams,
+    ) {
+        if let Some(previous) = comparison.take() {
+            previous.cancel();
+        }
+        let answers: Vec<Answer> = models
+            .into_iter()
+            .map(|model| Answer {
+                id: MessageId::new_v4(),
+                model,
+                text: String::new(),
+                reasoning: None,
+                usage: None,
+                done: false,
+                error: None,
+            })
+            .collect();
+        let tasks = answers
+            .iter()
+            .enumerate()
+            .map(|(i, answer)| {
+                let (session, prompt, params) = (session.clone(), prompt.clone(), params.clone());
+                let (model, reply_id) = (answer.model.name.clone(), answer.id);
+                spawn(async move {
+                    match api::streaming::stream_completion(session, prompt, model, params, reply_id).await {
+                        Ok(stream) => {
+                            let mut chunks = json_lines::<CompletionChunk>(stream);
+                            while let Some(chunk) = chunks.next().await {
+                                update(comparison, i, |answer| match chunk {
+                                    CompletionChunk::Text(text) => answer.text.push_str(&text),
+                                    CompletionChunk::Reasoning(text) => answer
+                                        .reasoning
+                                        .get_or_insert_with(String::new)
+                                        .push_str(&text),
+                                    CompletionChunk::Done(usage) => {
+                                        answer.usage = usage;
+                                        answer.done = true;
+                                    }
+                                    CompletionChunk::Failed(e) => {
+                                        answer.error = Some(e.message());
+                                        answer.done = true;
+                                    }
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("Failed to start streaming: {}", e);
+                        }
+                    }
+                    update(comparison, i, |answer| {
+                        if !answer.done {
+                            answer.done = true;
+                            answer.error = Some("The answer was cut off.".to_string());
+                        }
+                    });
+                })
+            })
+            .collect();
+        comparison.set(Some(Comparison {
+            conv_id,
+            answers,
+            tasks,
+        }));
+    }
+
+    /// Stop the answers that are still streaming.
+    pub(super) fn cancel(&self) {
+        for task in &self.tasks {
+            task.cancel();
+        }
+    }
+}
+
+fn update(mut comparison: Signal<Option<Comparison>>, index: usize, f: impl FnOnce(&mut Answer)) {
+    comparison.with_mut(|c| {
+        if let Some(answer) = c.as_mut().and_then(|c| c.answers.get_mut(index)) {
+            f(answer);
+        }
+    });
+}
+
+/// A checkbox for each model, to pick the ones a prompt is sent to.
+pub(super) fn render_compare_picker(
+    all_models: Vec<ModelConfig>,
+    mut selected: Signal<Vec<ModelConfig>>,
+) -> Element {
+    rsx! {
+        div { class: "flex flex-wrap gap-x-4 gap-y-1 mt-2 text-sm",
+            for model in all_models {
+                label { key: "{model.name}", class: "flex items-center gap-1 cursor-pointer",
+                    input {
+                        r#type: "checkbox",
+                        checked: selected.read().contains(&model),
+                        onchange: {
+                            let model = model.clone();
+                            move |e: Event<FormData>| {
+                                let checked = e.value().parse().unwrap_or(false);
+                                selected.with_mut(|models| {
+                                    models.retain(|m| m.name != model.name);
+                                    if checked {
+                                        models.push(model.clone());
+                                    }
+                                });
+                            }
+                        },
+                    }
+                    "{model.name}"
+                }
+            }
+            p { class: "basis-full text-xs text-gray-500",
+                "Compared models see only this message, not the conversation before it."
+            }
+        }
+    }
+}
+
+/// The answers side by side, each with a button to keep it.
+pub(super) fn render_comparison(
+    comparison: &Comparison,
+    on_keep: EventHandler<usize>,
+    on_discard: EventHandler<()>,
+) -> Element {
+    let streaming = comparison.answers.iter().any(|a| !a.done);
+    rsx! {
+        div { class: "mt-2 p-2 border border-gray-700 rounded",
+            div { class: "flex items-center justify-between mb-2 text-sm text-gray-400",
+                if streaming {
+                    "Waiting for every answer…"
+                } else {
+                    "Keep one answer to continue the conversation with it."
+                }
+                button { class: "underline", onclick: move |_| on_discard.call(()), "Discard all" }
+            }
+            div { class: "flex gap-2 overflow-x-auto",
+                for (i, answer) in comparison.answers.iter().enumerate() {
+                    div {
+                        key: "{i}",
+                        class: "flex-1 min-w-64 flex flex-col gap-1 p-2 border border-gray-700 rounded text-sm",
+                        div { class: "flex items-center justify-between font-semibold",
+                            "{answer.model.name}"
+                            button {
+                                class: "px-2 bg-blue-500 text-white rounded hover:bg-blue-600 disabled:opacity-50",
+                                disabled: !answer.can_keep(),
+                                onclick: move |_| on_keep.call(i),
+                                "Keep"
+                            }
+                        }
+                        if let Some(trace) = &answer.reasoning {
+                            details { class: "text-gray-400",
+                                summary { class: "cursor-pointer select-none", "Thinking" }
+                                div { class: "whitespace-pre-wrap border-l border-gray-600 pl-2 mt-1", "{trace}" }
+                            }
+                        }
+                        div { class: "max-h-96 overflow-y-auto",
+                            Markdown { text: answer.text.clone(), streaming: !answer.done }
+                        }
+                        if let Some(error) = &answer.error {
+                            p { class: "text-red-500", "{error}" }
+                        }
+                        if let Some(usage) = &answer.usage {
+                            p { class: "text-xs text-gray-500",
+                                "{usage.prompt_tokens} in · {usage.completion_tokens} out"
+                                if let Some(cost) = usage.cost {
+                                    " · ${cost:.4}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! The message composer's slash commands, per-conversation drafts and saved prompt
+//! templates.
+use api::model_config::ModelConfig;
+use api::templates::{PromptTemplate, TemplateId};
+use api::ConversationId;
+use dioxus::prelude::*;
+
+use crate::session::use_session;
+use crate::storage::{
+    load_from_storage, remove_from_storage, remove_from_storage_except, save_to_storage,
+};
+
+/// A slash command typed into the composer instead of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Command {
+    /// Switch to the model with this name.
+    Model(String),
+    /// Generate an image from the prompt.
+    Image(String),
+    /// Search the messages of every conversation.
+    Search(String),
+    /// Set the system prompt, or clear it when empty.
+    System(String),
+}
+
+/// Every command with what it takes, as shown in the hints.
+const COMMANDS: &[(&str, &str)] = &[
+    ("/model", "name"),
+    ("/image", "prompt"),
+    ("/search", "query"),
+    ("/system", "prompt, or nothing to clear it"),
+];
+
+/// The command `text` starts with, or `None` if it is an ordinary message. Text
+/// starting with any other `/word` is sent as it is.
+pub(super) fn parse_command(text: &str) -> Option<Result<Command, String>> {
+    let rest = text.strip_prefix('/')?;
+    let (name, arg) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let arg = arg.trim().to_string();
+    let command = match name {
+        "model" => Command::Model(arg.clone()),
+        "image" => Command::Image(arg.clone()),
+        "search" => Command::Search(arg.clone()),
+        "system" => return Some(Ok(Command::System(arg))),
+        _ => return None,
+    };
+    if arg.is_empty() {
+        let usage = COMMANDS
+            .iter()
+            .find(|(command, _)| command[1..] == *name)
+            .map_or("", |(_, usage)| usage);
+        return Some(Err(format!("Usage: /{name} {usage}")));
+    }
+    Some(Ok(command))
+}
+
+/// The model `name` picks: the one with exactly that name, ignoring case, or else
+/// the only one whose name contains it.
+pub(super) fn find_model(models: &[ModelConfig], name: &str) -> Option<ModelConfig> {
+    let name = name.to_lowercase();
+    if let Some(model) = models.iter().find(|m| m.name.to_lowercase() == name) {
+        return Some(model.clone());
+    }
+    let mut matches = models
+        .iter()
+        .filter(|m| m.name.to_lowercase().contains(&name));
+    match (matches.next(), matches.next()) {
+        (Some(model), None) => Some(model.clone()),
+        _ => None,
+    }
+}
+
+/// The commands matching what has been typed so far, while a command name is typed.
+pub(super) fn render_command_hints(input: &str) -> Element {
+    if !input.starts_with('/') || input.contains(char::is_whitespace) {
+        return rsx! {};
+    }
+    rsx! {
+        ul { class: "mt-1 list-none p-0 text-xs text-gray-400",
+            for (command, usage) in COMMANDS.iter().filter(|(command, _)| command.starts_with(input)) {
+                li { key: "{command}", "{command} ", span { class: "italic", "{usage}" } }
+            }
+        }
+    }
+}
+
+const DRAFT_PREFIX: &str = "draft:";
+
+/// Drafts are kept per session, so nobody who logs in on this device later sees them.
+fn drafts_prefix(session: &str) -> String {
+    format!("{DRAFT_PREFIX}{session}/")
+}
+
+fn draft_key(session: &str, conv_id: ConversationId) -> String {
+    format!("{}{conv_id}", drafts_prefix(session))
+}
+
+/// The unsent text last typed in a conversation on this device.
+pub(super) fn load_draft(session: &str, conv_id: ConversationId) -> String {
+    load_from_storage(&draft_key(session, conv_id)).unwrap_or_default()
+}
+
+/// Keep the text typed in a conversation until it is sent.
+pub(super) fn save_draft(session: &str, conv_id: ConversationId, text: &str) {
+    if text.trim().is_empty() {
+        remove_from_storage(&draft_key(session, conv_id));
+    } else {
+        save_to_storage(&draft_key(session, conv_id), text);
+    }
+}
+
+/// Delete the drafts typed under any session but `session`.
+pub(crate) fn forget_other_drafts(session: &str) {
+    remove_from_storage_except(DRAFT_PREFIX, &drafts_prefix(session));
+}
+
+/// The caller's saved templates: insert one into `input`, filling in its variables
+/// first, save the input as a new template, or delete one.
+#[component]
+pub(super) fn TemplatePicker(input: Signal<String>) -> Element {
+    let session = use_session();
+    let mut open = use_signal(|| false);
+    let mut templates = use_resource(move || async move {
+        let token = session()?;
+        api::templates::list_templates(token).await.ok()
+    });
+    // Template whose variables are being filled in, with the values so far.
+    let mut filling = use_signal(|| None::<(PromptTemplate, Vec<(String, String)>)>);
+    let mut name = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+
+    let mut insert = move |text: String| {
+        input.with_mut(|current| {
+            if !current.trim().is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&text);
+        });
+        filling.set(None);
+        open.set(false);
+    };
+
+    let save = move |_| async move {
+        let template = PromptTemplate {
+            id: TemplateId::nil(),
+            name: name(),
+            text: input(),
+        };
+        match api::templates::save_template(session().unwrap_or_default(), template).await {
+            Ok(_) => {
+                name.set(String::new());
+                error.set(None);
+                templates.restart();
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    let delete = move |id: TemplateId| async move {
+        if api::templates::delete_template(session().unwrap_or_default(), id)
+            .await
+            .is_ok()
+        {
+            templates.restart();
+        }
+    };
+
+    let list = templates().flatten().unwrap_or_default();
+    rsx! {
+        div { class: "relative",
+            button {
+                class: "underline text-sm",
+                onclick: move |_| {
+                    open.set(!open());
+                    filling.set(None);
+                },
+                "Templates"
+            }
+            if open() {
+                div { class: "absolute bottom-full left-0 z-10 mb-1 w-80 p-2 flex flex-col gap-2 border border-gray-700 rounded bg-white dark:bg-gray-900 text-sm",
+                    if let Some((template, values)) = filling() {
+                        p { class: "font-semibold", "{template.name}" }
+                        for (i, (variable, value)) in values.iter().enumerate() {
+                            label { key: "{variable}", class: "flex flex-col gap-1",
+                                "{variable}"
+                                input {
+                                    class: "p-1 border border-gray-700 rounded bg-transparent",
+                                    value: "{value}",
+                                    oninput: move |e| {
+                                        filling.with_mut(|f| {
+                                            if let Some((_, values)) = f {
+                                                values[i].1 = e.value();
+                                            }
+                                        })
+                                    },
+                                }
+                            }
+                        }
+                        div { class: "flex gap-2",
+                            button {
+                                class: "px-2 bg-blue-500 text-white rounded hover:bg-blue-600",
+                                onclick: move |_| insert(template.fill(&values)),
+                                "Insert"
+                            }
+                            button { class: "underline", onclick: move |_| filling.set(None), "Back" }
+                        }
+                    } else {
+                        if list.is_empty() {
+                            p { class: "text-gray-400", "No saved templates yet." }
+                        }
+                        ul { class: "max-h-48 overflow-y-auto list-none p-0",
+                            for template in list {
+                                li { key: "{template.id}", class: "flex items-center justify-between gap-2",
+                                    button {
+                                        class: "truncate text-left hover:underline",
+                                        title: "{template.text}",
+                                        onclick: {
+                                            let template = template.clone();
+                                            move |_| {
+                                                let variables = template.variables();
+                                                if variables.is_empty() {
+                                                    insert(template.text.clone());
+                                                } else {
+                                                    let values = variables.into_iter().map(|v| (v, String::new())).collect();
+                                                    filling.set(Some((template.clone(), values)));
+                                                }
+                                            }
+                                        },
+                                        "{template.name}"
+                                    }
+                                    button {
+                                        class: "underline text-xs text-red-500",
+                                        onclick: move |_| delete(template.id),
+                                        "Delete"
+                                    }
+                                }
+                            }
+                        }
+                        div { class: "flex gap-2",
+                            input {
+                                class: "flex-1 p-1 border border-gray-700 rounded bg-transparent",
+                                placeholder: "Name",
+                                maxlength: "{api::templates::MAX_TEMPLATE_NAME_LEN}",
+                                value: "{name}",
+                                oninput: move |e| name.set(e.value()),
+                            }
+                            button {
+                                class: "underline",
+                                disabled: name().trim().is_empty() || input().trim().is_empty(),
+                                onclick: save,
+                                "Save input"
+                            }
+                        }
+                        p { class: "text-xs text-gray-400", "Write {{{{variable}}}} in a template for text to fill in." }
+                    }
+                    if let Some(error) = error() {
+                        p { class: "text-red-500", "{error}" }
+                    }
+                }
+            }
+        }
+    }
+}
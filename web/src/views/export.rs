@@ -0,0 +1,63 @@
+use crate::session::use_session;
+use api::export::{ExportFormat, ExportedFile};
+use api::ConversationId;
+use dioxus::prelude::*;
+
+/// Hand a file to the browser as a download.
+fn download(file: &ExportedFile) {
+    let js_string = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    document::eval(&format!(
+        "const blob = new Blob([{}], {{ type: {} }});\
+         const a = document.createElement('a');\
+         a.href = URL.createObjectURL(blob);\
+         a.download = {};\
+         a.click();\
+         URL.revokeObjectURL(a.href);",
+        js_string(&file.content),
+        js_string(&file.content_type),
+        js_string(&file.filename),
+    ));
+}
+
+/// Download the conversation as Markdown, JSON or HTML.
+#[component]
+pub fn ExportMenu(conv_id: ConversationId) -> Element {
+    let session = use_session();
+    let mut error = use_signal(|| None::<String>);
+
+    let export = move |format: ExportFormat| async move {
+        let token = session().unwrap_or_default();
+        match api::export::export_conversation(token, conv_id, format).await {
+            Ok(file) => {
+                error.set(None);
+                download(&file);
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    rsx! {
+        select {
+            class: "p-1 border rounded bg-transparent text-sm",
+            title: error().unwrap_or_default(),
+            onchange: move |e| {
+                if let Ok(format) = e.value().parse::<ExportFormat>() {
+                    spawn(export(format));
+                }
+            },
+            option { value: "", selected: true, "Export…" }
+            for format in ExportFormat::ALL {
+                option { value: "{format}",
+                    match format {
+                        ExportFormat::Markdown => "Markdown",
+                        ExportFormat::Json => "JSON",
+                        ExportFormat::Html => "HTML",
+                    }
+                }
+            }
+        }
+        if let Some(error) = error() {
+            span { class: "text-sm text-red-500", "{error}" }
+        }
+    }
+}
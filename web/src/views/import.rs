@@ -0,0 +1,97 @@
+use crate::session::use_session;
+use api::import::ImportSummary;
+use dioxus::prelude::*;
+
+fn render_import_summary(summary: &ImportSummary) -> Element {
+    let messages: usize = summary.conversations.iter().map(|c| c.messages).sum();
+    let attachments: usize = summary.conversations.iter().map(|c| c.attachments).sum();
+    let other_branches: usize = summary
+        .conversations
+        .iter()
+        .map(|c| c.other_branch_messages)
+        .sum();
+    let verb = if summary.imported {
+        "Imported"
+    } else {
+        "Will import"
+    };
+    rsx! {
+        p { class: "text-sm",
+            "{verb} {summary.conversations.len()} {summary.source} conversations with {messages} messages and {attachments} attachments "
+            "into the “{summary.source}” folder."
+        }
+        if other_branches > 0 || summary.skipped_messages > 0 {
+            p { class: "text-sm text-gray-400",
+                "Left out: {other_branches} messages on edited or regenerated branches, "
+                "{summary.skipped_messages} system, tool or empty messages."
+            }
+        }
+        ul { class: "max-h-48 overflow-y-auto text-sm text-gray-400",
+            for (i, conv) in summary.conversations.iter().enumerate() {
+                li { key: "{i}", "{conv.title} · {conv.messages} messages" }
+            }
+        }
+    }
+}
+
+/// Upload a ChatGPT or Claude export, preview it, then import it.
+#[component]
+pub fn ImportHistory() -> Element {
+    let session = use_session();
+    let mut export = use_signal(|| None::<String>);
+    let mut summary = use_signal(|| None::<ImportSummary>);
+    let mut error = use_signal(|| None::<String>);
+
+    let run = move |dry_run: bool| {
+        spawn(async move {
+            let (Some(token), Some(data)) = (session(), export()) else {
+                return;
+            };
+            match api::import::import_history(token, data, dry_run).await {
+                Ok(result) => {
+                    error.set(None);
+                    if result.imported {
+                        export.set(None);
+                    }
+                    summary.set(Some(result));
+                }
+                Err(e) => {
+                    summary.set(None);
+                    error.set(Some(e.to_string()));
+                }
+            }
+        });
+    };
+
+    rsx! {
+        div { class: "space-y-2",
+            h2 { class: "font-semibold", "Import history" }
+            p { class: "text-sm text-gray-400",
+                "Choose conversations.json from a ChatGPT or Claude data export."
+            }
+            input {
+                r#type: "file",
+                accept: ".json,application/json",
+                onchange: move |e| async move {
+                    let Some(files) = e.files() else { return };
+                    let Some(name) = files.files().first().cloned() else { return };
+                    export.set(files.read_file_to_string(&name).await);
+                    run(true);
+                },
+            }
+            if let Some(error) = error() {
+                p { class: "text-red-500 text-sm", "{error}" }
+            }
+            if let Some(summary) = summary() {
+                {render_import_summary(&summary)}
+                if !summary.imported && !summary.conversations.is_empty() {
+                    button {
+                        class: "px-2 py-1 bg-blue-500 text-white rounded",
+                        onclick: move |_| run(false),
+                        "Import"
+                    }
+                }
+            }
+        }
+    }
+}
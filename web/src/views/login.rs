@@ -1,18 +1,21 @@
 use dioxus::prelude::*;
+use crate::session::{set_session, use_session};
 use crate::Route;
 
 #[component]
 pub fn Login() -> Element {
-    let mut username = use_signal(|| String::new());
-    let mut password = use_signal(|| String::new());
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
     let navigator = use_navigator();
+    let mut session = use_session();
 
     let on_login = move |_| {
         let user = username().clone();
         let pass = password().clone();
-        let nav = navigator.clone();
+        let nav = navigator;
         async move {
-            if api::login(user, pass).await.unwrap_or(false) {
+            if let Ok(Some(token)) = api::login(user, pass).await {
+                set_session(&mut session, token);
                 nav.push(Route::Chat {});
             }
         }
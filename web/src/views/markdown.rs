@@ -0,0 +1,365 @@
+//! Rendering message Markdown to HTML, with math, highlighted code and diagrams.
+//!
+//! Messages are rendered block by block. Once a block is followed by more text it
+//! is kept as rendered, so a message that grows while it streams only re-renders its
+//! last blocks and the finished ones keep their DOM.
+use api::graphviz::render_graphviz;
+use api::highlight::{highlight_code_blocks, language, Diagram};
+use api::markdown::{fence_lang, markdown_options, render_markdown};
+use api::math::{extract_math, restore_placeholders, split_placeholders, Math, Piece};
+use api::sanitize::TrustedHtml;
+use dioxus::prelude::*;
+use katex_wasmbind::KaTeXOptions;
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{Event as MdEvent, Parser, Tag};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::session::use_session;
+
+/// Spans of code the server has highlighted, by code and fence token. Code blocks
+/// are shown as plain text until their spans arrive.
+static HIGHLIGHTED: GlobalSignal<HashMap<(String, String), String>> =
+    Signal::global(HashMap::new);
+
+/// Graphviz diagrams the server has drawn, as SVG by their source.
+static DRAWN: GlobalSignal<HashMap<String, String>> = Signal::global(HashMap::new);
+
+/// Handles the code block toolbar buttons for every message on the page.
+const CODE_TOOLBAR_JS: &str = r#"
+if (!window.codeToolbarInstalled) {
+  window.codeToolbarInstalled = true;
+  document.addEventListener('click', (event) => {
+    const button = event.target.closest('.code-block button[data-action]');
+    if (!button) return;
+    const pre = button.closest('.code-block').querySelector('pre');
+    const code = pre.textContent;
+    switch (button.dataset.action) {
+      case 'copy':
+        navigator.clipboard.writeText(code).then(() => {
+          button.textContent = 'Copied';
+          setTimeout(() => { button.textContent = 'Copy'; }, 1500);
+        });
+        break;
+      case 'wrap':
+        pre.classList.toggle('whitespace-pre-wrap');
+        break;
+      case 'toggle-source': {
+        const diagram = button.closest('.code-block').querySelector('.diagram');
+        diagram.classList.toggle('hidden');
+        pre.classList.toggle('hidden');
+        button.textContent = diagram.classList.contains('hidden') ? 'Diagram' : 'Source';
+        break;
+      }
+      case 'download': {
+        const url = URL.createObjectURL(new Blob([code], { type: 'text/plain' }));
+        const link = document.createElement('a');
+        link.href = url;
+        link.download = button.dataset.filename;
+        link.click();
+        URL.revokeObjectURL(url);
+        break;
+      }
+    }
+  });
+}
+"#;
+
+/// Installs the click handler behind the code block toolbars.
+#[component]
+pub(super) fn CodeBlockScripts() -> Element {
+    use_effect(|| {
+        document::eval(CODE_TOOLBAR_JS);
+    });
+    rsx! {}
+}
+
+/// A highlighted code block under a header with its language and buttons to copy,
+/// soft-wrap and download it. Graphviz diagrams are drawn over their source once
+/// the server has drawn them, with a button to switch back to the source.
+fn code_block_html(code: &str, lang: &str) -> String {
+    let diagram = Diagram::from_fence(lang);
+    let (label, extension) = match (diagram, language(lang)) {
+        (Some(diagram), _) => (diagram.name(), diagram.extension()),
+        (None, Some(language)) => (language.name, language.extension),
+        (None, None) if !lang.is_empty() => (lang, "txt"),
+        (None, None) => ("Text", "txt"),
+    };
+    let mut label_html = String::new();
+    let _ = escape_html(&mut label_html, label);
+    let button = "class=\"hover:underline\" type=\"button\"";
+    let drawn = match diagram {
+        Some(Diagram::Graphviz) => DRAWN.peek().get(code).cloned(),
+        _ => None,
+    };
+    let (toggle, drawing, pre_hidden) = match drawn {
+        Some(svg) => (
+            "<button class=\"hover:underline\" type=\"button\" data-action=\"toggle-source\">Source</button>",
+            format!("<div class=\"diagram p-2 overflow-x-auto bg-white\">{svg}</div>"),
+            " hidden",
+        ),
+        None => ("", String::new(), ""),
+    };
+    let key = (code.to_string(), lang.to_string());
+    let spans = match HIGHLIGHTED.peek().get(&key) {
+        Some(spans) => spans.clone(),
+        None => {
+            let mut escaped = String::new();
+            let _ = escape_html(&mut escaped, code);
+            escaped
+        }
+    };
+    format!(
+        "<div class=\"code-block my-2 border border-gray-700 rounded overflow-hidden\">\
+<div class=\"flex items-center gap-3 px-2 py-1 text-xs text-gray-400 border-b border-gray-700\">\
+<span class=\"flex-1\">{label_html}</span>{toggle}\
+<button {button} data-action=\"wrap\">Wrap</button>\
+<button {button} data-action=\"download\" data-filename=\"snippet.{extension}\">Download</button>\
+<button {button} data-action=\"copy\">Copy</button>\
+</div>{drawing}<pre class=\"hl-code overflow-x-auto p-2 m-0{pre_hidden}\"><code>{spans}</code></pre></div>"
+    )
+}
+
+/// The fenced code blocks of `text`, as their code and fence token.
+fn code_blocks(text: &str) -> Vec<(String, String)> {
+    let (source, math) = extract_math(&TrustedHtml::strip_markers(text));
+    let mut blocks = Vec::new();
+    let mut code_block: Option<(String, String)> = None;
+    for event in Parser::new_ext(&source, markdown_options()) {
+        match event {
+            MdEvent::Start(Tag::CodeBlock(kind)) => {
+                code_block = Some((String::new(), fence_lang(kind)));
+            }
+            MdEvent::End(Tag::CodeBlock(_)) => blocks.extend(code_block.take()),
+            MdEvent::Text(t) => {
+                if let Some((code, _)) = &mut code_block {
+                    code.push_str(&restore_placeholders(&t, &math));
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks.retain(|(_, lang)| !lang.is_empty());
+    blocks
+}
+
+/// Ask the server to highlight the code blocks in `text` and draw the Graphviz
+/// ones, where it has not done so yet.
+fn request_rendering(session: &str, text: &str) {
+    let blocks = code_blocks(text);
+    let drawings: Vec<String> = blocks
+        .iter()
+        .filter(|(code, lang)| {
+            Diagram::from_fence(lang) == Some(Diagram::Graphviz) && !DRAWN.peek().contains_key(code)
+        })
+        .map(|(code, _)| code.clone())
+        .collect();
+    for source in drawings {
+        let session = session.to_string();
+        spawn(async move {
+            if let Ok(svg) = render_graphviz(session, source.clone()).await {
+                DRAWN.write().insert(source, svg);
+            }
+        });
+    }
+    let blocks: Vec<(String, String)> = blocks
+        .into_iter()
+        .filter(|block| language(&block.1).is_some() && !HIGHLIGHTED.peek().contains_key(block))
+        .collect();
+    if blocks.is_empty() {
+        return;
+    }
+    let session = session.to_string();
+    spawn(async move {
+        if let Ok(spans) = highlight_code_blocks(session, blocks.clone()).await {
+            HIGHLIGHTED.write().extend(blocks.into_iter().zip(spans));
+        }
+    });
+}
+
+/// Render Markdown with TeX math, inline formulas with `katex_opts` and display
+/// formulas in display mode, and code blocks highlighted as far as the server has
+/// highlighted them.
+fn markdown_to_html(text: &str, katex_opts: &KaTeXOptions) -> String {
+    let display_opts = KaTeXOptions::display_mode();
+    render_markdown(
+        text,
+        |m| {
+            let opts = if m.display { &display_opts } else { katex_opts };
+            opts.render(&m.tex)
+        },
+        code_block_html,
+    )
+}
+
+/// The words of a message for reading aloud: Markdown syntax and code blocks dropped,
+/// one line per block.
+pub(super) fn plain_text(text: &str) -> String {
+    let mut plain = String::new();
+    let mut in_code_block = false;
+    for event in Parser::new_ext(text, markdown_options()) {
+        match event {
+            MdEvent::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            MdEvent::End(Tag::CodeBlock(_)) => in_code_block = false,
+            MdEvent::Text(text) | MdEvent::Code(text) if !in_code_block => plain.push_str(&text),
+            MdEvent::SoftBreak => plain.push(' '),
+            MdEvent::HardBreak
+            | MdEvent::End(Tag::Paragraph | Tag::Heading(..) | Tag::Item | Tag::TableRow) => {
+                plain.push('\n')
+            }
+            _ => {}
+        }
+    }
+    plain
+}
+
+/// Offset in the original text of `offset` in the same text with its math extracted.
+fn raw_offset(source: &str, math: &[Math], offset: usize) -> usize {
+    split_placeholders(&source[..offset], math)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.len(),
+            Piece::Math(m) => m.source.len(),
+        })
+        .sum()
+}
+
+/// Where each top-level block of `text` starts. The first block starts at 0 so
+/// leading blank lines stay with it.
+fn block_starts(text: &str) -> Vec<usize> {
+    let (source, math) = extract_math(text);
+    let mut starts = Vec::new();
+    let mut depth = 0;
+    let mut in_html = false;
+    for (event, range) in Parser::new_ext(&source, markdown_options()).into_offset_iter() {
+        let starts_block = match event {
+            MdEvent::Start(_) => {
+                depth += 1;
+                depth == 1
+            }
+            MdEvent::End(_) => {
+                depth -= 1;
+                false
+            }
+            // Each line of an HTML block is an event of its own.
+            MdEvent::Html(_) if depth == 0 => !in_html,
+            _ => depth == 0,
+        };
+        in_html = depth == 0 && matches!(event, MdEvent::Html(_));
+        if starts_block {
+            starts.push(raw_offset(&source, &math, range.start));
+        }
+    }
+    if let Some(first) = starts.first_mut() {
+        *first = 0;
+    }
+    starts
+}
+
+/// Reference links and footnotes refer across blocks, so text with definitions is
+/// rendered in one piece.
+fn has_definitions(text: &str) -> bool {
+    text.contains("]:")
+}
+
+/// Whether `text` may have fenced code blocks, the ones that are highlighted and
+/// drawn.
+fn has_fences(text: &str) -> bool {
+    text.contains("```") || text.contains("~~~")
+}
+
+/// Where an unclosed `$$` or `\[` starts in `text`, unless `text` is code.
+fn unclosed_display_math(text: &str) -> Option<usize> {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+        return None;
+    }
+    let (source, math) = extract_math(text);
+    let start = ["$$", "\\["].iter().filter_map(|d| source.find(d)).min()?;
+    Some(raw_offset(&source, &math, start))
+}
+
+/// Render the last blocks of a message. While it streams, a display formula whose
+/// closing delimiter has not arrived yet is shown as its source, rather than as
+/// Markdown that mangles the TeX.
+fn render_tail(text: &str, streaming: bool, katex_opts: &KaTeXOptions) -> String {
+    match unclosed_display_math(text).filter(|_| streaming) {
+        Some(start) => {
+            let mut pending = String::new();
+            let _ = escape_html(&mut pending, &text[start..]);
+            format!(
+                "{}<div class=\"katex-display text-gray-400 whitespace-pre-wrap\">{pending}</div>",
+                markdown_to_html(&text[..start], katex_opts)
+            )
+        }
+        None => markdown_to_html(text, katex_opts),
+    }
+}
+
+/// Blocks of a message rendered so far, kept between renders.
+#[derive(Default)]
+struct RenderedBlocks {
+    /// The start of the message the blocks were rendered from.
+    source: String,
+    html: Vec<String>,
+    /// How many code blocks had been highlighted or drawn when the blocks were
+    /// rendered.
+    rendered_code: usize,
+}
+
+impl RenderedBlocks {
+    /// Render the blocks of `text` not rendered yet, keeping all but the last two,
+    /// which can still change as text arrives: a list can gain items after a blank
+    /// line. Returns the HTML of the blocks that were not kept.
+    fn update(&mut self, text: &str, streaming: bool, katex_opts: &KaTeXOptions) -> String {
+        let rendered_code = HIGHLIGHTED.peek().len() + DRAWN.peek().len();
+        let code_arrived = rendered_code != self.rendered_code && has_fences(&self.source);
+        if !text.starts_with(&self.source) || has_definitions(text) || code_arrived {
+            *self = Self::default();
+        }
+        self.rendered_code = rendered_code;
+        let rest = &text[self.source.len()..];
+        let starts = block_starts(rest);
+        if starts.len() > 2 && !has_definitions(text) {
+            let kept = starts[starts.len() - 2];
+            for block in starts[..starts.len() - 1].windows(2) {
+                self.html
+                    .push(markdown_to_html(&rest[block[0]..block[1]], katex_opts));
+            }
+            self.source.push_str(&rest[..kept]);
+        }
+        render_tail(&text[self.source.len()..], streaming, katex_opts)
+    }
+}
+
+/// A message's Markdown. Set `streaming` while text is still arriving.
+#[component]
+pub(super) fn Markdown(text: String, #[props(default)] streaming: bool) -> Element {
+    let blocks = use_hook(|| Rc::new(RefCell::new(RenderedBlocks::default())));
+    // Render again when the server sends spans or drawings for this message's code.
+    if has_fences(&text) {
+        let _ = HIGHLIGHTED.read();
+        let _ = DRAWN.read();
+    }
+    let session = use_session();
+    use_effect(use_reactive(
+        (&text, &streaming),
+        move |(text, streaming)| {
+            if let Some(session) = session().filter(|_| !streaming && has_fences(&text)) {
+                request_rendering(&session, &text);
+            }
+        },
+    ));
+    let katex_opts = KaTeXOptions::inline_mode();
+    let tail = blocks.borrow_mut().update(&text, streaming, &katex_opts);
+    let blocks = blocks.borrow();
+    rsx! {
+        div {
+            for (i, html) in blocks.html.iter().enumerate() {
+                div { key: "{i}", dangerous_inner_html: "{html}" }
+            }
+            div { dangerous_inner_html: "{tail}" }
+        }
+    }
+}
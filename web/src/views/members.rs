@@ -0,0 +1,116 @@
+use crate::session::use_session;
+use api::{ConversationId, Member, Role};
+use dioxus::prelude::*;
+
+fn role_label(role: Role) -> &'static str {
+    match role {
+        Role::Owner => "Owner",
+        Role::Editor => "Editor",
+        Role::Viewer => "Viewer",
+    }
+}
+
+/// List who has access to a conversation. Owners can invite and remove members, and
+/// everyone else can leave.
+#[component]
+pub fn MembersPanel(conv_id: ConversationId, role: Role, user: Option<String>) -> Element {
+    let session = use_session();
+    let mut username = use_signal(String::new);
+    let mut invite_role = use_signal(|| Role::Editor);
+    let mut error = use_signal(|| None::<String>);
+    let mut members = use_resource(move || async move {
+        match session() {
+            Some(token) => api::members::list_members(token, conv_id)
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    });
+
+    let invite = move |_| async move {
+        let Some(token) = session() else { return };
+        match api::members::add_member(token, conv_id, username(), invite_role()).await {
+            Ok(()) => {
+                error.set(None);
+                username.set(String::new());
+                members.restart();
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    let remove = move |name: String| async move {
+        let Some(token) = session() else { return };
+        if let Err(e) = api::members::remove_member(token, conv_id, name).await {
+            error.set(Some(e.to_string()));
+        }
+        members.restart();
+    };
+
+    let render_member = |member: Member| {
+        let can_remove = member.role != Role::Owner
+            && (role == Role::Owner || user.as_ref() == Some(&member.name));
+        let is_self = user.as_ref() == Some(&member.name);
+        rsx! {
+            li { class: "flex items-center gap-2 text-sm",
+                span { class: "flex-1", "{member.name}" }
+                span { class: "text-gray-400", {role_label(member.role)} }
+                if can_remove {
+                    button {
+                        class: "underline text-red-500",
+                        onclick: move |_| {
+                            spawn(remove(member.name.clone()));
+                        },
+                        if is_self { "Leave" } else { "Remove" }
+                    }
+                }
+            }
+        }
+    };
+
+    rsx! {
+        div { class: "mt-2 p-2 border border-gray-700 rounded space-y-2",
+            if role == Role::Owner {
+                p { class: "text-sm text-gray-400",
+                    "Members see this conversation in their sidebar. Editors can also write in it."
+                }
+                div { class: "flex items-center gap-2",
+                    input {
+                        class: "flex-1 p-1 border rounded bg-transparent text-sm",
+                        placeholder: "Username",
+                        value: "{username}",
+                        oninput: move |e| username.set(e.value()),
+                    }
+                    select {
+                        class: "p-1 border rounded bg-transparent text-sm",
+                        onchange: move |e| {
+                            if let Ok(role) = e.value().parse() {
+                                invite_role.set(role);
+                            }
+                        },
+                        for role in Role::INVITABLE {
+                            option {
+                                value: "{role}",
+                                selected: invite_role() == role,
+                                {role_label(role)}
+                            }
+                        }
+                    }
+                    button {
+                        class: "px-2 py-1 bg-blue-500 text-white rounded text-sm",
+                        onclick: invite,
+                        "Invite"
+                    }
+                }
+            }
+            if let Some(error) = error() {
+                p { class: "text-sm text-red-500", "{error}" }
+            }
+            ul { class: "space-y-1",
+                for member in members().unwrap_or_default() {
+                    {render_member(member)}
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,28 @@
 mod chat;
 pub use chat::{Chat, ChatShare};
 
+mod compare;
+
+mod composer;
+pub(crate) use composer::forget_other_drafts;
+
+mod export;
+
+mod import;
+
+mod markdown;
+
+mod members;
+
+mod share;
+pub use share::SharedChat;
+
 mod settings;
 pub use settings::Settings;
 
+mod usage;
+pub use usage::Usage;
+
 mod login;
 pub use login::Login;
 
@@ -1,37 +1,150 @@
+use crate::session::use_session;
+use crate::speech::{self, VoiceSettings};
+use crate::storage::{load_from_storage, remove_from_storage, save_to_storage};
 use crate::{views::Theme, Route};
+use super::import::ImportHistory;
+use api::keys::MaskedKey;
+use api::model_config::Provider;
 use dioxus::prelude::*;
 
-#[cfg(feature = "web")]
-fn save_to_storage(key: &str, value: &str) {
-    if let Some(win) = web_sys::window() {
-        if let Ok(Some(storage)) = win.local_storage() {
-            let _ = storage.set_item(key, value);
+fn render_saved_keys(
+    keys: Vec<MaskedKey>,
+    session: Signal<Option<String>>,
+    mut saved_keys: Resource<Vec<MaskedKey>>,
+) -> Element {
+    rsx! {
+        ul { class: "space-y-1",
+            for key in keys {
+                li { key: "{key.provider}", class: "flex items-center gap-2",
+                    span { class: "w-24", "{key.provider}" }
+                    span { class: "font-mono text-sm", "{key.masked}" }
+                    button {
+                        class: "underline text-sm",
+                        onclick: move |_| async move {
+                            if let Some(token) = session() {
+                                if let Err(e) = api::keys::delete_provider_key(token, key.provider).await {
+                                    log::error!("Failed to remove API key: {}", e);
+                                }
+                                saved_keys.restart();
+                            }
+                        },
+                        "Remove"
+                    }
+                }
+            }
         }
     }
 }
 
-#[cfg(not(feature = "web"))]
-fn save_to_storage(_key: &str, _value: &str) {}
-
-#[cfg(feature = "web")]
-fn load_from_storage(key: &str) -> Option<String> {
-    web_sys::window()
-        .and_then(|w| w.local_storage().ok().flatten())
-        .and_then(|s| s.get_item(key).ok().flatten())
+fn update_voice(mut voice: Signal<VoiceSettings>, f: impl FnOnce(&mut VoiceSettings)) {
+    voice.with_mut(|settings| {
+        f(settings);
+        settings.save();
+    });
 }
 
-#[cfg(not(feature = "web"))]
-fn load_from_storage(_key: &str) -> Option<String> {
-    None
+/// Voice mode: whether it is on, the language spoken and the voice replies are read with.
+#[component]
+fn VoiceOptions() -> Element {
+    let voice = use_signal(VoiceSettings::load);
+    let mut voices = use_signal(speech::voices);
+    // Browsers load their voices after the page, so the list fills in late.
+    use_future(move || async move {
+        loop {
+            speech::voices_changed().await;
+            voices.set(speech::voices());
+        }
+    });
+
+    let settings = voice();
+    let language = settings.language.to_lowercase();
+    let prefix = language.split('-').next().unwrap_or_default().to_string();
+    rsx! {
+        div { class: "space-y-2",
+            h2 { class: "font-semibold", "Voice" }
+            label { class: "flex items-center gap-2",
+                input {
+                    r#type: "checkbox",
+                    checked: settings.enabled,
+                    onchange: move |e: Event<FormData>| {
+                        let enabled = e.value().parse().unwrap_or(false);
+                        update_voice(voice, |s| s.enabled = enabled);
+                    },
+                }
+                "Dictate messages and hear replies read aloud"
+            }
+            if settings.enabled {
+                div { class: "flex items-center gap-2",
+                    label { "Language:" }
+                    input {
+                        class: "w-24 border border-gray-700 rounded p-1 bg-transparent",
+                        placeholder: "en-US",
+                        value: "{settings.language}",
+                        onchange: move |e: Event<FormData>| {
+                            let language = e.value().trim().to_string();
+                            update_voice(voice, |s| s.language = language);
+                        },
+                    }
+                    select {
+                        class: "flex-1 border border-gray-700 rounded p-1 bg-transparent",
+                        value: settings.voice.clone().unwrap_or_default(),
+                        onchange: move |e: Event<FormData>| {
+                            let name = Some(e.value()).filter(|name| !name.is_empty());
+                            update_voice(voice, |s| s.voice = name);
+                        },
+                        option { value: "", "Default voice" }
+                        for (name, lang) in voices().into_iter().filter(|(_, lang)| lang.to_lowercase().starts_with(&prefix)) {
+                            option { key: "{name}", value: "{name}", "{name} ({lang})" }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[component]
 pub fn Settings() -> Element {
-    let mut api_key = use_signal(|| load_from_storage("api_key").unwrap_or_default());
+    let session = use_session();
+    let mut new_key = use_signal(String::new);
+    let mut key_error = use_signal(|| None::<String>);
     let mut provider =
         use_signal(|| load_from_storage("provider").unwrap_or_else(|| "openai".into()));
     let mut theme = use_context::<Signal<Theme>>();
 
+    // Older versions kept the key in plain text in localStorage.
+    use_hook(|| remove_from_storage("api_key"));
+
+    let user = use_resource(move || async move {
+        match session() {
+            Some(token) => api::current_user(token).await.ok().flatten(),
+            None => None,
+        }
+    });
+    let mut saved_keys = use_resource(move || async move {
+        match session() {
+            Some(token) => api::keys::list_provider_keys(token)
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    });
+
+    let on_save_key = move |_| async move {
+        let Some(token) = session() else { return };
+        let Ok(selected) = provider().parse::<Provider>() else {
+            return;
+        };
+        match api::keys::set_provider_key(token, selected, new_key()).await {
+            Ok(_) => {
+                new_key.set(String::new());
+                key_error.set(None);
+                saved_keys.restart();
+            }
+            Err(e) => key_error.set(Some(e.to_string())),
+        }
+    };
+
     rsx! {
         div { class: "p-4 space-y-4 max-w-md mx-auto",
             h1 { class: "text-xl font-bold mb-2", "Settings" }
@@ -55,29 +168,48 @@ pub fn Settings() -> Element {
                     option { value: "dark",   "Dark"   }
                 }
             }
-            div {
-                label { class: "mr-2", "Provider:" }
-                select {
-                    value: "{provider}",
-                    onchange: move |e| {
-                        save_to_storage("provider", &e.value());
-                        provider.set(e.value());
-                    },
-                    option { value: "openai", "OpenAI" }
-                    option { value: "anthropic", "Anthropic" }
+            if user().flatten().is_some() {
+                div { class: "space-y-2",
+                    h2 { class: "font-semibold", "API keys" }
+                    {render_saved_keys(saved_keys().unwrap_or_default(), session, saved_keys)}
+                    div { class: "flex items-center gap-2",
+                        select {
+                            value: "{provider}",
+                            onchange: move |e| {
+                                save_to_storage("provider", &e.value());
+                                provider.set(e.value());
+                            },
+                            option { value: "openai", "OpenAI" }
+                            option { value: "anthropic", "Anthropic" }
+                            option { value: "openrouter", "OpenRouter" }
+                        }
+                        input {
+                            class: "flex-1 border border-gray-700 rounded p-1 bg-transparent",
+                            r#type: "password",
+                            autocomplete: "off",
+                            placeholder: "Paste a new key",
+                            value: "{new_key}",
+                            oninput: move |e| new_key.set(e.value()),
+                        }
+                        button {
+                            class: "px-2 py-1 bg-blue-500 text-white rounded",
+                            onclick: on_save_key,
+                            "Save"
+                        }
+                    }
+                    if let Some(error) = key_error() {
+                        p { class: "text-red-500 text-sm", "{error}" }
+                    }
                 }
-            }
-            div {
-                label { class: "mr-2", "API Key:" }
-                input {
-                    r#type: "text",
-                    value: "{api_key}",
-                    oninput: move |e| {
-                        save_to_storage("api_key", &e.value());
-                        api_key.set(e.value());
-                    },
+            } else {
+                p {
+                    Link { to: Route::Login {}, class: "text-blue-500 underline", "Log in" }
+                    " to store API keys."
                 }
             }
+            VoiceOptions {}
+            ImportHistory {}
+            Link { to: Route::Usage {}, class: "block text-blue-500 underline", "Usage and spend" }
             Link { to: Route::Chat {}, class: "text-blue-500 underline", "Back" }
         }
     }
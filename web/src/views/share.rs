@@ -0,0 +1,187 @@
+use super::chat::render_message_list;
+use crate::session::use_session;
+use crate::Route;
+use api::{ConversationId, ShareLink};
+use dioxus::prelude::*;
+
+/// Expiry choices offered when creating a link, in days.
+const EXPIRY_OPTIONS: [(&str, Option<u32>); 4] = [
+    ("Never expires", None),
+    ("1 day", Some(1)),
+    ("7 days", Some(7)),
+    ("30 days", Some(30)),
+];
+
+/// Absolute URL of a share link when running in the browser, otherwise its path.
+fn share_url(token: &str) -> String {
+    let path = Route::SharedChat {
+        token: token.to_string(),
+    }
+    .to_string();
+    #[cfg(feature = "web")]
+    if let Some(origin) = web_sys::window().and_then(|w| w.location().origin().ok()) {
+        return format!("{origin}{path}");
+    }
+    path
+}
+
+fn render_share_link(link: &ShareLink, on_revoke: EventHandler<String>) -> Element {
+    let token = link.token.clone();
+    let expiry = match &link.expires_at {
+        Some(at) => format!("expires {at}"),
+        None => "never expires".to_string(),
+    };
+    rsx! {
+        li { class: "flex items-center gap-2 text-sm",
+            input {
+                class: "flex-1 p-1 border rounded bg-transparent",
+                readonly: true,
+                value: share_url(&link.token),
+            }
+            span { class: "text-gray-400", "{expiry}" }
+            button {
+                class: "underline text-red-500",
+                onclick: move |_| on_revoke.call(token.clone()),
+                "Revoke"
+            }
+        }
+    }
+}
+
+/// Create, list and revoke share links for a conversation.
+#[component]
+pub fn SharePanel(conv_id: ConversationId) -> Element {
+    let session = use_session();
+    let mut expires_in_days = use_signal(|| None::<u32>);
+    let mut error = use_signal(|| None::<String>);
+    let mut links = use_resource(move || async move {
+        match session() {
+            Some(token) => api::share::list_shares(token, conv_id)
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    });
+
+    let create = move |_| async move {
+        let Some(token) = session() else { return };
+        match api::share::share_conversation(token, conv_id, expires_in_days()).await {
+            Ok(_) => {
+                error.set(None);
+                links.restart();
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    let revoke = move |share: String| async move {
+        let Some(token) = session() else { return };
+        if let Err(e) = api::share::revoke_share(token, share).await {
+            error.set(Some(e.to_string()));
+        }
+        links.restart();
+    };
+
+    rsx! {
+        div { class: "mt-2 p-2 border border-gray-700 rounded space-y-2",
+            p { class: "text-sm text-gray-400",
+                "Links show a read-only copy of the conversation as it is now."
+            }
+            div { class: "flex items-center gap-2",
+                select {
+                    class: "p-1 border rounded bg-transparent text-sm",
+                    onchange: move |e| expires_in_days.set(e.value().parse().ok()),
+                    for (label, days) in EXPIRY_OPTIONS {
+                        option {
+                            value: days.map(|d| d.to_string()).unwrap_or_default(),
+                            selected: expires_in_days() == days,
+                            "{label}"
+                        }
+                    }
+                }
+                button {
+                    class: "px-2 py-1 bg-blue-500 text-white rounded text-sm",
+                    onclick: create,
+                    "Create link"
+                }
+            }
+            if let Some(error) = error() {
+                p { class: "text-sm text-red-500", "{error}" }
+            }
+            ul { class: "space-y-1",
+                for link in links().unwrap_or_default() {
+                    {render_share_link(&link, EventHandler::new(move |token| {
+                        spawn(revoke(token));
+                    }))}
+                }
+            }
+        }
+    }
+}
+
+/// Read-only view of a shared snapshot, without the sidebar or input box.
+#[component]
+pub fn SharedChat(token: String) -> Element {
+    let shared = use_resource(use_reactive!(|token| async move {
+        api::share::get_shared_conversation(token).await
+    }));
+    let session = use_session();
+    let navigator = use_navigator();
+    let mut fork_error = use_signal(|| None::<String>);
+    let user = use_resource(move || async move {
+        match session() {
+            Some(token) => api::current_user(token).await.ok().flatten(),
+            None => None,
+        }
+    });
+
+    let fork = move |_| {
+        let token = token.clone();
+        async move {
+            let Some(session_token) = session() else {
+                return;
+            };
+            match api::fork_shared_conversation(session_token, token).await {
+                Ok(id) => {
+                    navigator.push(Route::ChatShare { id });
+                }
+                Err(e) => fork_error.set(Some(e.to_string())),
+            }
+        }
+    };
+
+    rsx! {
+        div { class: "flex flex-col h-screen max-w-3xl mx-auto p-4",
+            match &*shared.read_unchecked() {
+                Some(Ok(conv)) => rsx! {
+                    if let Some(title) = &conv.title {
+                        h1 { class: "text-xl font-bold", "{title}" }
+                    }
+                    p { class: "text-sm text-gray-400 mb-2", "Shared conversation · snapshot from {conv.created_at}" }
+                    {render_message_list(&conv.messages, None, None)}
+                },
+                Some(Err(_)) => rsx! {
+                    p { class: "m-auto text-gray-400", "This link has expired or been revoked." }
+                },
+                None => rsx! {
+                    p { class: "m-auto text-gray-400", "Loading…" }
+                },
+            }
+            div { class: "flex items-center gap-4 mt-2 text-sm",
+                if user().flatten().is_some() {
+                    button {
+                        class: "px-2 py-1 bg-blue-500 text-white rounded",
+                        onclick: fork,
+                        "Continue this chat"
+                    }
+                } else {
+                    Link { to: Route::Login {}, class: "underline", "Log in to continue this chat" }
+                }
+                Link { to: Route::Chat {}, class: "underline", "Start your own chat" }
+            }
+            if let Some(error) = fork_error() {
+                p { class: "text-sm text-red-500", "{error}" }
+            }
+        }
+    }
+}
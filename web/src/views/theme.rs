@@ -7,7 +7,7 @@ pub enum Theme {
     System,
 }
 
-#[allow(non_snake_case)]
+#[allow(non_snake_case, dead_code)]
 pub fn DarkModeToggle() -> Element {
     let mut theme = use_context::<Signal<Theme>>();
     let checked = matches!(theme(), Theme::Dark);
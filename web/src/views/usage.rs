@@ -0,0 +1,64 @@
+use crate::session::use_session;
+use crate::Route;
+use api::usage::UsageReport;
+use api::UsageTotals;
+use dioxus::prelude::*;
+
+fn render_usage_table(heading: &str, rows: &[(String, UsageTotals)]) -> Element {
+    rsx! {
+        h2 { class: "text-lg font-semibold mt-4", "{heading}" }
+        if rows.is_empty() {
+            p { class: "text-sm text-gray-400", "No usage yet." }
+        } else {
+            table { class: "w-full text-sm",
+                thead {
+                    tr {
+                        th { class: "text-left", "" }
+                        th { class: "text-right", "Requests" }
+                        th { class: "text-right", "Prompt" }
+                        th { class: "text-right", "Completion" }
+                        th { class: "text-right", "Cost" }
+                    }
+                }
+                tbody {
+                    for (label, totals) in rows.iter() {
+                        tr { key: "{label}",
+                            td { "{label}" }
+                            td { class: "text-right", "{totals.requests}" }
+                            td { class: "text-right", "{totals.prompt_tokens}" }
+                            td { class: "text-right", "{totals.completion_tokens}" }
+                            td { class: "text-right", "${totals.cost:.4}" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Spend by model and by day for the current session.
+#[component]
+pub fn Usage() -> Element {
+    let session = use_session();
+    let report = use_resource(move || async move {
+        match session() {
+            Some(token) => api::get_usage(token).await.unwrap_or_default(),
+            None => UsageReport::default(),
+        }
+    });
+
+    let report = report().unwrap_or_default();
+
+    rsx! {
+        div { class: "p-4 space-y-2 max-w-2xl mx-auto",
+            h1 { class: "text-xl font-bold mb-2", "Usage" }
+            p {
+                "{report.total.total_tokens()} tokens over {report.total.requests} requests, "
+                "${report.total.cost:.4} total"
+            }
+            {render_usage_table("By model", &report.by_model)}
+            {render_usage_table("By day", &report.by_day)}
+            Link { to: Route::Settings {}, class: "text-blue-500 underline", "Back" }
+        }
+    }
+}